@@ -0,0 +1,86 @@
+use askama::Template;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize};
+
+use crate::gateway::Transaction;
+use crate::money::Money;
+use crate::util;
+
+//----------------------------------------------------------------------------------------------------
+//----------------------------------------------------------------------------------------------------
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct Organization {
+    pub name : String,
+    pub ein : String,
+    pub address : String,
+}
+
+//----------------------------------------------------------------------------------------------------
+//----------------------------------------------------------------------------------------------------
+#[derive(Template, Debug, Clone)]
+#[template(path = "acknowledgement.html")]
+pub struct Acknowledgement {
+    pub organization : Organization,
+    pub donor_name : String,
+    pub email : String,
+    pub address_lines : Vec<String>,
+    pub amount : Money,
+    pub date : DateTime<Utc>,
+    pub fundraiser : String,
+    pub transaction_id : String,
+}
+
+//------------------------------------------------------------------------------------------------------
+//------------------------------------------------------------------------------------------------------
+impl Default for Organization {
+    fn default() -> Organization {
+        Organization {
+            name: "Santa Barbara Hackerspace".to_string(),
+            ein: String::new(),
+            address: "5782 Thornwood Dr, Goleta, CA 93117".to_string(),
+        }
+    }
+}
+
+//------------------------------------------------------------------------------------------------------
+//------------------------------------------------------------------------------------------------------
+impl Acknowledgement {
+
+    //--------------------------------------------------------------------------------------------------
+    //--------------------------------------------------------------------------------------------------
+    pub fn new(
+        organization : &Organization,
+        payment : &util::Payment,
+        fundraiser : &str,
+        amount : Money,
+        transaction : &Transaction) -> Acknowledgement {
+
+        let mut address_lines = Vec::new();
+        if let Some(company_name) = payment.company_name.as_ref().filter(|company_name| !company_name.is_empty()) {
+            address_lines.push(company_name.clone());
+        }
+        address_lines.push(payment.address.clone());
+        if !payment.address2.is_empty() {
+            address_lines.push(payment.address2.clone());
+        }
+        address_lines.push(format!("{}, {}", payment.city, payment.state));
+
+        Acknowledgement {
+            organization: organization.clone(),
+            donor_name: format!("{} {}", payment.first_name, payment.last_name),
+            email: payment.email.clone(),
+            address_lines,
+            amount,
+            date: Utc::now(),
+            fundraiser: fundraiser.to_string(),
+            transaction_id: transaction.id.clone(),
+        }
+    }
+
+    //--------------------------------------------------------------------------------------------------
+    //--------------------------------------------------------------------------------------------------
+    pub fn file_name(&self) -> String {
+        format!("donation-acknowledgement-{}.html", self.transaction_id)
+    }
+}
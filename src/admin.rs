@@ -0,0 +1,789 @@
+use actix_web::{web, HttpRequest, HttpResponse};
+use actix_web::http::StatusCode;
+use askama::Template;
+use chrono::NaiveDate;
+use serde::{Serialize, Deserialize};
+use log::{info, warn};
+use std::collections::BTreeMap;
+use url::Url;
+
+use crate::checkout::Checkouts;
+use crate::config::Config;
+use crate::fundraise::Fundraiser;
+use crate::ledger::Ledger;
+use crate::mail::Mailer;
+use crate::membership::Tier;
+use crate::money::{Fraction, Money};
+use crate::pending::{self, PendingCharge, PendingCharges, Resolution};
+use crate::promo::{self, Discount, PromoCode};
+use crate::quote::InvoiceRepository;
+use crate::state::{self, State};
+use crate::storage;
+use crate::store::Item;
+use crate::util;
+
+#[derive(Deserialize,Debug, Serialize)]
+pub struct ItemForm {
+    pub key : String,
+    pub name : String,
+    pub price : Money,
+    pub discount : Money,
+    pub number_of_items : String,
+    pub image : String,
+    pub dates : String,
+    #[serde(default)]
+    pub taxable : Option<String>,
+    #[serde(default)]
+    pub member_price : String,
+    #[serde(default)]
+    pub loaded_seats : String,
+}
+
+#[derive(Deserialize,Debug, Serialize)]
+pub struct FundraiserForm {
+    pub key : String,
+    pub name : String,
+    pub goal : Money,
+    pub image : String,
+    pub description : String,
+}
+
+#[derive(Deserialize,Debug, Serialize)]
+pub struct PromoForm {
+    pub code : String,
+    pub kind : String,
+    pub value : String,
+    pub item : String,
+    pub expires : String,
+    pub max_uses : String,
+    pub max_uses_per_email : String,
+}
+
+#[derive(Deserialize,Debug, Serialize)]
+pub struct PendingForm {
+    pub action : String,
+    #[serde(default)]
+    pub transaction_id : String,
+}
+
+struct PendingRow {
+    id : String,
+    charge : PendingCharge,
+    payment_type : &'static str,
+    timestamp : String,
+}
+
+struct ItemRow {
+    key : String,
+    item : Item,
+    member_price : String,
+    seats : String,
+    archive : &'static str,
+}
+
+struct FundraiserRow {
+    key : String,
+    fundraiser : Fundraiser,
+    archive : &'static str,
+}
+
+struct PromoRow {
+    code : String,
+    promo : PromoCode,
+    applies_to : String,
+    expires : String,
+    used : String,
+    per_email : String,
+    expired : bool,
+}
+
+#[derive(Template)]
+#[template(path = "admin_message.html")]
+struct MessagePage<'a> {
+    message : &'a str,
+}
+
+#[derive(Template)]
+#[template(path = "admin_index.html")]
+struct IndexPage<'a> {
+    pending : &'a [PendingRow],
+    items : &'a [ItemRow],
+    fundraisers : &'a [FundraiserRow],
+    promo_codes : &'a [PromoRow],
+}
+
+#[derive(Template)]
+#[template(path = "admin_item.html")]
+struct ItemFormPage<'a> {
+    key : &'a str,
+    item : &'a Item,
+    member_price : String,
+    seats : String,
+}
+
+#[derive(Template)]
+#[template(path = "admin_fundraiser.html")]
+struct FundraiserFormPage<'a> {
+    key : &'a str,
+    fundraiser : &'a Fundraiser,
+}
+
+#[derive(Template)]
+#[template(path = "admin_promo.html")]
+struct PromoFormPage<'a> {
+    code : &'a str,
+    promo : &'a PromoCode,
+    kind : &'a str,
+    value : String,
+    item : &'a str,
+    expires : String,
+    max_uses : String,
+    max_uses_per_email : String,
+}
+
+//----------------------------------------------------------------------------------------------------
+//----------------------------------------------------------------------------------------------------
+fn authorized(req : &HttpRequest) -> bool {
+    let login = match req.app_data::<web::Data<Config>>() {
+        Some(config) => &config.credentials,
+        None => return false,
+    };
+
+    //the admin area is disabled entirely unless a password has been configured
+    let (user, password) = match login.admin_password.as_ref() {
+        Some(password) => (&login.admin_user, password),
+        None => return false,
+    };
+
+    //basic auth is sent automatically by the browser so refuse cross site posts
+    if let Some(origin) = req.headers().get("Origin").and_then(|origin| origin.to_str().ok()) {
+        let host = req.connection_info().host().to_string();
+        if !same_host(origin, &host) {
+            warn!("rejecting admin request from origin {}\n", origin);
+            return false;
+        }
+    }
+
+    let credentials = req.headers().get("Authorization")
+        .and_then(|header| header.to_str().ok())
+        .and_then(|header| header.strip_prefix("Basic "))
+        .and_then(|encoded| base64::decode(encoded.trim()).ok());
+
+    match credentials {
+        Some(credentials) => util::constant_time_eq(&credentials, format!("{}:{}", user, password).as_bytes()),
+        None => false,
+    }
+}
+
+//----------------------------------------------------------------------------------------------------
+//----------------------------------------------------------------------------------------------------
+//the origin has to name this host and port exactly, evilstore.example.org ends with store.example.org
+fn same_host(origin : &str, host : &str) -> bool {
+    let origin = match Url::parse(origin) {
+        Ok(origin) => origin,
+        Err(_) => return false,
+    };
+
+    match (origin.host_str(), origin.port()) {
+        (Some(origin_host), Some(port)) => format!("{}:{}", origin_host, port).eq_ignore_ascii_case(host),
+        (Some(origin_host), None) => origin_host.eq_ignore_ascii_case(host),
+        (None, _) => false,
+    }
+}
+
+//----------------------------------------------------------------------------------------------------
+//----------------------------------------------------------------------------------------------------
+fn unauthorized() -> HttpResponse {
+    HttpResponse::Unauthorized()
+        .header("WWW-Authenticate", "Basic realm=\"sbhx admin\"")
+        .finish()
+}
+
+//----------------------------------------------------------------------------------------------------
+//----------------------------------------------------------------------------------------------------
+fn page<T : Template>(template : &T) -> HttpResponse {
+    util::render(StatusCode::OK, template)
+}
+
+//----------------------------------------------------------------------------------------------------
+//----------------------------------------------------------------------------------------------------
+fn message(message : &str) -> HttpResponse {
+    page(&MessagePage{ message })
+}
+
+//----------------------------------------------------------------------------------------------------
+//----------------------------------------------------------------------------------------------------
+//the change is already live, it is written again with the next one
+fn not_saved(what : &str, error : storage::Error) -> HttpResponse {
+    HttpResponse::InternalServerError().body(format!("the change is live but unable to save {} {:?}", what, error))
+}
+
+//----------------------------------------------------------------------------------------------------
+//----------------------------------------------------------------------------------------------------
+fn redirect_to_index() -> HttpResponse {
+    HttpResponse::SeeOther().header("Location", "/admin/").finish()
+}
+
+//----------------------------------------------------------------------------------------------------
+//----------------------------------------------------------------------------------------------------
+fn valid_key(key : &str) -> bool {
+    !key.is_empty() && key.chars().all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-' || c == '_')
+}
+
+//----------------------------------------------------------------------------------------------------
+//----------------------------------------------------------------------------------------------------
+fn archive_action(archived : bool) -> &'static str {
+    if archived { "unarchive" } else { "archive" }
+}
+
+//----------------------------------------------------------------------------------------------------
+//----------------------------------------------------------------------------------------------------
+fn pending_row(id : String, charge : PendingCharge) -> PendingRow {
+    PendingRow {
+        id,
+        payment_type: charge.hold.payment_type().as_str(),
+        timestamp: charge.timestamp.format("%Y-%m-%d %H:%M UTC").to_string(),
+        charge,
+    }
+}
+
+//----------------------------------------------------------------------------------------------------
+//----------------------------------------------------------------------------------------------------
+fn item_row(key : String, item : Item) -> ItemRow {
+    ItemRow {
+        key,
+        member_price: item.tier_prices.get(&Tier::Member).map_or(String::new(), |member_price| member_price.to_string()),
+        seats: item.number_of_items.map_or("unlimited".to_string(), |number_of_items| number_of_items.to_string()),
+        archive: archive_action(item.archived),
+        item,
+    }
+}
+
+//----------------------------------------------------------------------------------------------------
+//----------------------------------------------------------------------------------------------------
+fn fundraiser_row(key : String, fundraiser : Fundraiser) -> FundraiserRow {
+    FundraiserRow { key, archive: archive_action(fundraiser.archived), fundraiser }
+}
+
+//----------------------------------------------------------------------------------------------------
+//----------------------------------------------------------------------------------------------------
+fn promo_row(code : String, promo : PromoCode) -> PromoRow {
+    PromoRow {
+        code,
+        applies_to: promo.item.clone().unwrap_or_else(|| "all classes".to_string()),
+        expires: promo.expires.map_or("never".to_string(), |expires| expires.to_string()),
+        used: format!("{}{}", promo.uses(), promo.max_uses.map_or(String::new(), |max_uses| format!(" of {}", max_uses))),
+        per_email: promo.max_uses_per_email.map_or("unlimited".to_string(), |max_uses_per_email| max_uses_per_email.to_string()),
+        expired: promo.is_expired(),
+        promo,
+    }
+}
+
+//----------------------------------------------------------------------------------------------------
+//----------------------------------------------------------------------------------------------------
+fn item_form<'a>(key : &'a str, item : &'a Item) -> ItemFormPage<'a> {
+    ItemFormPage {
+        key,
+        item,
+        member_price: item.tier_prices.get(&Tier::Member).map_or(String::new(), |member_price| member_price.to_string()),
+        seats: item.number_of_items.map_or(String::new(), |number_of_items| number_of_items.to_string()),
+    }
+}
+
+//----------------------------------------------------------------------------------------------------
+//----------------------------------------------------------------------------------------------------
+fn promo_form<'a>(code : &'a str, promo : &'a PromoCode) -> PromoFormPage<'a> {
+    let (kind, value) = match &promo.discount {
+        Discount::Percent(percent) => ("percent", percent.percent()),
+        Discount::Fixed(amount) => ("fixed", amount.to_string()),
+    };
+
+    PromoFormPage {
+        code,
+        promo,
+        kind,
+        value,
+        item: promo.item.as_deref().unwrap_or(""),
+        expires: promo.expires.map_or(String::new(), |expires| expires.to_string()),
+        max_uses: promo.max_uses.map_or(String::new(), |max_uses| max_uses.to_string()),
+        max_uses_per_email: promo.max_uses_per_email.map_or(String::new(), |max_uses_per_email| max_uses_per_email.to_string()),
+    }
+}
+
+//----------------------------------------------------------------------------------------------------
+//----------------------------------------------------------------------------------------------------
+fn optional_count(value : &str) -> Result<Option<u32>, ()> {
+    match value.trim() {
+        "" => Ok(None),
+        value => value.parse::<u32>().map(Some).map_err(|_| ()),
+    }
+}
+
+//----------------------------------------------------------------------------------------------------
+//----------------------------------------------------------------------------------------------------
+fn optional_seats(value : &str) -> Result<Option<i32>, ()> {
+    match value.trim() {
+        "" => Ok(None),
+        value => value.parse::<i32>().map(Some).map_err(|_| ()),
+    }
+}
+
+//----------------------------------------------------------------------------------------------------
+//----------------------------------------------------------------------------------------------------
+pub async fn index(req : HttpRequest, state : web::Data<State>, pending : web::Data<PendingCharges>) -> HttpResponse {
+    if !authorized(&req) {
+        return unauthorized();
+    }
+
+    let pending : Vec<PendingRow> = pending.charges().into_iter()
+        .map(|(id, charge)| pending_row(id, charge))
+        .collect();
+
+    let items : Vec<ItemRow> = state::sorted(&state.stocked_items()).into_iter()
+        .map(|(key, item)| item_row(key, item))
+        .collect();
+
+    let fundraisers : Vec<FundraiserRow> = state::sorted(&state.fundraisers()).into_iter()
+        .map(|(key, fundraiser)| fundraiser_row(key, fundraiser))
+        .collect();
+
+    let promo_codes : Vec<PromoRow> = state.promo_codes().into_iter()
+        .map(|(code, promo)| promo_row(code, promo))
+        .collect();
+
+    page(&IndexPage{ pending: &pending, items: &items, fundraisers: &fundraisers, promo_codes: &promo_codes })
+}
+
+//----------------------------------------------------------------------------------------------------
+//----------------------------------------------------------------------------------------------------
+pub async fn edit_item(req : HttpRequest, state : web::Data<State>, key : web::Path<String>) -> HttpResponse {
+    if !authorized(&req) {
+        return unauthorized();
+    }
+
+    if key.as_str() == "new" {
+        return page(&item_form("", &Item {
+            number_of_items: None,
+            price: Money::zero(),
+            discount: Money::zero(),
+            name: String::new(),
+            formname: String::new(),
+            image: "../assets/images/sbhx.png".to_string(),
+            dates: String::new(),
+            archived: false,
+            position: state.stocked_items().len() as i32,
+            taxable: false,
+            tier_prices: BTreeMap::new(),
+        }));
+    }
+
+    match state.stocked_items().get(key.as_str()) {
+        Some(item) => page(&item_form(&key, item)),
+        None => HttpResponse::NotFound().finish(),
+    }
+}
+
+//----------------------------------------------------------------------------------------------------
+//----------------------------------------------------------------------------------------------------
+pub async fn save_item(req : HttpRequest, state : web::Data<State>, form : web::Form<ItemForm>) -> HttpResponse {
+    if !authorized(&req) {
+        return unauthorized();
+    }
+
+    if !valid_key(&form.key) || form.key == "new" {
+        return message("Keys may only contain lower case letters, numbers, - and _");
+    }
+
+    let (number_of_items, loaded_seats) = match (optional_seats(&form.number_of_items), optional_seats(&form.loaded_seats)) {
+        (Ok(number_of_items), Ok(loaded_seats)) => (number_of_items, loaded_seats),
+        _ => return message("Seats left must be a whole number"),
+    };
+
+    let member_price = match form.member_price.trim() {
+        "" => None,
+        member_price => match member_price.parse::<Money>() {
+            Ok(member_price) if member_price.cents() < 0 => return message("Member price must not be negative"),
+            Ok(member_price) => Some(member_price),
+            Err(_) => return message("Member price must be an amount like 40.00"),
+        },
+    };
+
+    let existing = state.stocked_items().remove(&form.key);
+    let form = form.into_inner();
+
+    //other tiers are kept as they are, the form only edits the member price
+    let mut tier_prices = existing.as_ref().map_or(BTreeMap::new(), |item| item.tier_prices.clone());
+    tier_prices.remove(&Tier::Member);
+    if let Some(member_price) = member_price {
+        tier_prices.insert(Tier::Member, member_price);
+    }
+
+    info!("admin saving item {}\n", form.key);
+    let saved = state.save_item(&form.key, Item {
+        number_of_items,
+        price: form.price,
+        discount: form.discount,
+        name: form.name,
+        formname: form.key.clone(),
+        image: form.image,
+        dates: form.dates,
+        archived: existing.as_ref().map_or(false, |item| item.archived),
+        position: existing.as_ref().map_or(state.stocked_items().len() as i32, |item| item.position),
+        taxable: form.taxable.is_some(),
+        tier_prices,
+    }, loaded_seats);
+
+    let saved = match saved {
+        Ok(saved) => saved,
+        Err(error) => return not_saved("inventory", error),
+    };
+    if !saved {
+        warn!("admin save of item {} rejected, its seats changed since the form was loaded\n", form.key);
+        return message("Seats were sold while you were editing this class, nothing was saved. Reload it and try again");
+    }
+    redirect_to_index()
+}
+
+//----------------------------------------------------------------------------------------------------
+//----------------------------------------------------------------------------------------------------
+pub async fn item_action(req : HttpRequest, state : web::Data<State>, path : web::Path<(String, String)>) -> HttpResponse {
+    if !authorized(&req) {
+        return unauthorized();
+    }
+
+    let (key, action) = path.into_inner();
+    info!("admin {} item {}\n", action, key);
+
+    let found = match action.as_str() {
+        "archive" => state.archive_item(&key, true),
+        "unarchive" => state.archive_item(&key, false),
+        "up" => state.move_item(&key, -1),
+        "down" => state.move_item(&key, 1),
+        _ => Ok(false),
+    };
+
+    let found = match found {
+        Ok(found) => found,
+        Err(error) => return not_saved("inventory", error),
+    };
+    if !found {
+        return HttpResponse::NotFound().finish();
+    }
+    redirect_to_index()
+}
+
+//----------------------------------------------------------------------------------------------------
+//----------------------------------------------------------------------------------------------------
+pub async fn edit_fundraiser(req : HttpRequest, state : web::Data<State>, key : web::Path<String>) -> HttpResponse {
+    if !authorized(&req) {
+        return unauthorized();
+    }
+
+    if key.as_str() == "new" {
+        return page(&FundraiserFormPage{ key: "", fundraiser: &Fundraiser {
+            name: String::new(),
+            goal: Money::zero(),
+            amount_raised: Money::zero(),
+            formname: String::new(),
+            image: "../assets/images/sbhx.png".to_string(),
+            description: String::new(),
+            archived: false,
+            position: state.fundraisers().len() as i32,
+        }});
+    }
+
+    match state.fundraisers().get(key.as_str()) {
+        Some(fundraiser) => page(&FundraiserFormPage{ key: &key, fundraiser }),
+        None => HttpResponse::NotFound().finish(),
+    }
+}
+
+//----------------------------------------------------------------------------------------------------
+//----------------------------------------------------------------------------------------------------
+pub async fn save_fundraiser(req : HttpRequest, state : web::Data<State>, form : web::Form<FundraiserForm>) -> HttpResponse {
+    if !authorized(&req) {
+        return unauthorized();
+    }
+
+    if !valid_key(&form.key) || form.key == "new" {
+        return message("Keys may only contain lower case letters, numbers, - and _");
+    }
+
+    let existing = state.fundraisers().remove(&form.key);
+    let form = form.into_inner();
+
+    info!("admin saving fundraiser {}\n", form.key);
+    let saved = state.save_fundraiser(&form.key, Fundraiser {
+        name: form.name,
+        goal: form.goal,
+        amount_raised: Money::zero(),
+        formname: form.key.clone(),
+        image: form.image,
+        description: form.description,
+        archived: existing.as_ref().map_or(false, |fundraiser| fundraiser.archived),
+        position: existing.as_ref().map_or(state.fundraisers().len() as i32, |fundraiser| fundraiser.position),
+    });
+
+    if let Err(error) = saved {
+        return not_saved("fundraisers", error);
+    }
+    redirect_to_index()
+}
+
+//----------------------------------------------------------------------------------------------------
+//----------------------------------------------------------------------------------------------------
+pub async fn fundraiser_action(req : HttpRequest, state : web::Data<State>, path : web::Path<(String, String)>) -> HttpResponse {
+    if !authorized(&req) {
+        return unauthorized();
+    }
+
+    let (key, action) = path.into_inner();
+    info!("admin {} fundraiser {}\n", action, key);
+
+    let found = match action.as_str() {
+        "archive" => state.archive_fundraiser(&key, true),
+        "unarchive" => state.archive_fundraiser(&key, false),
+        "up" => state.move_fundraiser(&key, -1),
+        "down" => state.move_fundraiser(&key, 1),
+        _ => Ok(false),
+    };
+
+    let found = match found {
+        Ok(found) => found,
+        Err(error) => return not_saved("fundraisers", error),
+    };
+    if !found {
+        return HttpResponse::NotFound().finish();
+    }
+    redirect_to_index()
+}
+
+//----------------------------------------------------------------------------------------------------
+//----------------------------------------------------------------------------------------------------
+pub async fn edit_promo_code(req : HttpRequest, state : web::Data<State>, code : web::Path<String>) -> HttpResponse {
+    if !authorized(&req) {
+        return unauthorized();
+    }
+
+    if code.as_str() == "new" {
+        return page(&promo_form("", &PromoCode {
+            discount: Discount::Percent(Fraction::from_millionths(100_000)),
+            item: None,
+            expires: None,
+            max_uses: None,
+            max_uses_per_email: Some(1),
+            redemptions: Vec::new(),
+        }));
+    }
+
+    match state.promo_codes().get(code.as_str()) {
+        Some(promo) => page(&promo_form(&code, promo)),
+        None => HttpResponse::NotFound().finish(),
+    }
+}
+
+//----------------------------------------------------------------------------------------------------
+//----------------------------------------------------------------------------------------------------
+pub async fn save_promo_code(req : HttpRequest, state : web::Data<State>, form : web::Form<PromoForm>) -> HttpResponse {
+    if !authorized(&req) {
+        return unauthorized();
+    }
+
+    let code = promo::normalize_code(&form.code);
+    if code.is_empty() || code == "NEW" || !code.chars().all(|c| c.is_ascii_uppercase() || c.is_ascii_digit() || c == '-' || c == '_') {
+        return message("Promo codes may only contain letters, numbers, - and _");
+    }
+
+    let discount = match form.kind.as_str() {
+        "percent" => Fraction::from_percent(&form.value).ok().map(Discount::Percent),
+        "fixed" => form.value.parse::<Money>().ok().map(Discount::Fixed),
+        _ => None,
+    };
+    let discount = match discount {
+        Some(discount) if discount.is_valid() => discount,
+        _ => return message("The discount must be a percentage between 0 and 100 or a positive amount"),
+    };
+
+    let item = match form.item.trim() {
+        "" => None,
+        item if state.stocked_items().contains_key(item) => Some(item.to_string()),
+        _ => return message("There is no class with that key"),
+    };
+
+    let expires = match form.expires.trim() {
+        "" => None,
+        expires => match expires.parse::<NaiveDate>() {
+            Ok(expires) => Some(expires),
+            Err(_) => return message("The expiry date must look like 2026-12-31"),
+        },
+    };
+
+    let (max_uses, max_uses_per_email) = match (optional_count(&form.max_uses), optional_count(&form.max_uses_per_email)) {
+        (Ok(max_uses), Ok(max_uses_per_email)) => (max_uses, max_uses_per_email),
+        _ => return message("Use limits must be whole numbers"),
+    };
+
+    info!("admin saving promo code {}\n", code);
+    let saved = state.save_promo_code(&code, PromoCode {
+        discount,
+        item,
+        expires,
+        max_uses,
+        max_uses_per_email,
+        redemptions: Vec::new(),
+    });
+
+    if let Err(error) = saved {
+        return not_saved("promo codes", error);
+    }
+    redirect_to_index()
+}
+
+//----------------------------------------------------------------------------------------------------
+//----------------------------------------------------------------------------------------------------
+//the treasurer looks the charge up in braintree and says whether the card was charged
+pub async fn resolve_pending(
+    req : HttpRequest,
+    id : web::Path<String>,
+    form : web::Form<PendingForm>,
+    pending : web::Data<PendingCharges>,
+    state : web::Data<State>,
+    invoices : web::Data<InvoiceRepository>,
+    ledger : web::Data<Ledger>,
+    mailer : web::Data<Mailer>,
+    checkouts : web::Data<Checkouts>) -> HttpResponse {
+    if !authorized(&req) {
+        return unauthorized();
+    }
+
+    let resolution = match (form.action.as_str(), form.transaction_id.trim()) {
+        ("charged", "") => return message("Enter the braintree transaction id of the charge"),
+        ("charged", transaction_id) => Resolution::Charged(transaction_id.to_string()),
+        ("not_charged", _) => Resolution::NotCharged,
+        _ => return HttpResponse::NotFound().finish(),
+    };
+
+    let charge = match pending.take(&id) {
+        Ok(Some(charge)) => charge,
+        Ok(None) => return HttpResponse::NotFound().finish(),
+        Err(error) => return HttpResponse::InternalServerError().body(format!("unable to save pending charges {:?}", error)),
+    };
+
+    info!("admin resolving pending charge {} as {:?}\n", id, resolution);
+    pending::resolve(charge, &resolution, &state, &invoices, &ledger, &mailer);
+    checkouts.forget(&id);
+
+    redirect_to_index()
+}
+
+//----------------------------------------------------------------------------------------------------
+//----------------------------------------------------------------------------------------------------
+pub async fn ledger_csv(req : HttpRequest, ledger : web::Data<Ledger>) -> HttpResponse {
+    if !authorized(&req) {
+        return unauthorized();
+    }
+
+    let mut csv = Vec::new();
+    if let Err(error) = ledger.write_csv(&mut csv) {
+        return HttpResponse::InternalServerError().body(format!("unable to read ledger {:?}", error));
+    }
+
+    HttpResponse::Ok()
+        .content_type("text/csv; charset=utf-8")
+        .header("Content-Disposition", "attachment; filename=\"ledger.csv\"")
+        .body(csv)
+}
+
+//----------------------------------------------------------------------------------------------------
+//----------------------------------------------------------------------------------------------------
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{assert_escaped, fundraiser, item, promo, PAYLOAD};
+
+    //--------------------------------------------------------------------------------------------------
+    //--------------------------------------------------------------------------------------------------
+    #[test]
+    fn index_escapes_every_row() {
+        let items = [item_row(PAYLOAD.to_string(), item())];
+        let fundraisers = [fundraiser_row(PAYLOAD.to_string(), Fundraiser{ archived: true, ..fundraiser() })];
+        let promo_codes = [promo_row(PAYLOAD.to_string(), promo())];
+
+        let html = IndexPage{ pending: &[], items: &items, fundraisers: &[], promo_codes: &[] }.render().unwrap();
+        assert_escaped(&html);
+        assert!(html.contains("<td>4</td>"));
+        assert!(html.contains(">archive</button>"));
+
+        let html = IndexPage{ pending: &[], items: &[], fundraisers: &fundraisers, promo_codes: &[] }.render().unwrap();
+        assert_escaped(&html);
+        assert!(html.contains("$250.00 of $1000.00"));
+        assert!(html.contains(">unarchive</button>"));
+
+        let html = IndexPage{ pending: &[], items: &[], fundraisers: &[], promo_codes: &promo_codes }.render().unwrap();
+        assert_escaped(&html);
+        assert!(html.contains("10% off"));
+        assert!(html.contains("0 of 5"));
+
+        let payment = util::Payment {
+            first_name: PAYLOAD.to_string(),
+            last_name: PAYLOAD.to_string(),
+            email: PAYLOAD.to_string(),
+            ..util::Payment::default()
+        };
+        let sale = util::Sale { payment_type: util::PaymentType::Invoice, description: PAYLOAD, amount: Money::from_cents(4000), tax: None, promo_code: None };
+        let charge = PendingCharge::new(&payment, &sale, pending::Hold::Invoice{ invoice_id: PAYLOAD.to_string() });
+        let pending = [pending_row(PAYLOAD.to_string(), charge)];
+        let html = IndexPage{ pending: &pending, items: &[], fundraisers: &[], promo_codes: &[] }.render().unwrap();
+        assert_escaped(&html);
+        assert!(html.contains("$40.00"));
+        assert!(html.contains("Not charged"));
+    }
+
+    //--------------------------------------------------------------------------------------------------
+    //--------------------------------------------------------------------------------------------------
+    #[test]
+    fn forms_escape_every_field() {
+        let item = item();
+        let html = item_form(PAYLOAD, &item).render().unwrap();
+        assert_escaped(&html);
+        assert!(html.contains("readonly"));
+
+        let fundraiser = fundraiser();
+        assert_escaped(&FundraiserFormPage{ key: PAYLOAD, fundraiser: &fundraiser }.render().unwrap());
+
+        let promo = promo();
+        assert_escaped(&promo_form(PAYLOAD, &promo).render().unwrap());
+    }
+
+    //--------------------------------------------------------------------------------------------------
+    //--------------------------------------------------------------------------------------------------
+    #[test]
+    fn origin_must_be_this_host() {
+        assert!(same_host("https://store.example.org", "store.example.org"));
+        assert!(same_host("http://localhost:7777", "localhost:7777"));
+        assert!(same_host("https://Store.Example.org", "store.example.org"));
+
+        assert!(!same_host("https://evilstore.example.org", "store.example.org"));
+        assert!(!same_host("https://store.example.org.evil.com", "store.example.org"));
+        assert!(!same_host("https://evil.com/https://store.example.org", "store.example.org"));
+        assert!(!same_host("http://localhost:8080", "localhost:7777"));
+        assert!(!same_host("null", "store.example.org"));
+    }
+
+    //--------------------------------------------------------------------------------------------------
+    //--------------------------------------------------------------------------------------------------
+    #[test]
+    fn messages_are_escaped() {
+        assert_escaped(&MessagePage{ message: PAYLOAD }.render().unwrap());
+    }
+
+    //--------------------------------------------------------------------------------------------------
+    //--------------------------------------------------------------------------------------------------
+    #[test]
+    fn new_forms_leave_the_key_editable() {
+        let item = item();
+        assert!(!item_form("", &item).render().unwrap().contains("readonly"));
+        let promo = promo();
+        assert!(!promo_form("", &promo).render().unwrap().contains("readonly"));
+    }
+}
@@ -0,0 +1,486 @@
+use actix_web::cookie::{Cookie, SameSite};
+use actix_web::http::StatusCode;
+use actix_web::{web, HttpRequest, HttpResponse};
+use askama::Template;
+use log::{debug, info, warn};
+use serde::{Serialize, Deserialize};
+use std::collections::BTreeMap;
+use std::time::{Duration, Instant};
+
+use crate::checkout::{self, Checkouts};
+use crate::config::Config;
+use crate::error::{Error, Page};
+use crate::gateway::{self, Gateway};
+use crate::ledger::Ledger;
+use crate::mail::{Mailer, Receipt};
+use crate::membership::Tier;
+use crate::money::{Money, MoneyError};
+use crate::pending::{Hold, PendingCharge, PendingCharges};
+use crate::promo::{Discount, PromoReservation};
+use crate::state::{ReservationError, State};
+use crate::storage::Order;
+use crate::store::Item;
+use crate::tax::TaxError;
+use crate::util;
+
+const CART_COOKIE : &str = "sbhx_cart";
+
+//a cart nobody has touched in a week is dropped
+const CART_LIFETIME : Duration = Duration::from_secs(7 * 24 * 60 * 60);
+
+pub const MAX_SEATS_PER_CLASS : u32 = 10;
+
+//----------------------------------------------------------------------------------------------------
+//----------------------------------------------------------------------------------------------------
+pub struct Cart {
+    pub lines : BTreeMap<String, u32>,
+    touched : Instant,
+}
+
+#[derive(Deserialize,Debug, Serialize)]
+pub struct CartUpdate {
+    pub course_type : String,
+    pub quantity : u32,
+}
+
+#[derive(Deserialize,Debug, Serialize, Default)]
+pub struct CartCheckout {
+    #[serde(default)]
+    pub cart_id : String,
+    #[serde(default)]
+    pub checkout_token : String,
+    #[serde(default)]
+    pub promo_code : String,
+    #[serde(default)]
+    pub member_code : String,
+    #[serde(default)]
+    pub tax_certificate : String,
+    #[serde(flatten)]
+    payment : util::Payment,
+}
+
+#[derive(Debug)]
+pub enum CartError {
+    Empty,
+}
+
+//----------------------------------------------------------------------------------------------------
+//----------------------------------------------------------------------------------------------------
+struct CartLine {
+    key : String,
+    item : Item,
+    quantity : u32,
+    seats_left : String,
+    short : bool,
+    unit_price : Money,
+    subtotal : Money,
+    discount : Money,
+}
+
+#[derive(Template)]
+#[template(path = "cart.html")]
+struct CartPage<'a> {
+    lines : &'a [CartLine],
+    subtotal : Money,
+    tax : Money,
+    total : Money,
+    taxable : bool,
+    client_token : String,
+    checkout_token : String,
+    cart_id : &'a str,
+    buyer : &'a util::Payment,
+    promo_code : &'a str,
+    member_code : &'a str,
+    tax_certificate : &'a str,
+    member : bool,
+    listed : bool,
+    member_prices : bool,
+    message : &'a str,
+}
+
+//------------------------------------------------------------------------------------------------------
+//------------------------------------------------------------------------------------------------------
+impl Cart {
+
+    //--------------------------------------------------------------------------------------------------
+    //--------------------------------------------------------------------------------------------------
+    pub fn new() -> Cart {
+        Cart { lines: BTreeMap::new(), touched: Instant::now() }
+    }
+
+    //--------------------------------------------------------------------------------------------------
+    //--------------------------------------------------------------------------------------------------
+    pub fn set(&mut self, item_name : &str, quantity : u32) {
+        if quantity == 0 {
+            self.lines.remove(item_name);
+        } else {
+            self.lines.insert(item_name.to_string(), quantity.min(MAX_SEATS_PER_CLASS));
+        }
+        self.touched = Instant::now();
+    }
+
+    //--------------------------------------------------------------------------------------------------
+    //--------------------------------------------------------------------------------------------------
+    pub fn is_expired(&self) -> bool {
+        self.touched.elapsed() >= CART_LIFETIME
+    }
+}
+
+//------------------------------------------------------------------------------------------------------
+//------------------------------------------------------------------------------------------------------
+impl util::Failure for CartError {
+
+    //--------------------------------------------------------------------------------------------------
+    //--------------------------------------------------------------------------------------------------
+    fn status_code(&self) -> StatusCode {
+        StatusCode::BAD_REQUEST
+    }
+
+    //--------------------------------------------------------------------------------------------------
+    //--------------------------------------------------------------------------------------------------
+    fn buyer_message(&self) -> &'static str {
+        match self {
+            CartError::Empty => "Your cart is empty. Please add a class before checking out.",
+        }
+    }
+}
+
+//----------------------------------------------------------------------------------------------------
+//----------------------------------------------------------------------------------------------------
+fn cart_id(req : &HttpRequest) -> Option<String> {
+    req.cookie(CART_COOKIE)
+        .map(|cookie| cookie.value().to_string())
+        .filter(|cart_id| !cart_id.is_empty())
+}
+
+//----------------------------------------------------------------------------------------------------
+//----------------------------------------------------------------------------------------------------
+fn redirect_to_cart(config : &Config, cart_id : &str) -> HttpResponse {
+    let cookie = Cookie::build(CART_COOKIE, cart_id.to_string())
+        .path("/")
+        .http_only(true)
+        .same_site(SameSite::Lax)
+        .finish();
+
+    HttpResponse::SeeOther()
+        .header("Location", format!("{}/cart", config.urls.store.trim_end_matches('/')))
+        .cookie(cookie)
+        .finish()
+}
+
+//----------------------------------------------------------------------------------------------------
+//----------------------------------------------------------------------------------------------------
+fn cart_lines(state : &State, cart : &BTreeMap<String, u32>, tier : Tier) -> Result<Vec<CartLine>, MoneyError> {
+    cart.iter()
+        .filter_map(|(key, quantity)| {
+            let item = state.item(key)?;
+            let unit_price = item.price_for(tier);
+            let subtotal = match unit_price.checked_times(*quantity) {
+                Ok(subtotal) => subtotal,
+                Err(error) => return Some(Err(error)),
+            };
+            Some(Ok(CartLine {
+                key: key.clone(),
+                quantity: *quantity,
+                seats_left: item.number_of_items.map_or("unlimited".to_string(), |number_of_items| number_of_items.to_string()),
+                short: item.archived || item.number_of_items.map_or(false, |number_of_items| number_of_items < *quantity as i32),
+                unit_price,
+                subtotal,
+                discount: Money::zero(),
+                item,
+            }))
+        })
+        .collect()
+}
+
+//----------------------------------------------------------------------------------------------------
+//----------------------------------------------------------------------------------------------------
+// a percent code comes off every line it applies to, a fixed amount comes off the order once
+fn apply_discount(lines : &mut [CartLine], promo : &PromoReservation) -> Result<(), MoneyError> {
+    let mut fixed_left = match promo.discount {
+        Discount::Fixed(amount) => amount,
+        Discount::Percent(_) => Money::zero(),
+    };
+
+    for line in lines.iter_mut().filter(|line| promo.item.as_ref().map_or(true, |item| *item == line.key)) {
+        line.discount = match promo.discount {
+            Discount::Percent(_) => promo.discount.amount_off(line.subtotal)?,
+            Discount::Fixed(_) => {
+                let amount = fixed_left.min(line.subtotal);
+                fixed_left = fixed_left.checked_sub(amount)?;
+                amount
+            },
+        };
+    }
+    Ok(())
+}
+
+//----------------------------------------------------------------------------------------------------
+//----------------------------------------------------------------------------------------------------
+pub async fn cart_page(
+    req : HttpRequest,
+    config : web::Data<Config>,
+    gateway : web::Data<Gateway>,
+    state : web::Data<State>,
+    checkouts : web::Data<Checkouts>) -> Result<HttpResponse, Error> {
+    let checkout = CartCheckout { cart_id: cart_id(&req).unwrap_or_default(), ..Default::default() };
+
+    render_cart_page(&config, &gateway, &checkouts, &state, &checkout, None).await
+}
+
+//----------------------------------------------------------------------------------------------------
+//----------------------------------------------------------------------------------------------------
+pub async fn add_to_cart(
+    req : HttpRequest,
+    config : web::Data<Config>,
+    state : web::Data<State>,
+    update : web::Form<CartUpdate>) -> Result<HttpResponse, Error> {
+    let cart_id = cart_id(&req).unwrap_or_else(checkout::random_token);
+
+    if !state.add_to_cart(&cart_id, &update.course_type, update.quantity.max(1)) {
+        return Err(Error::NotFound(Page::new(&config, util::PaymentType::CourseSignup), update.course_type.clone()));
+    }
+
+    info!("added {} x {} to cart\n", update.quantity.max(1), update.course_type);
+    Ok(redirect_to_cart(&config, &cart_id))
+}
+
+//----------------------------------------------------------------------------------------------------
+//----------------------------------------------------------------------------------------------------
+pub async fn update_cart(
+    req : HttpRequest,
+    config : web::Data<Config>,
+    state : web::Data<State>,
+    update : web::Form<CartUpdate>) -> Result<HttpResponse, Error> {
+    let cart_id = cart_id(&req).unwrap_or_else(checkout::random_token);
+
+    if !state.set_cart_quantity(&cart_id, &update.course_type, update.quantity) {
+        return Err(Error::NotFound(Page::new(&config, util::PaymentType::CourseSignup), update.course_type.clone()));
+    }
+
+    Ok(redirect_to_cart(&config, &cart_id))
+}
+
+//----------------------------------------------------------------------------------------------------
+//----------------------------------------------------------------------------------------------------
+pub async fn checkout_cart(
+    checkout : web::Form<CartCheckout>,
+    config : web::Data<Config>,
+    gateway : web::Data<Gateway>,
+    ledger : web::Data<Ledger>,
+    mailer : web::Data<Mailer>,
+    state : web::Data<State>,
+    pending : web::Data<PendingCharges>,
+    checkouts : web::Data<Checkouts>) -> Result<HttpResponse, Error> {
+    debug!("cart checkout request = {:#?}\n", checkout);
+    let page = Page::new(&config, util::PaymentType::CourseSignup);
+
+    let checkout = checkout.into_inner();
+    let token = checkout.checkout_token.clone();
+    let form_checkouts = checkouts.clone();
+    checkouts.once(&token, page, async move {
+        cart_checkout(&checkout, &config, &gateway, &ledger, &mailer, &state, &pending, &form_checkouts).await
+    }).await
+}
+
+//----------------------------------------------------------------------------------------------------
+//----------------------------------------------------------------------------------------------------
+async fn cart_checkout(
+    checkout : &CartCheckout,
+    config : &Config,
+    gateway : &web::Data<Gateway>,
+    ledger : &Ledger,
+    mailer : &Mailer,
+    state : &State,
+    pending : &PendingCharges,
+    checkouts : &Checkouts) -> Result<HttpResponse, Error> {
+    let cart = state.cart(&checkout.cart_id);
+    if cart.is_empty() {
+        return render_cart_page(config, gateway, checkouts, state, checkout, Some(&CartError::Empty)).await;
+    }
+
+    let tier = match config.members.tier(&checkout.member_code) {
+        Ok(tier) => tier,
+        Err(error) => return render_cart_page(config, gateway, checkouts, state, checkout, Some(&error)).await,
+    };
+
+    //exempt buyers give the certificate number on file for their email
+    let exemption = match config.tax.exemption(&checkout.payment.email, &checkout.tax_certificate, config.today()) {
+        Ok(exemption) => exemption,
+        Err(error) => return render_cart_page(config, gateway, checkouts, state, checkout, Some(&error)).await,
+    };
+
+    //every seat in the cart is held at once, if any class is short the whole order is turned away
+    let seats : Vec<(String, i32)> = cart.iter().map(|(item_name, quantity)| (item_name.clone(), *quantity as i32)).collect();
+    let reservation = match state.reserve_seats(&seats) {
+        Ok(reservation) => reservation,
+        Err((item_name, error)) => {
+            warn!("cart checkout rejected, {} is unavailable {:?}\n", item_name, error);
+            //a class that has been deleted can't be shown in the cart so it is dropped from it
+            if let ReservationError::UnknownItem = error {
+                state.set_cart_quantity(&checkout.cart_id, &item_name, 0);
+            }
+            return render_cart_page(config, gateway, checkouts, state, checkout, Some(&error)).await;
+        },
+    };
+
+    let item_names : Vec<&str> = cart.keys().map(|item_name| item_name.as_str()).collect();
+    let promo = match checkout.promo_code.trim() {
+        "" => None,
+        code => match state.reserve_promo(code, &item_names, &checkout.payment.email) {
+            Ok(promo) => Some(promo),
+            Err(error) => {
+                state.release_seat(reservation);
+                return render_cart_page(config, gateway, checkouts, state, checkout, Some(&error)).await;
+            },
+        },
+    };
+
+    //an order too large to add up is refused before the card is charged
+    let certificate = exemption.map(|customer| customer.certificate.as_str());
+    let totals = cart_lines(state, &cart, tier).map_err(TaxError::from).and_then(|mut lines| {
+        if let Some(promo) = promo.as_ref() {
+            apply_discount(&mut lines, promo)?;
+        }
+        let amounts = lines.iter()
+            .map(|line| Ok((line.subtotal.checked_sub(line.discount)?, line.item.taxable)))
+            .collect::<Result<Vec<(Money, bool)>, MoneyError>>()?;
+        let tax = config.tax.quote(None, &amounts, certificate)?;
+        let tax_total = tax.total()?;
+        let amount = Money::total(amounts.iter().map(|(amount, _)| *amount))?.checked_add(tax_total)?;
+        Ok((lines, amounts, tax, tax_total, amount))
+    });
+    let (lines, amounts, tax, tax_total, amount) = match totals {
+        Ok(totals) => totals,
+        Err(error) => {
+            state.release_seat(reservation);
+            if let Some(promo) = promo {
+                state.release_promo(promo);
+            }
+            return Err(Error::Tax(Page::new(config, util::PaymentType::CourseSignup), error));
+        },
+    };
+
+    let description = lines.iter()
+        .map(|line| format!("{} x {}", line.quantity, line.item.name))
+        .collect::<Vec<String>>()
+        .join(", ");
+    let promo_code = promo.as_ref().map(|promo| promo.code.clone());
+    let sale = util::Sale {
+        payment_type: util::PaymentType::CourseSignup,
+        description: &description,
+        amount,
+        tax: Some(tax),
+        promo_code: promo_code.as_deref(),
+    };
+
+    //each line records what it cost before tax, the ledger has the charged total with the tax
+    let mut orders : Vec<Order> = lines.iter().zip(amounts.iter())
+        .map(|(line, (amount, _))| Order {
+            item: line.key.clone(),
+            amount: *amount,
+            email: checkout.payment.email.clone(),
+            transaction_id: String::new(),
+            promo_code: promo.as_ref().filter(|_| line.discount.is_positive()).map(|promo| promo.code.clone()),
+            tier,
+            quantity: line.quantity as i32,
+        })
+        .collect();
+    let dates = lines.iter()
+        .filter(|line| !line.item.dates.is_empty())
+        .map(|line| format!("{} {}", line.item.name, line.item.dates))
+        .collect::<Vec<String>>()
+        .join(", ");
+
+    let result = util::process_payment(&checkout.payment, &sale, gateway.clone(), config.gateway_timeout(), ledger).await;
+    let transaction = match result {
+        Ok(transaction) => transaction,
+        //the card may still be charged so the seats, the promo code and the cart are kept until the charge is checked
+        Err(error @ gateway::Error::Pending) => {
+            pending.hold(&checkout.checkout_token, PendingCharge::new(&checkout.payment, &sale, Hold::Seats{
+                reservation,
+                promo,
+                orders,
+                cart_id: Some(checkout.cart_id.clone()),
+                dates,
+            }));
+            return Err(Error::Payment(Page::new(config, util::PaymentType::CourseSignup), error));
+        },
+        Err(error) => {
+            state.release_seat(reservation);
+            if let Some(promo) = promo {
+                state.release_promo(promo);
+            }
+            return render_cart_page(config, gateway, checkouts, state, checkout, Some(&error)).await;
+        },
+    };
+
+    for order in orders.iter_mut() {
+        order.transaction_id = transaction.id.clone();
+    }
+    state.commit_seats(reservation, &orders);
+    if let Some(promo) = promo {
+        state.commit_promo(promo, &transaction.id);
+    }
+    state.clear_cart(&checkout.cart_id);
+
+    let mut receipt = Receipt::new(&checkout.payment, &sale.payment_type, &description, sale.amount, &transaction);
+    receipt.dates = dates;
+    receipt.tax = tax_total;
+    mailer.send_receipt(&receipt);
+
+    Ok(util::thanks(config, util::PaymentType::CourseSignup).await)
+}
+
+//----------------------------------------------------------------------------------------------------
+//----------------------------------------------------------------------------------------------------
+async fn render_cart_page(
+    config : &Config,
+    gateway : &web::Data<Gateway>,
+    checkouts : &Checkouts,
+    state : &State,
+    checkout : &CartCheckout,
+    failure : Option<&dyn util::Failure>) -> Result<HttpResponse, Error> {
+    let cart = state.cart(&checkout.cart_id);
+    let tier = config.members.tier(&checkout.member_code).unwrap_or_default();
+
+    //like the class page this shows the full tax rate, an exemption is applied when the buyer pays
+    let totals = cart_lines(state, &cart, tier).map_err(TaxError::from).and_then(|lines| {
+        let subtotal = Money::total(lines.iter().map(|line| line.subtotal))?;
+        let amounts : Vec<(Money, bool)> = lines.iter().map(|line| (line.subtotal, line.item.taxable)).collect();
+        let tax = config.tax.quote(None, &amounts, None)?.total()?;
+        Ok((lines, subtotal, tax, subtotal.checked_add(tax)?))
+    });
+    let (lines, subtotal, tax, total) = match totals {
+        Ok(totals) => totals,
+        Err(error) => return Err(Error::Tax(Page::new(config, util::PaymentType::CourseSignup), error)),
+    };
+
+    //the payment form is only shown once there is something in the cart to pay for
+    let client_token = if lines.is_empty() {
+        String::new()
+    } else {
+        let page = Page::new(config, util::PaymentType::CourseSignup);
+        gateway::client_token(gateway, config.gateway_timeout()).await
+            .map_err(|error| Error::Payment(page, error))?
+    };
+
+    Ok(util::render(util::checkout_status(failure), &CartPage{
+        lines: &lines,
+        subtotal,
+        tax,
+        total,
+        taxable: lines.iter().any(|line| line.item.taxable),
+        client_token,
+        checkout_token: checkouts.issue(),
+        cart_id: &checkout.cart_id,
+        buyer: &checkout.payment,
+        promo_code: &checkout.promo_code,
+        member_code: &checkout.member_code,
+        tax_certificate: &checkout.tax_certificate,
+        member: tier == Tier::Member,
+        listed: tier != Tier::Member && config.members.member(&checkout.payment.email).is_some(),
+        member_prices: lines.iter().any(|line| line.item.tier_prices.contains_key(&Tier::Member)),
+        message: failure.map_or("", |failure| failure.buyer_message()),
+    }))
+}
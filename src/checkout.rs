@@ -0,0 +1,228 @@
+use actix_web::dev::{Body, ResponseBody};
+use actix_web::http::{header, HeaderValue, StatusCode};
+use actix_web::web::Bytes;
+use actix_web::HttpResponse;
+use futures_channel::oneshot;
+use log::{info};
+use rand::distributions::Alphanumeric;
+use rand::Rng;
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::error::{Error, Page};
+use crate::gateway;
+
+//a checkout form left open longer than this has to be reloaded before it can be paid
+const TOKEN_LIFETIME : Duration = Duration::from_secs(24 * 60 * 60);
+
+const TOKEN_LENGTH : usize = 32;
+
+//every form view issues a token, past this many the oldest ones that aren't being paid are dropped
+const MAX_TOKENS : usize = 100_000;
+
+//----------------------------------------------------------------------------------------------------
+//----------------------------------------------------------------------------------------------------
+enum TokenState {
+    Issued,
+    Processing,
+    Completed(StatusCode, Option<HeaderValue>, Bytes),
+}
+
+//----------------------------------------------------------------------------------------------------
+//----------------------------------------------------------------------------------------------------
+struct Token {
+    state : TokenState,
+    issued : Instant,
+}
+
+//----------------------------------------------------------------------------------------------------
+//----------------------------------------------------------------------------------------------------
+// every checkout form carries a one time token so a double click or a refresh of the
+// POST gets the original result back instead of charging the card a second time
+pub struct Checkouts {
+    tokens : Arc<Mutex<HashMap<String, Token>>>,
+}
+
+//------------------------------------------------------------------------------------------------------
+//------------------------------------------------------------------------------------------------------
+impl Checkouts {
+    pub fn new() -> Checkouts {
+        Checkouts { tokens: Arc::new(Mutex::new(HashMap::new())) }
+    }
+
+    //--------------------------------------------------------------------------------------------------
+    //--------------------------------------------------------------------------------------------------
+    pub fn issue(&self) -> String {
+        let token = random_token();
+
+        let mut tokens = self.tokens.lock().unwrap();
+        tokens.retain(|_, token| token.issued.elapsed() < TOKEN_LIFETIME);
+        if tokens.len() >= MAX_TOKENS {
+            let oldest = tokens.iter()
+                .filter(|(_, token)| !matches!(token.state, TokenState::Processing))
+                .min_by_key(|(_, token)| token.issued)
+                .map(|(key, _)| key.clone());
+            if let Some(oldest) = oldest {
+                tokens.remove(&oldest);
+            }
+        }
+        tokens.insert(token.clone(), Token{ state: TokenState::Issued, issued: Instant::now() });
+        token
+    }
+
+    //--------------------------------------------------------------------------------------------------
+    //--------------------------------------------------------------------------------------------------
+    pub async fn once<F>(&self, token : &str, page : Page, checkout : F) -> Result<HttpResponse, Error>
+        where F : Future<Output = Result<HttpResponse, Error>> + 'static {
+
+        {
+            let mut tokens = self.tokens.lock().unwrap();
+            match tokens.get_mut(token) {
+                Some(entry) if entry.issued.elapsed() >= TOKEN_LIFETIME => return Err(Error::Expired(page)),
+                Some(entry) => match &entry.state {
+                    TokenState::Issued => entry.state = TokenState::Processing,
+                    TokenState::Processing => return Err(Error::InProgress(page)),
+                    TokenState::Completed(status, content_type, body) => {
+                        info!("repeat submission of checkout {}, returning the original result\n", token);
+                        return Ok(response(*status, content_type, body));
+                    },
+                },
+                None => return Err(Error::Expired(page)),
+            }
+        }
+
+        //actix drops the handler when the buyer goes away or clicks again, the checkout runs on its own
+        //task so it still finishes the charge, commits the seats and writes the ledger, and the token
+        //stays processing until it has
+        let (sender, receiver) = oneshot::channel();
+        let tokens = self.tokens.clone();
+        let token = token.to_string();
+        actix_web::rt::spawn(async move {
+            let result = settle(&tokens, &token, checkout.await);
+            if sender.send(result).is_err() {
+                info!("checkout {} finished after the buyer left\n", token);
+            }
+        });
+
+        match receiver.await {
+            Ok(result) => result,
+            //the checkout task panicked, the token stays processing so the card is not charged again
+            Err(_) => Err(Error::InProgress(page)),
+        }
+    }
+
+    //--------------------------------------------------------------------------------------------------
+    //--------------------------------------------------------------------------------------------------
+    //a pending charge that has been resolved by hand, the form it came from can't be paid again
+    pub fn forget(&self, token : &str) {
+        self.tokens.lock().unwrap().remove(token);
+    }
+}
+
+//----------------------------------------------------------------------------------------------------
+//----------------------------------------------------------------------------------------------------
+fn settle(tokens : &Mutex<HashMap<String, Token>>, token : &str, result : Result<HttpResponse, Error>) -> Result<HttpResponse, Error> {
+    let mut tokens = tokens.lock().unwrap();
+    let entry = match tokens.get_mut(token) {
+        Some(entry) => entry,
+        None => return result,
+    };
+
+    match result {
+        //only a successful checkout is final, a declined card can be retried with the same form
+        Ok(mut checkout_response) if checkout_response.status().is_success() => {
+            let status = checkout_response.status();
+            let content_type = checkout_response.headers().get(header::CONTENT_TYPE).cloned();
+            let body = match checkout_response.take_body() {
+                ResponseBody::Body(Body::Bytes(body)) | ResponseBody::Other(Body::Bytes(body)) => body,
+                _ => Bytes::new(),
+            };
+
+            let first_response = response(status, &content_type, &body);
+            entry.state = TokenState::Completed(status, content_type, body);
+            Ok(first_response)
+        },
+        //the card may have been charged so the form can't be paid again until the charge is checked
+        result @ Err(Error::Payment(_, gateway::Error::Pending)) => result,
+        result => {
+            entry.state = TokenState::Issued;
+            result
+        },
+    }
+}
+
+//----------------------------------------------------------------------------------------------------
+//----------------------------------------------------------------------------------------------------
+pub fn random_token() -> String {
+    rand::thread_rng()
+        .sample_iter(&Alphanumeric)
+        .take(TOKEN_LENGTH)
+        .map(char::from)
+        .collect()
+}
+
+//----------------------------------------------------------------------------------------------------
+//----------------------------------------------------------------------------------------------------
+fn response(status : StatusCode, content_type : &Option<HeaderValue>, body : &Bytes) -> HttpResponse {
+    let mut builder = HttpResponse::build(status);
+    if let Some(content_type) = content_type {
+        builder.set_header(header::CONTENT_TYPE, content_type.clone());
+    }
+    builder.body(body.clone())
+}
+
+//----------------------------------------------------------------------------------------------------
+//----------------------------------------------------------------------------------------------------
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::rt::time::{delay_for, timeout};
+    use std::cell::Cell;
+    use std::rc::Rc;
+
+    //--------------------------------------------------------------------------------------------------
+    //--------------------------------------------------------------------------------------------------
+    fn page() -> Page {
+        Page { name: "Course Signup", url: "https://store.example.org".to_string() }
+    }
+
+    //--------------------------------------------------------------------------------------------------
+    //--------------------------------------------------------------------------------------------------
+    //a checkout that takes a while to charge the card, counting the charges
+    fn charge(charges : Rc<Cell<u32>>) -> impl Future<Output = Result<HttpResponse, Error>> {
+        async move {
+            delay_for(Duration::from_millis(100)).await;
+            charges.set(charges.get() + 1);
+            Ok(HttpResponse::Ok().body("charged"))
+        }
+    }
+
+    //--------------------------------------------------------------------------------------------------
+    //--------------------------------------------------------------------------------------------------
+    #[actix_rt::test]
+    async fn abandoned_checkout_finishes_and_is_not_charged_again() {
+        let checkouts = Checkouts::new();
+        let token = checkouts.issue();
+        let charges = Rc::new(Cell::new(0));
+
+        //the buyer goes away mid charge and actix drops the handler
+        let abandoned = timeout(Duration::from_millis(10), checkouts.once(&token, page(), charge(charges.clone()))).await;
+        assert!(abandoned.is_err());
+
+        //submitting the form again while the charge is still running is turned away
+        match checkouts.once(&token, page(), charge(charges.clone())).await {
+            Err(Error::InProgress(_)) => {},
+            other => panic!("expected the checkout to be in progress, got {:?}", other),
+        }
+
+        delay_for(Duration::from_millis(200)).await;
+        assert_eq!(charges.get(), 1);
+
+        //once the abandoned checkout has finished a resubmission gets its result without another charge
+        let response = checkouts.once(&token, page(), charge(charges.clone())).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(charges.get(), 1);
+    }
+}
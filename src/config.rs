@@ -0,0 +1,453 @@
+use braintree::Environment;
+use chrono::{NaiveDate, Utc};
+use chrono_tz::Tz;
+use lettre::message::Mailbox;
+use log::{info, warn};
+use serde::{Deserialize, Deserializer};
+use std::fmt;
+use std::io;
+use std::net::IpAddr;
+use std::str::FromStr;
+use std::time::Duration;
+
+use crate::acknowledgement::Organization;
+use crate::membership::Members;
+use crate::tax::TaxTable;
+
+const DEFAULT_CONFIG_FILE : &str = "config.toml";
+
+#[derive(Debug)]
+pub enum Error {
+    Io(String, io::Error),
+    Parse(String, toml::de::Error),
+    Env(String, String),
+    Invalid(Vec<String>),
+}
+
+//----------------------------------------------------------------------------------------------------
+//----------------------------------------------------------------------------------------------------
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct Descriptor {
+    pub name : String,
+    pub phone : String,
+    pub url : String,
+}
+
+//----------------------------------------------------------------------------------------------------
+//----------------------------------------------------------------------------------------------------
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct Urls {
+    pub store : String,
+    pub donate : String,
+    pub invoice : String,
+}
+
+//----------------------------------------------------------------------------------------------------
+//----------------------------------------------------------------------------------------------------
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct MailConfig {
+    pub from : String,
+    pub organizers : Vec<String>,
+    pub outbox : Option<String>,
+    pub smtp_host : Option<String>,
+    pub smtp_port : Option<u16>,
+    pub smtp_user : Option<String>,
+    pub templates : Option<String>,
+}
+
+//----------------------------------------------------------------------------------------------------
+//----------------------------------------------------------------------------------------------------
+// the braintree keys and admin login only ever come from the environment so they can't end up in a
+// config file, and Debug leaves the secrets out
+#[derive(Clone)]
+pub struct Credentials {
+    pub environment : Option<String>,
+    pub merchant_id : String,
+    pub public_key : String,
+    pub private_key : String,
+    pub admin_user : String,
+    pub admin_password : Option<String>,
+}
+
+//----------------------------------------------------------------------------------------------------
+//----------------------------------------------------------------------------------------------------
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub bind_address : String,
+    pub port : u16,
+    pub inventory_file : String,
+    pub fundraisers_file : String,
+    pub invoices_file : String,
+    pub promo_codes_file : String,
+    pub ledger_file : String,
+    pub orders_file : String,
+    pub donations_file : String,
+    pub pending_file : String,
+    pub database : Option<String>,
+    #[serde(deserialize_with = "timezone")]
+    pub timezone : Tz,
+    pub tax : TaxTable,
+    pub members : Members,
+    pub verify_gateway : bool,
+    pub gateway_timeout_seconds : u64,
+    pub descriptor : Descriptor,
+    pub urls : Urls,
+    pub organization : Organization,
+    pub mail : MailConfig,
+    #[serde(skip)]
+    pub credentials : Credentials,
+}
+
+//------------------------------------------------------------------------------------------------------
+//------------------------------------------------------------------------------------------------------
+impl Default for Descriptor {
+    fn default() -> Descriptor {
+        Descriptor {
+            name: "sbhx   *   product".to_string(),
+            phone: "8052422533".to_string(),
+            url: String::new(),
+        }
+    }
+}
+
+//------------------------------------------------------------------------------------------------------
+//------------------------------------------------------------------------------------------------------
+impl Default for Urls {
+    fn default() -> Urls {
+        Urls {
+            store: "https://store.sbhackerspace.com".to_string(),
+            donate: "https://donate.sbhackerspace.com".to_string(),
+            invoice: "https://invoice.sbhackerspace.com".to_string(),
+        }
+    }
+}
+
+//------------------------------------------------------------------------------------------------------
+//------------------------------------------------------------------------------------------------------
+impl Default for MailConfig {
+    fn default() -> MailConfig {
+        MailConfig {
+            from: "SBHX Store <store@sbhackerspace.com>".to_string(),
+            organizers: Vec::new(),
+            outbox: None,
+            smtp_host: None,
+            smtp_port: None,
+            smtp_user: None,
+            templates: None,
+        }
+    }
+}
+
+//------------------------------------------------------------------------------------------------------
+//------------------------------------------------------------------------------------------------------
+impl Default for Credentials {
+    fn default() -> Credentials {
+        Credentials {
+            environment: None,
+            merchant_id: String::new(),
+            public_key: String::new(),
+            private_key: String::new(),
+            admin_user: "admin".to_string(),
+            admin_password: None,
+        }
+    }
+}
+
+//------------------------------------------------------------------------------------------------------
+//------------------------------------------------------------------------------------------------------
+impl fmt::Debug for Credentials {
+    fn fmt(&self, f : &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Credentials")
+            .field("environment", &self.environment)
+            .field("merchant_id", &self.merchant_id)
+            .field("admin_user", &self.admin_user)
+            .field("admin_password", &self.admin_password.as_ref().map(|_| "set"))
+            .finish()
+    }
+}
+
+//------------------------------------------------------------------------------------------------------
+//------------------------------------------------------------------------------------------------------
+impl Default for Config {
+    fn default() -> Config {
+        Config {
+            bind_address: "0.0.0.0".to_string(),
+            port: 7777,
+            inventory_file: "inventory.json".to_string(),
+            fundraisers_file: "fundraising_goals.json".to_string(),
+            invoices_file: "invoices.json".to_string(),
+            promo_codes_file: "promo_codes.json".to_string(),
+            ledger_file: "ledger.jsonl".to_string(),
+            orders_file: "orders.jsonl".to_string(),
+            donations_file: "donations.jsonl".to_string(),
+            pending_file: "pending_charges.json".to_string(),
+            database: None,
+            timezone: chrono_tz::America::Los_Angeles,
+            tax: TaxTable::default(),
+            members: Members::default(),
+            verify_gateway: true,
+            gateway_timeout_seconds: 30,
+            descriptor: Descriptor::default(),
+            urls: Urls::default(),
+            organization: Organization::default(),
+            mail: MailConfig::default(),
+            credentials: Credentials::default(),
+        }
+    }
+}
+
+//------------------------------------------------------------------------------------------------------
+//------------------------------------------------------------------------------------------------------
+impl fmt::Display for Error {
+    fn fmt(&self, f : &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Io(file_name, error) => write!(f, "unable to read {}: {}", file_name, error),
+            Error::Parse(file_name, error) => write!(f, "unable to parse config file {}: {}", file_name, error),
+            Error::Env(name, error) => write!(f, "environment variable {} is invalid: {}", name, error),
+            Error::Invalid(problems) => write!(f, "invalid configuration:\n  {}", problems.join("\n  ")),
+        }
+    }
+}
+
+//----------------------------------------------------------------------------------------------------
+//----------------------------------------------------------------------------------------------------
+fn env_string(name : &str, value : &mut String) {
+    if let Ok(env_value) = std::env::var(name) {
+        *value = env_value;
+    }
+}
+
+//----------------------------------------------------------------------------------------------------
+//----------------------------------------------------------------------------------------------------
+fn env_option(name : &str, value : &mut Option<String>) {
+    if let Ok(env_value) = std::env::var(name) {
+        *value = Some(env_value).filter(|env_value| !env_value.is_empty());
+    }
+}
+
+//----------------------------------------------------------------------------------------------------
+//----------------------------------------------------------------------------------------------------
+fn env_parsed<T : FromStr>(name : &str, value : &mut T) -> Result<(), Error> where T::Err : fmt::Display {
+    if let Ok(env_value) = std::env::var(name) {
+        *value = env_value.parse().map_err(|error : T::Err| Error::Env(name.to_string(), error.to_string()))?;
+    }
+    Ok(())
+}
+
+//----------------------------------------------------------------------------------------------------
+//----------------------------------------------------------------------------------------------------
+fn timezone<'de, D : Deserializer<'de>>(deserializer : D) -> Result<Tz, D::Error> {
+    let name = String::deserialize(deserializer)?;
+    name.parse().map_err(|error| serde::de::Error::custom(format!("timezone {} is unknown: {}", name, error)))
+}
+
+//------------------------------------------------------------------------------------------------------
+//------------------------------------------------------------------------------------------------------
+impl Credentials {
+
+    //--------------------------------------------------------------------------------------------------
+    //--------------------------------------------------------------------------------------------------
+    //the ledger, statements and tax-report commands run without ENVIRONMENT, the server won't start
+    fn validate(&self, problems : &mut Vec<String>) {
+        match self.environment.as_deref() {
+            None => {},
+            Some("fake") if !cfg!(any(test, feature = "fake-gateway")) =>
+                problems.push("ENVIRONMENT fake needs a build with the fake-gateway feature".to_string()),
+            Some("fake") => {},
+            Some(environment) => {
+                if Environment::from_str(environment).is_err() {
+                    problems.push(format!("ENVIRONMENT {} is not a braintree environment", environment));
+                }
+                for (name, value) in [
+                    ("MERCHANT_ID", &self.merchant_id),
+                    ("PUBLIC_KEY", &self.public_key),
+                    ("PRIVATE_KEY", &self.private_key)] {
+                    if value.trim().is_empty() {
+                        problems.push(format!("environment variable {} must be set for braintree", name));
+                    }
+                }
+            },
+        }
+
+        if self.admin_user.trim().is_empty() {
+            problems.push("ADMIN_USER must not be empty".to_string());
+        }
+    }
+}
+
+//------------------------------------------------------------------------------------------------------
+//------------------------------------------------------------------------------------------------------
+impl Config {
+
+    //--------------------------------------------------------------------------------------------------
+    //--------------------------------------------------------------------------------------------------
+    pub fn load() -> Result<Config, Error> {
+        //the default config file is optional, one named in CONFIG has to exist
+        let (file_name, required) = match std::env::var("CONFIG") {
+            Ok(file_name) => (file_name, true),
+            Err(_) => (DEFAULT_CONFIG_FILE.to_string(), false),
+        };
+
+        let mut config = match std::fs::read_to_string(&file_name) {
+            Ok(contents) => {
+                info!("loading config from {}", file_name);
+                toml::from_str(&contents).map_err(|error| Error::Parse(file_name.clone(), error))?
+            },
+            Err(error) if error.kind() == io::ErrorKind::NotFound && !required => Config::default(),
+            Err(error) => return Err(Error::Io(file_name, error)),
+        };
+
+        config.apply_env()?;
+        config.tax.load_exempt_customers().map_err(|error| Error::Io(config.tax.exempt_customers_file.clone(), error))?;
+        config.members.load().map_err(|error| Error::Io(config.members.file.clone(), error))?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    //--------------------------------------------------------------------------------------------------
+    //--------------------------------------------------------------------------------------------------
+    pub fn gateway_timeout(&self) -> Duration {
+        Duration::from_secs(self.gateway_timeout_seconds)
+    }
+
+    //--------------------------------------------------------------------------------------------------
+    //--------------------------------------------------------------------------------------------------
+    //the date at the store, not on whatever clock the server happens to be set to
+    pub fn today(&self) -> NaiveDate {
+        Utc::now().with_timezone(&self.timezone).date_naive()
+    }
+
+    //--------------------------------------------------------------------------------------------------
+    //--------------------------------------------------------------------------------------------------
+    fn apply_env(&mut self) -> Result<(), Error> {
+        env_string("BIND_ADDRESS", &mut self.bind_address);
+        env_parsed("PORT", &mut self.port)?;
+        env_string("INVENTORY_FILE", &mut self.inventory_file);
+        env_string("FUNDRAISERS_FILE", &mut self.fundraisers_file);
+        env_string("INVOICES_FILE", &mut self.invoices_file);
+        env_string("PROMO_CODES_FILE", &mut self.promo_codes_file);
+        env_string("LEDGER_FILE", &mut self.ledger_file);
+        env_string("ORDERS_FILE", &mut self.orders_file);
+        env_string("DONATIONS_FILE", &mut self.donations_file);
+        env_string("PENDING_FILE", &mut self.pending_file);
+        env_option("DATABASE", &mut self.database);
+        env_parsed("TIMEZONE", &mut self.timezone)?;
+        env_parsed("VERIFY_GATEWAY", &mut self.verify_gateway)?;
+        env_parsed("GATEWAY_TIMEOUT_SECONDS", &mut self.gateway_timeout_seconds)?;
+        env_string("DESCRIPTOR_NAME", &mut self.descriptor.name);
+        env_string("DESCRIPTOR_PHONE", &mut self.descriptor.phone);
+        env_string("DESCRIPTOR_URL", &mut self.descriptor.url);
+        env_string("STORE_URL", &mut self.urls.store);
+        env_string("DONATE_URL", &mut self.urls.donate);
+        env_string("INVOICE_URL", &mut self.urls.invoice);
+        env_string("TAX_JURISDICTION", &mut self.tax.jurisdiction);
+        env_string("TAX_EXEMPT_CUSTOMERS_FILE", &mut self.tax.exempt_customers_file);
+        env_string("MEMBERS_FILE", &mut self.members.file);
+        env_string("ORGANIZATION_NAME", &mut self.organization.name);
+        env_string("ORGANIZATION_EIN", &mut self.organization.ein);
+        env_string("ORGANIZATION_ADDRESS", &mut self.organization.address);
+        env_string("MAIL_FROM", &mut self.mail.from);
+        env_option("MAIL_OUTBOX", &mut self.mail.outbox);
+        env_option("SMTP_HOST", &mut self.mail.smtp_host);
+        env_option("SMTP_USER", &mut self.mail.smtp_user);
+        env_option("MAIL_TEMPLATES", &mut self.mail.templates);
+        env_option("ENVIRONMENT", &mut self.credentials.environment);
+        env_string("MERCHANT_ID", &mut self.credentials.merchant_id);
+        env_string("PUBLIC_KEY", &mut self.credentials.public_key);
+        env_string("PRIVATE_KEY", &mut self.credentials.private_key);
+        env_string("ADMIN_USER", &mut self.credentials.admin_user);
+        env_option("ADMIN_PASSWORD", &mut self.credentials.admin_password);
+
+        if let Ok(organizers) = std::env::var("MAIL_ORGANIZERS") {
+            self.mail.organizers = organizers.split(',')
+                .map(|organizer| organizer.trim().to_string())
+                .filter(|organizer| !organizer.is_empty())
+                .collect();
+        }
+        if let Ok(port) = std::env::var("SMTP_PORT") {
+            self.mail.smtp_port = Some(port.parse().map_err(|error : std::num::ParseIntError| Error::Env("SMTP_PORT".to_string(), error.to_string()))?);
+        }
+        Ok(())
+    }
+
+    //--------------------------------------------------------------------------------------------------
+    //--------------------------------------------------------------------------------------------------
+    fn validate(&self) -> Result<(), Error> {
+        let mut problems = Vec::new();
+
+        if self.bind_address.parse::<IpAddr>().is_err() {
+            problems.push(format!("bind_address {} is not an ip address", self.bind_address));
+        }
+        if self.port == 0 {
+            problems.push("port must not be 0".to_string());
+        }
+
+        for (name, file_name) in [
+            ("inventory_file", &self.inventory_file),
+            ("fundraisers_file", &self.fundraisers_file),
+            ("invoices_file", &self.invoices_file),
+            ("promo_codes_file", &self.promo_codes_file),
+            ("ledger_file", &self.ledger_file),
+            ("orders_file", &self.orders_file),
+            ("donations_file", &self.donations_file),
+            ("pending_file", &self.pending_file)] {
+            if file_name.trim().is_empty() {
+                problems.push(format!("{} must not be empty", name));
+            }
+        }
+
+        match self.database.as_deref() {
+            Some(database) if database.trim().is_empty() => problems.push("database must not be empty".to_string()),
+            Some(_) if !cfg!(feature = "sqlite") =>
+                problems.push("database is set but this build has no sqlite support, rebuild with --features sqlite or remove it".to_string()),
+            _ => {},
+        }
+
+        if self.gateway_timeout_seconds == 0 {
+            problems.push("gateway_timeout_seconds must not be 0".to_string());
+        }
+
+        self.tax.validate(&mut problems);
+        self.credentials.validate(&mut problems);
+
+        //braintree rejects the whole transaction if the descriptor is malformed
+        match self.descriptor.name.split_once('*') {
+            Some((company, _)) if [3, 7, 12].contains(&company.len()) && self.descriptor.name.len() <= 22 => {},
+            _ => problems.push(format!(
+                "descriptor.name \"{}\" must be \"company*product\" with a 3, 7 or 12 character company and at most 22 characters",
+                self.descriptor.name)),
+        }
+        if self.descriptor.phone.chars().filter(|c| c.is_ascii_digit()).count() != 10 {
+            problems.push(format!("descriptor.phone {} must have 10 digits", self.descriptor.phone));
+        }
+
+        for (name, page_url) in [("urls.store", &self.urls.store), ("urls.donate", &self.urls.donate), ("urls.invoice", &self.urls.invoice)] {
+            if let Err(error) = url::Url::parse(page_url) {
+                problems.push(format!("{} {} is not a valid url: {}", name, page_url, error));
+            }
+        }
+
+        if self.organization.name.trim().is_empty() {
+            problems.push("organization.name must not be empty".to_string());
+        }
+        if self.organization.ein.trim().is_empty() {
+            warn!("organization.ein is not set, donation acknowledgements will be missing it");
+        }
+
+        for mailbox in std::iter::once(&self.mail.from).chain(self.mail.organizers.iter()) {
+            if let Err(error) = mailbox.parse::<Mailbox>() {
+                problems.push(format!("mail address {} is invalid: {}", mailbox, error));
+            }
+        }
+
+        if problems.is_empty() {
+            Ok(())
+        } else {
+            Err(Error::Invalid(problems))
+        }
+    }
+}
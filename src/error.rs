@@ -0,0 +1,153 @@
+use actix_web::http::StatusCode;
+use actix_web::{HttpResponse, ResponseError};
+use askama::Template;
+use log::{error, warn};
+use std::fmt;
+
+use crate::config::Config;
+use crate::gateway;
+use crate::money::MoneyError;
+use crate::state::ReservationError;
+use crate::storage;
+use crate::tax::TaxError;
+use crate::util::{self, PaymentType};
+
+//----------------------------------------------------------------------------------------------------
+//----------------------------------------------------------------------------------------------------
+#[derive(Debug, Clone)]
+pub struct Page {
+    pub name : &'static str,
+    pub url : String,
+}
+
+//----------------------------------------------------------------------------------------------------
+//----------------------------------------------------------------------------------------------------
+#[derive(Debug)]
+pub enum Error {
+    NotFound(Page, String),
+    Unavailable(Page, String, ReservationError),
+    Payment(Page, gateway::Error),
+    Amount(Page, MoneyError),
+    Tax(Page, TaxError),
+    Storage(Page, storage::Error),
+    InProgress(Page),
+    Expired(Page),
+}
+
+#[derive(Template)]
+#[template(path = "error.html")]
+struct ErrorPage<'a> {
+    name : &'static str,
+    url : &'a str,
+    message : &'static str,
+}
+
+#[derive(Template)]
+#[template(path = "pending.html")]
+struct PendingPage<'a> {
+    name : &'static str,
+    url : &'a str,
+    message : &'static str,
+}
+
+#[derive(Template)]
+#[template(path = "not_found.html")]
+struct NotFoundPage<'a> {
+    name : &'static str,
+    url : &'a str,
+}
+
+//------------------------------------------------------------------------------------------------------
+//------------------------------------------------------------------------------------------------------
+impl Page {
+    pub fn new(config : &Config, payment_type : PaymentType) -> Page {
+        Page { name: payment_type.as_str(), url: payment_type.get_url(&config.urls).to_string() }
+    }
+}
+
+//------------------------------------------------------------------------------------------------------
+//------------------------------------------------------------------------------------------------------
+impl Error {
+
+    //--------------------------------------------------------------------------------------------------
+    //--------------------------------------------------------------------------------------------------
+    fn page(&self) -> &Page {
+        match self {
+            Error::NotFound(page, _) => page,
+            Error::Unavailable(page, _, _) => page,
+            Error::Payment(page, _) => page,
+            Error::Amount(page, _) => page,
+            Error::Tax(page, _) => page,
+            Error::Storage(page, _) => page,
+            Error::InProgress(page) => page,
+            Error::Expired(page) => page,
+        }
+    }
+
+    //--------------------------------------------------------------------------------------------------
+    //--------------------------------------------------------------------------------------------------
+    fn message(&self) -> &'static str {
+        match self {
+            Error::InProgress(_) => "Your payment is already being processed. Please wait for your receipt instead of submitting again.",
+            Error::Expired(_) => "This checkout page has expired. Please go back and reload the page before paying.",
+            Error::Amount(_, _) | Error::Tax(_, TaxError::Amount(_)) => "That amount is too large to pay online. Please contact us to arrange payment.",
+            _ => "There was an error. Please try again!",
+        }
+    }
+}
+
+//------------------------------------------------------------------------------------------------------
+//------------------------------------------------------------------------------------------------------
+impl fmt::Display for Error {
+    fn fmt(&self, f : &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::NotFound(page, name) => write!(f, "{} {} not found", page.name, name),
+            Error::Unavailable(page, name, error) => write!(f, "{} {} is unavailable {:?}", page.name, name, error),
+            Error::Payment(page, error) => write!(f, "{} payment failed {:?}", page.name, error),
+            Error::Amount(page, error) => write!(f, "{} amount can not be worked out {}", page.name, error),
+            Error::Tax(page, error) => write!(f, "{} tax can not be worked out {}", page.name, error),
+            Error::Storage(page, error) => write!(f, "{} storage failed {:?}", page.name, error),
+            Error::InProgress(page) => write!(f, "{} checkout submitted again while it was processing", page.name),
+            Error::Expired(page) => write!(f, "{} checkout token is unknown or expired", page.name),
+        }
+    }
+}
+
+//------------------------------------------------------------------------------------------------------
+//------------------------------------------------------------------------------------------------------
+impl ResponseError for Error {
+
+    //--------------------------------------------------------------------------------------------------
+    //--------------------------------------------------------------------------------------------------
+    fn status_code(&self) -> StatusCode {
+        match self {
+            Error::NotFound(_, _) => StatusCode::NOT_FOUND,
+            Error::Unavailable(_, _, _) => StatusCode::CONFLICT,
+            Error::Payment(_, error) => error.status_code(),
+            Error::Amount(_, _) | Error::Tax(_, TaxError::Amount(_)) => StatusCode::UNPROCESSABLE_ENTITY,
+            Error::Tax(_, TaxError::NoRate(_)) => StatusCode::INTERNAL_SERVER_ERROR,
+            Error::Storage(_, _) => StatusCode::INTERNAL_SERVER_ERROR,
+            Error::InProgress(_) => StatusCode::CONFLICT,
+            Error::Expired(_) => StatusCode::BAD_REQUEST,
+        }
+    }
+
+    //--------------------------------------------------------------------------------------------------
+    //--------------------------------------------------------------------------------------------------
+    fn error_response(&self) -> HttpResponse {
+        let status = self.status_code();
+        if status.is_server_error() {
+            error!("Error: {}\n", self);
+        } else {
+            warn!("{}\n", self);
+        }
+
+        let page = self.page();
+        match self {
+            Error::NotFound(_, _) => util::render(status, &NotFoundPage{ name: page.name, url: &page.url }),
+            Error::Payment(_, error @ gateway::Error::Pending) =>
+                util::render(status, &PendingPage{ name: page.name, url: &page.url, message: error.buyer_message() }),
+            _ => util::render(status, &ErrorPage{ name: page.name, url: &page.url, message: self.message() }),
+        }
+    }
+}
@@ -1,139 +1,330 @@
 use actix_web::{web, HttpResponse};
-use braintree::{Braintree};
+use actix_web::http::StatusCode;
+use askama::Template;
 use serde::{Serialize, Deserialize};
-use log::{debug, error, info};
-use std::collections::BTreeMap;
-use std::fs::File;
-use std::sync::{Mutex};
+use log::{error, info};
 
+use crate::acknowledgement::Acknowledgement;
+use crate::checkout::Checkouts;
+use crate::config::Config;
+use crate::error::{Error, Page};
+use crate::gateway::{self, Gateway};
+use crate::ledger::Ledger;
+use crate::mail::{Mailer, Receipt};
+use crate::money::Money;
+use crate::pending::{Hold, PendingCharge, PendingCharges};
+use crate::state::{self, Listing, State};
+use crate::storage::DonationRecord;
 use crate::util;
 
 #[derive(Deserialize,Debug, Serialize)]
 pub struct Donation
 {
-    pub amount : f32,
+    pub amount : Money,
     pub fundraiser_name : String,
+    #[serde(default)]
+    pub checkout_token : String,
     #[serde(flatten)]
     payment : util::Payment,
 }
 
-#[derive(Deserialize,Debug, Serialize)]
+#[derive(Deserialize,Debug, Serialize, Clone)]
 pub struct Fundraiser {
     pub name : String,
-    pub goal : f32,
-    pub amount_raised : f32,
+    pub goal : Money,
+    pub amount_raised : Money,
     pub formname : String,
     pub image : String,
     pub description : String,
+    #[serde(default)]
+    pub archived : bool,
+    #[serde(default)]
+    pub position : i32,
 }
-//----------------------------------------------------------------------------------------------------
-//----------------------------------------------------------------------------------------------------
-impl Fundraiser {
+
+#[derive(Debug)]
+pub enum DonationError {
+    NotPositive,
+}
+
+#[derive(Template)]
+#[template(path = "fundraise.html")]
+struct FundraisersPage<'a> {
+    fundraisers : &'a [Fundraiser],
+}
+
+#[derive(Template)]
+#[template(path = "donation_thanks.html")]
+struct DonationThanksPage<'a> {
+    url : &'a str,
+    letter : String,
+    download : String,
+    file_name : String,
+}
+
+#[derive(Template)]
+#[template(path = "donate.html")]
+struct DonatePage<'a> {
+    fundraiser : &'a Fundraiser,
+    client_token : String,
+    checkout_token : String,
+    amount : String,
+    buyer : &'a util::Payment,
+    message : &'a str,
+}
+
+//------------------------------------------------------------------------------------------------------
+//------------------------------------------------------------------------------------------------------
+impl util::Failure for DonationError {
+
     //--------------------------------------------------------------------------------------------------
     //--------------------------------------------------------------------------------------------------
-    pub fn get_button(&self) -> String {
-        return format!(
-            "<a href=\"{}\" class=\"w-50 btn btn-lg btn-success\" role=\"button\">Donate Now</a>",
-            self.formname);
+    fn status_code(&self) -> StatusCode {
+        StatusCode::BAD_REQUEST
     }
 
     //--------------------------------------------------------------------------------------------------
     //--------------------------------------------------------------------------------------------------
-    pub fn get_entry(&self) -> String {
-
-          format!("<div class=\"col-md-6 col-lg-4 g-mb-30\"><article class=\"u-shadow-v18 g-bg-white text-center rounded g-px-20 g-py-40 g-mb-5\">
-            <img class=\"d-inline-block img-fluid mb-4\" Width=\"400\" Height=\"200\" src=\"{}\" alt=\"Image Description\">
-            <h4 class=\"h5 g-color-black g-font-weight-600 g-mb-10\">{}</h4>
-            <p> {} </p>
-            <div class=\"progress\">
-              <div class=\"progress-bar bg-success\" role=\"progressbar\" style=\"width: {}%\" aria-valuenow=\"{}\" aria-valuemin=\"0\" aria-valuemax=\"{}\">${} of ${} Raised</div>
-            </div>
-            <p>  </p>
-            {}
-            </article></div>",
-          self.image,
-          self.name,
-          self.description,
-          (100.0* (1.0- (self.goal - self.amount_raised)/self.goal)) as i32,
-          self.amount_raised as i32,
-          self.goal as i32,
-          self.amount_raised as i32,
-          self.goal as i32,
-          self.get_button())
+    fn buyer_message(&self) -> &'static str {
+        match self {
+            DonationError::NotPositive => "Please enter a donation amount greater than zero.",
+        }
     }
+}
+
+//------------------------------------------------------------------------------------------------------
+//------------------------------------------------------------------------------------------------------
+impl Listing for Fundraiser {
+    fn position(&self) -> i32 { self.position }
+
+    fn set_position(&mut self, position : i32) { self.position = position; }
+
+    fn archived(&self) -> bool { self.archived }
 
+    fn set_archived(&mut self, archived : bool) { self.archived = archived; }
 }
 
 //----------------------------------------------------------------------------------------------------
 //----------------------------------------------------------------------------------------------------
 pub async fn process_donation(
     donation : web::Form<Donation>,
-    braintree : web::Data<Mutex<Braintree>>) -> HttpResponse {
+    config : web::Data<Config>,
+    gateway : web::Data<Gateway>,
+    ledger : web::Data<Ledger>,
+    mailer : web::Data<Mailer>,
+    state : web::Data<State>,
+    pending : web::Data<PendingCharges>,
+    checkouts : web::Data<Checkouts>) -> Result<HttpResponse, Error> {
+    let page = Page::new(&config, util::PaymentType::Donation);
 
-    let mut fundraisers = util::get_file::<BTreeMap<String, Fundraiser>>("fundraising_goals.json".to_string());
+    let donation = donation.into_inner();
+    let token = donation.checkout_token.clone();
+    let form_checkouts = checkouts.clone();
+    checkouts.once(&token, page, async move {
+        donation_checkout(&donation, &config, &gateway, &ledger, &mailer, &state, &pending, &form_checkouts).await
+    }).await
+}
+
+//----------------------------------------------------------------------------------------------------
+//----------------------------------------------------------------------------------------------------
+async fn donation_checkout(
+    donation : &Donation,
+    config : &Config,
+    gateway : &web::Data<Gateway>,
+    ledger : &Ledger,
+    mailer : &Mailer,
+    state : &State,
+    pending : &PendingCharges,
+    checkouts : &Checkouts) -> Result<HttpResponse, Error> {
+    let page = Page::new(config, util::PaymentType::Donation);
 
-    debug!("fundraisers = {:#?}\n", fundraisers);
-    let result = util::process_payment(&donation.payment, donation.amount, braintree, util::PaymentType::Donation, &donation.fundraiser_name);
+    let fundraiser = match state.fundraiser(&donation.fundraiser_name) {
+        Some(fundraiser) if !fundraiser.archived => fundraiser,
+        _ => return Err(Error::NotFound(page, donation.fundraiser_name.clone())),
+    };
 
-    if result.is_err() {
-        error!("Error: payment process {:#?}\n", result);
-        return util::error(util::PaymentType::Donation).await;
+    if !donation.amount.is_positive() {
+        return render_donate_page(config, gateway, checkouts, page, &fundraiser, Some(donation), Some(&DonationError::NotPositive)).await;
+    }
+    //a donation too large to add to the total is refused before the card is charged
+    if let Err(error) = fundraiser.amount_raised.checked_add(donation.amount) {
+        return Err(Error::Amount(page, error));
     }
 
-    info!("donation of {} processed for {}\n",donation.amount, donation.fundraiser_name);
+    //a donation is not a sale so it carries no sales tax
+    let sale = util::Sale {
+        payment_type: util::PaymentType::Donation,
+        description: &donation.fundraiser_name,
+        amount: donation.amount,
+        tax: None,
+        promo_code: None,
+    };
+    let mut record = DonationRecord{
+        fundraiser: donation.fundraiser_name.clone(),
+        amount: donation.amount,
+        email: donation.payment.email.clone(),
+        transaction_id: String::new(),
+    };
 
-    match fundraisers.get_mut(&donation.fundraiser_name) {
-        Some(ref mut fundraiser) => {
-            fundraiser.amount_raised += donation.amount;
-            info!("amount_raised = {:#?}\n", fundraiser.amount_raised);
-        },
-        None => {
-            error!("Error: unknown fundraiser name {}\n", donation.fundraiser_name);
-            return util::error(util::PaymentType::Donation).await;
+    let result = util::process_payment(&donation.payment, &sale, gateway.clone(), config.gateway_timeout(), ledger).await;
+
+    let transaction = match result {
+        Ok(transaction) => transaction,
+        //the card may still be charged so the buyer is told not to donate again
+        Err(error @ gateway::Error::Pending) => {
+            pending.hold(&donation.checkout_token, PendingCharge::new(&donation.payment, &sale, Hold::Donation{ record }));
+            return Err(Error::Payment(page, error));
         },
-    }
+        Err(error) => return render_donate_page(config, gateway, checkouts, page, &fundraiser, Some(donation), Some(&error)).await,
+    };
+
+    info!("donation of {} processed for {}\n",donation.amount, donation.fundraiser_name);
+
+    record.transaction_id = transaction.id.clone();
+    let fundraiser = state.add_donation(&record)
+        .ok_or_else(|| Error::NotFound(page, donation.fundraiser_name.clone()))?;
 
-    debug!("fundraisers = {:?}\n", fundraisers);
+    mailer.send_receipt(&Receipt::new(&donation.payment, &util::PaymentType::Donation, &fundraiser.name, donation.amount, &transaction));
 
-    serde_json::to_writer_pretty(
-        &File::create("fundraising_goals.json").expect("unable to open file"),
-        &fundraisers).expect("unable to write fundraising_goals.json");
+    let acknowledgement = Acknowledgement::new(&config.organization, &donation.payment, &fundraiser.name, donation.amount, &transaction);
+    mailer.send_acknowledgement(&acknowledgement);
 
-    util::thanks(util::PaymentType::Donation).await
+    let letter = match acknowledgement.render() {
+        Ok(letter) => letter,
+        Err(render_error) => {
+            //the donation already went through so still thank them
+            error!("Error: unable to render acknowledgement {:?}\n", render_error);
+            return Ok(util::thanks(config, util::PaymentType::Donation).await);
+        },
+    };
+
+    Ok(util::render(StatusCode::OK, &DonationThanksPage{
+        url: util::PaymentType::Donation.get_url(&config.urls),
+        download: base64::encode(&letter),
+        file_name: acknowledgement.file_name(),
+        letter,
+    }))
 }
 
 //----------------------------------------------------------------------------------------------------
 //----------------------------------------------------------------------------------------------------
-pub async fn fundraisers_page()-> HttpResponse {
-    let web_page = include_str!("../static/fundraise.html");
-    let fundraisers = util::get_file::<BTreeMap<String, Fundraiser>>("fundraising_goals.json".to_string());
+pub async fn fundraisers_page(state : web::Data<State>)-> HttpResponse {
+    let fundraisers = state.fundraisers();
     info!("fundraisers = {:?}\n", fundraisers);
 
-    let mut content = String::new();
-    for (_, fundraiser) in fundraisers.iter() {
-        content += fundraiser.get_entry().as_str();
-    }
-    let web_page = web_page.replace("FUNDRAISERS", &content);
+    let listed : Vec<Fundraiser> = state::listed(&fundraisers).into_iter()
+        .map(|(_, fundraiser)| fundraiser)
+        .collect();
 
-    HttpResponse::Ok().content_type("text/html; charset=utf-8").body(web_page)
+    util::render(StatusCode::OK, &FundraisersPage{ fundraisers: &listed })
 }
 
 //----------------------------------------------------------------------------------------------------
 //----------------------------------------------------------------------------------------------------
-pub async fn fundraiser_page(braintree : web::Data<Mutex<Braintree>>, name : String) -> HttpResponse {
-    let fundraisers = util::get_file::<BTreeMap<String, Fundraiser>>("fundraising_goals.json".to_string());
-    let fundraiser = fundraisers.get(&name).unwrap();
-    let braintree = braintree.lock().unwrap();
-
-    info!("{} amount_raised = {}\n", name, fundraiser.amount_raised);
-    HttpResponse::Ok()
-        .content_type("text/html; charset=utf-8")
-        .body(include_str!("../static/donate.html")
-              .replace("FORMNAME", &fundraiser.formname)
-              .replace("NAME", &fundraiser.name)
-              .replace("DESCRIPTION", &fundraiser.description)
-              .replace(
-                  "CLIENT_TOKEN_FROM_SERVER",
-                  braintree.client_token().generate(Default::default()).expect("unable to get client token").value.as_str()))
+pub async fn fundraiser_page(
+    config : web::Data<Config>,
+    gateway : web::Data<Gateway>,
+    state : web::Data<State>,
+    checkouts : web::Data<Checkouts>,
+    name : web::Path<String>) -> Result<HttpResponse, Error> {
+    let page = Page::new(&config, util::PaymentType::Donation);
+
+    let fundraiser = match state.fundraiser(&name) {
+        Some(fundraiser) if !fundraiser.archived => fundraiser,
+        _ => return Err(Error::NotFound(page, name.into_inner())),
+    };
+
+    info!("{} amount_raised = {}\n", name.as_str(), fundraiser.amount_raised);
+    render_donate_page(&config, &gateway, &checkouts, page, &fundraiser, None, None).await
+}
+
+//----------------------------------------------------------------------------------------------------
+//----------------------------------------------------------------------------------------------------
+async fn render_donate_page(
+    config : &Config,
+    gateway : &web::Data<Gateway>,
+    checkouts : &Checkouts,
+    page : Page,
+    fundraiser : &Fundraiser,
+    donation : Option<&Donation>,
+    failure : Option<&dyn util::Failure>) -> Result<HttpResponse, Error> {
+    let client_token = gateway::client_token(gateway, config.gateway_timeout()).await
+        .map_err(|error| Error::Payment(page, error))?;
+
+    let no_buyer = util::Payment::default();
+    Ok(util::render(util::checkout_status(failure), &DonatePage{
+        fundraiser,
+        client_token,
+        checkout_token: checkouts.issue(),
+        amount: donation.map_or(String::new(), |donation| format!("{}", donation.amount)),
+        buyer: donation.map_or(&no_buyer, |donation| &donation.payment),
+        message: failure.map_or("", |failure| failure.buyer_message()),
+    }))
 }
 
+//----------------------------------------------------------------------------------------------------
+//----------------------------------------------------------------------------------------------------
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::test;
+    use crate::ledger::LedgerStatus;
+    use crate::testing::{assert_escaped, checkout_service, fundraiser, TestStore};
+
+    //--------------------------------------------------------------------------------------------------
+    //--------------------------------------------------------------------------------------------------
+    #[test]
+    fn fundraisers_page_escapes_fundraiser_fields() {
+        let html = FundraisersPage{ fundraisers: &[fundraiser()] }.render().unwrap();
+        assert_escaped(&html);
+        assert!(html.contains("width: 25%"));
+        assert!(html.contains("$250 of $1000 Raised"));
+        assert!(html.contains("Donate Now"));
+    }
+
+    //--------------------------------------------------------------------------------------------------
+    //--------------------------------------------------------------------------------------------------
+    const DONATION : [(&str, &str); 2] = [("fundraiser_name", "tools"), ("amount", "25.00")];
+
+    //--------------------------------------------------------------------------------------------------
+    //--------------------------------------------------------------------------------------------------
+    #[actix_rt::test]
+    async fn donation_is_charged_and_counted_towards_the_goal() {
+        let store = TestStore::new();
+        let mut service = checkout_service!(store);
+
+        let response = test::call_service(&mut service, store.checkout("/donate/process_donation", &DONATION, "fake-valid-nonce").to_request()).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(store.state.fundraiser("tools").unwrap().amount_raised, Money::from_cents(2500));
+        assert_eq!(store.ledger.entries().unwrap()[0].status, LedgerStatus::Succeeded);
+    }
+
+    //--------------------------------------------------------------------------------------------------
+    //--------------------------------------------------------------------------------------------------
+    #[actix_rt::test]
+    async fn declined_donation_is_not_counted() {
+        let store = TestStore::new();
+        let mut service = checkout_service!(store);
+
+        let response = test::call_service(&mut service, store.checkout("/donate/process_donation", &DONATION, "fake-cvv-mismatch-nonce").to_request()).await;
+        assert_eq!(response.status(), StatusCode::PAYMENT_REQUIRED);
+        assert!(store.state.fundraiser("tools").unwrap().amount_raised.is_zero());
+        assert_eq!(store.ledger.entries().unwrap()[0].status, LedgerStatus::Failed);
+    }
+
+    //--------------------------------------------------------------------------------------------------
+    //--------------------------------------------------------------------------------------------------
+    #[actix_rt::test]
+    async fn timed_out_donation_waits_to_be_resolved() {
+        let store = TestStore::new();
+        let mut service = checkout_service!(store);
+
+        let response = test::call_service(&mut service, store.checkout("/donate/process_donation", &DONATION, "fake-gateway-timeout-nonce").to_request()).await;
+        assert_eq!(response.status(), StatusCode::ACCEPTED);
+        assert!(store.state.fundraiser("tools").unwrap().amount_raised.is_zero());
+
+        let charges = store.pending.charges();
+        assert_eq!(charges.len(), 1);
+        assert!(matches!(&charges.values().next().unwrap().hold, Hold::Donation{ record } if record.fundraiser == "tools"));
+    }
+}
@@ -0,0 +1,374 @@
+use actix_web::error::BlockingError;
+use actix_web::http::StatusCode;
+use actix_web::web;
+use braintree::{Address, Braintree, CreditCard, Environment};
+use log::{debug, error, info};
+use std::collections::HashMap;
+#[cfg(any(test, feature = "fake-gateway"))]
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
+
+use crate::config::{Config, Descriptor};
+use crate::util;
+
+pub type Gateway = Box<dyn PaymentGateway>;
+
+#[derive(Debug, Clone)]
+pub struct Customer {
+    pub payment_method_token : Option<String>,
+}
+
+#[derive(Debug, Clone)]
+pub struct TransactionRequest {
+    pub amount : String,
+    pub payment_method_token : Option<String>,
+    pub custom_fields : HashMap<String, String>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Transaction {
+    pub id : String,
+    pub amount : String,
+}
+
+#[derive(Debug)]
+pub enum Error {
+    Braintree(String),
+    Declined(String),
+    CvvMismatch,
+    AvsMismatch,
+    Validation(String),
+    Unavailable(String),
+    MissingPaymentMethod,
+    Config(String),
+    Timeout,
+    Pending,
+}
+
+//------------------------------------------------------------------------------------------------------
+//------------------------------------------------------------------------------------------------------
+impl Error {
+
+    //--------------------------------------------------------------------------------------------------
+    //--------------------------------------------------------------------------------------------------
+    pub fn status_code(&self) -> StatusCode {
+        match self {
+            Error::Declined(_) | Error::CvvMismatch | Error::AvsMismatch => StatusCode::PAYMENT_REQUIRED,
+            Error::Validation(_) => StatusCode::UNPROCESSABLE_ENTITY,
+            Error::MissingPaymentMethod => StatusCode::BAD_REQUEST,
+            Error::Unavailable(_) => StatusCode::SERVICE_UNAVAILABLE,
+            Error::Timeout => StatusCode::GATEWAY_TIMEOUT,
+            Error::Pending => StatusCode::ACCEPTED,
+            Error::Braintree(_) | Error::Config(_) => StatusCode::BAD_GATEWAY,
+        }
+    }
+
+    //--------------------------------------------------------------------------------------------------
+    //--------------------------------------------------------------------------------------------------
+    pub fn buyer_message(&self) -> &'static str {
+        match self {
+            Error::Declined(_) => "Your card was declined. Please try a different card or contact your bank.",
+            Error::CvvMismatch => "The security code (CVV) did not match your card. Please check it and try again.",
+            Error::AvsMismatch => "The billing address did not match your card. Please check your address and zip code and try again.",
+            Error::Validation(_) => "Some of the payment details were not accepted. Please check your card details and try again.",
+            Error::MissingPaymentMethod => "No payment method was provided. Please enter your card details and try again.",
+            Error::Unavailable(_) | Error::Timeout =>
+                "The payment processor is not responding right now. Please wait a few minutes before trying again, \
+                 your card has not been charged unless you receive a receipt.",
+            Error::Braintree(_) | Error::Config(_) => "We were unable to process your payment. Please try again later.",
+            Error::Pending =>
+                "Your payment is still being confirmed by the card processor. Please do not submit it again, \
+                 you will get a receipt by email once it goes through or we will contact you.",
+        }
+    }
+}
+
+//------------------------------------------------------------------------------------------------------
+//------------------------------------------------------------------------------------------------------
+impl util::Failure for Error {
+    fn status_code(&self) -> StatusCode {
+        Error::status_code(self)
+    }
+
+    fn buyer_message(&self) -> &'static str {
+        Error::buyer_message(self)
+    }
+}
+
+//----------------------------------------------------------------------------------------------------
+//----------------------------------------------------------------------------------------------------
+// an api error carries the failed transaction when the card got as far as the processor, without one
+// the request itself was refused, e.g. an expired or reused nonce, anything else never got an answer
+fn classify(error : braintree::Error) -> Error {
+    match error {
+        braintree::Error::Api(response) => match response.transaction {
+            Some(transaction) => classify_response(
+                transaction.processor_response_code.as_deref(),
+                transaction.gateway_rejection_reason.as_deref(),
+                response.message),
+            None => Error::Validation(response.message),
+        },
+        error => Error::Unavailable(format!("{:?}", error)),
+    }
+}
+
+//----------------------------------------------------------------------------------------------------
+//----------------------------------------------------------------------------------------------------
+// the gateway rejection reasons and processor response codes are the ones braintree documents,
+// 2000-2999 are declines and 3000 means the processor network could not be reached
+fn classify_response(processor_response_code : Option<&str>, gateway_rejection_reason : Option<&str>, message : String) -> Error {
+    match gateway_rejection_reason {
+        Some("cvv") => return Error::CvvMismatch,
+        Some("avs") | Some("avs_and_cvv") => return Error::AvsMismatch,
+        Some(_) => return Error::Declined(message),
+        None => {},
+    }
+
+    match processor_response_code.and_then(|code| code.trim().parse::<u32>().ok()) {
+        Some(2010) => Error::CvvMismatch,
+        Some(2060) => Error::AvsMismatch,
+        Some(2000..=2999) => Error::Declined(message),
+        Some(3000) => Error::Unavailable(message),
+        _ => Error::Braintree(message),
+    }
+}
+
+//----------------------------------------------------------------------------------------------------
+//----------------------------------------------------------------------------------------------------
+pub trait PaymentGateway : Send + Sync {
+    fn create_customer(&self, payment : &util::Payment) -> Result<Customer, Error>;
+
+    fn create_transaction(&self, request : TransactionRequest) -> Result<Transaction, Error>;
+
+    fn generate_client_token(&self) -> Result<String, Error>;
+}
+
+//----------------------------------------------------------------------------------------------------
+//----------------------------------------------------------------------------------------------------
+pub struct BraintreeGateway {
+    braintree : Braintree,
+    descriptor : Descriptor,
+}
+
+//------------------------------------------------------------------------------------------------------
+//------------------------------------------------------------------------------------------------------
+impl BraintreeGateway {
+    pub fn new(braintree : Braintree, descriptor : Descriptor) -> BraintreeGateway {
+        BraintreeGateway { braintree, descriptor }
+    }
+}
+
+//------------------------------------------------------------------------------------------------------
+//------------------------------------------------------------------------------------------------------
+impl PaymentGateway for BraintreeGateway {
+
+    //--------------------------------------------------------------------------------------------------
+    //--------------------------------------------------------------------------------------------------
+    fn create_customer(&self, payment : &util::Payment) -> Result<Customer, Error> {
+        let customer = self.braintree.customer().generate(braintree::Customer{
+            email: Some(payment.email.to_string()),
+            first_name: Some(payment.first_name.to_string()),
+            last_name: Some(payment.last_name.to_string()),
+            company: payment.company_name.clone(),
+            payment_method_nonce: Some(payment.payment_method_nonce.to_string()),
+            credit_card: Some(CreditCard{
+                billing_address: Some(Address{
+                    first_name: Some(payment.first_name.to_string()),
+                    last_name: Some(payment.last_name.to_string()),
+                    locality: Some(payment.city.to_string()),
+                    region: Some(payment.state.to_string()),
+                    street_address: Some(payment.address.to_string()),
+                    ..Default::default()
+                }),
+                ..Default::default()
+            }),
+            ..Default::default()
+        }).map_err(classify)?;
+
+        debug!("customer = {:?}\n", customer);
+        match customer.credit_card {
+            Some(credit_card) => Ok(Customer{ payment_method_token: credit_card.token }),
+            None => Err(Error::MissingPaymentMethod),
+        }
+    }
+
+    //--------------------------------------------------------------------------------------------------
+    //--------------------------------------------------------------------------------------------------
+    fn create_transaction(&self, request : TransactionRequest) -> Result<Transaction, Error> {
+        let transaction = self.braintree.transaction().create(braintree::transaction::Request{
+            amount: request.amount.clone(),
+            payment_method_token: request.payment_method_token,
+            options: Some(braintree::transaction::Options{
+                submit_for_settlement: Some(true),
+                ..Default::default()
+            }),
+            descriptor: Some(braintree::descriptor::Descriptor{
+                name: Some(self.descriptor.name.clone()),
+                url: Some(self.descriptor.url.clone()),
+                phone: Some(self.descriptor.phone.clone()),
+            }),
+            custom_fields: request.custom_fields,
+            ..Default::default()
+        }).map_err(classify)?;
+
+        Ok(Transaction{ id: transaction.id.clone(), amount: request.amount })
+    }
+
+    //--------------------------------------------------------------------------------------------------
+    //--------------------------------------------------------------------------------------------------
+    fn generate_client_token(&self) -> Result<String, Error> {
+        self.braintree.client_token().generate(Default::default())
+            .map(|client_token| client_token.value)
+            .map_err(classify)
+    }
+}
+
+//----------------------------------------------------------------------------------------------------
+//----------------------------------------------------------------------------------------------------
+// offline stand in for braintree, nonces follow the braintree sandbox naming so
+// "fake-valid-nonce" is charged and "fake-processor-declined-visa-nonce" is declined,
+// see FAKE_FAILURES for the other failures that can be triggered, it is only built for tests
+// and with the fake-gateway feature so a production build can't be pointed at it
+#[cfg(any(test, feature = "fake-gateway"))]
+pub struct FakeGateway {
+    next_id : AtomicUsize,
+}
+
+#[cfg(any(test, feature = "fake-gateway"))]
+const FAKE_FAILURES : [(&str, fn(&str) -> Error); 7] = [
+    ("fake-processor-declined", |token| Error::Declined(format!("processor declined {}", token))),
+    ("fake-gateway-rejected-fraud", |token| Error::Declined(format!("gateway rejected fraud {}", token))),
+    ("fake-cvv-mismatch", |_| Error::CvvMismatch),
+    ("fake-avs-mismatch", |_| Error::AvsMismatch),
+    ("fake-luhn-invalid", |token| Error::Validation(format!("credit card number is invalid {}", token))),
+    ("fake-gateway-unavailable", |token| Error::Unavailable(format!("gateway unavailable {}", token))),
+    ("fake-gateway-timeout", |_| Error::Pending),
+];
+
+//------------------------------------------------------------------------------------------------------
+//------------------------------------------------------------------------------------------------------
+#[cfg(any(test, feature = "fake-gateway"))]
+impl FakeGateway {
+    pub fn new() -> FakeGateway {
+        FakeGateway { next_id: AtomicUsize::new(1) }
+    }
+
+    //--------------------------------------------------------------------------------------------------
+    //--------------------------------------------------------------------------------------------------
+    fn next_id(&self) -> usize {
+        self.next_id.fetch_add(1, Ordering::SeqCst)
+    }
+}
+
+//------------------------------------------------------------------------------------------------------
+//------------------------------------------------------------------------------------------------------
+#[cfg(any(test, feature = "fake-gateway"))]
+impl PaymentGateway for FakeGateway {
+
+    //--------------------------------------------------------------------------------------------------
+    //--------------------------------------------------------------------------------------------------
+    fn create_customer(&self, payment : &util::Payment) -> Result<Customer, Error> {
+        if payment.payment_method_nonce.is_empty() {
+            return Err(Error::MissingPaymentMethod);
+        }
+
+        Ok(Customer{ payment_method_token: Some(payment.payment_method_nonce.to_string()) })
+    }
+
+    //--------------------------------------------------------------------------------------------------
+    //--------------------------------------------------------------------------------------------------
+    fn create_transaction(&self, request : TransactionRequest) -> Result<Transaction, Error> {
+        let token = request.payment_method_token.ok_or(Error::MissingPaymentMethod)?;
+
+        for (prefix, failure) in FAKE_FAILURES.iter() {
+            if token.starts_with(prefix) {
+                return Err(failure(&token));
+            }
+        }
+
+        let transaction = Transaction{ id: format!("fake{:06}", self.next_id()), amount: request.amount };
+        info!("fake gateway charged {} as transaction {}\n", transaction.amount, transaction.id);
+        Ok(transaction)
+    }
+
+    //--------------------------------------------------------------------------------------------------
+    //--------------------------------------------------------------------------------------------------
+    fn generate_client_token(&self) -> Result<String, Error> {
+        Ok("fake-client-token".to_string())
+    }
+}
+
+//----------------------------------------------------------------------------------------------------
+//----------------------------------------------------------------------------------------------------
+pub async fn call<T, F>(gateway : &web::Data<Gateway>, timeout : Duration, request : F) -> Result<T, Error>
+    where T : Send + 'static, F : FnOnce(&dyn PaymentGateway) -> Result<T, Error> + Send + 'static {
+
+    //the braintree client blocks so run it on the thread pool rather than stalling the worker
+    let gateway = gateway.clone();
+    let blocking = web::block(move || request(&**gateway));
+
+    match actix_web::rt::time::timeout(timeout, blocking).await {
+        Ok(Ok(value)) => Ok(value),
+        Ok(Err(BlockingError::Error(error))) => Err(error),
+        Ok(Err(BlockingError::Canceled)) => Err(Error::Unavailable("gateway call was canceled".to_string())),
+        Err(_) => {
+            //the call keeps running on the pool so a timed out charge may still go through
+            error!("Error: payment gateway call timed out after {:?}\n", timeout);
+            Err(Error::Timeout)
+        },
+    }
+}
+
+//----------------------------------------------------------------------------------------------------
+//----------------------------------------------------------------------------------------------------
+pub async fn client_token(gateway : &web::Data<Gateway>, timeout : Duration) -> Result<String, Error> {
+    call(gateway, timeout, |gateway| gateway.generate_client_token()).await
+}
+
+//----------------------------------------------------------------------------------------------------
+//----------------------------------------------------------------------------------------------------
+#[cfg(any(test, feature = "fake-gateway"))]
+fn fake_gateway() -> Result<Box<dyn PaymentGateway>, Error> {
+    info!("using fake payment gateway");
+    Ok(Box::new(FakeGateway::new()))
+}
+
+//----------------------------------------------------------------------------------------------------
+//----------------------------------------------------------------------------------------------------
+#[cfg(not(any(test, feature = "fake-gateway")))]
+fn fake_gateway() -> Result<Box<dyn PaymentGateway>, Error> {
+    Err(Error::Config("ENVIRONMENT fake needs a build with the fake-gateway feature".to_string()))
+}
+
+//----------------------------------------------------------------------------------------------------
+//----------------------------------------------------------------------------------------------------
+pub fn from_config(config : &Config) -> Result<Box<dyn PaymentGateway>, Error> {
+    //the keys were checked when the config was loaded, only whether there are any is left
+    let credentials = &config.credentials;
+    let environment = credentials.environment.as_deref()
+        .ok_or_else(|| Error::Config("environment variable ENVIRONMENT is not defined".to_string()))?;
+
+    let gateway : Box<dyn PaymentGateway> = if environment == "fake" {
+        fake_gateway()?
+    } else {
+        let braintree_environment = Environment::from_str(environment)
+            .map_err(|error| Error::Config(format!("ENVIRONMENT {} is not a braintree environment {:?}", environment, error)))?;
+
+        Box::new(BraintreeGateway::new(
+            Braintree::new(
+                braintree_environment,
+                credentials.merchant_id.clone(),
+                credentials.public_key.clone(),
+                credentials.private_key.clone(),
+                ),
+            config.descriptor.clone()))
+    };
+
+    //a client token needs valid credentials so this catches bad keys before the first buyer does
+    if config.verify_gateway {
+        info!("checking payment gateway credentials");
+        gateway.generate_client_token()?;
+    }
+
+    Ok(gateway)
+}
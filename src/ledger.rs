@@ -0,0 +1,146 @@
+use chrono::{DateTime, Utc};
+use log::{error};
+use serde::{Serialize, Deserialize};
+use std::io::{self, Write};
+use std::sync::{Arc, Mutex};
+
+use crate::gateway::{self, Transaction};
+use crate::money::Money;
+use crate::tax::TaxQuote;
+use crate::storage::{self, Storage};
+use crate::util;
+
+const CSV_HEADER : &str = "timestamp,payment_type,description,amount,first_name,last_name,email,transaction_id,status,error,tax,tax_exempt_certificate";
+
+//----------------------------------------------------------------------------------------------------
+//----------------------------------------------------------------------------------------------------
+#[derive(Deserialize,Debug, Serialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum LedgerStatus {
+    Succeeded,
+    Failed,
+    Pending,
+}
+
+//----------------------------------------------------------------------------------------------------
+//----------------------------------------------------------------------------------------------------
+#[derive(Deserialize,Debug, Serialize, Clone)]
+pub struct LedgerEntry {
+    pub timestamp : DateTime<Utc>,
+    pub payment_type : String,
+    pub description : String,
+    pub amount : Money,
+    pub first_name : String,
+    pub last_name : String,
+    pub email : String,
+    pub transaction_id : Option<String>,
+    pub status : LedgerStatus,
+    pub error : Option<String>,
+    #[serde(default)]
+    pub tax : Option<TaxQuote>,
+}
+
+//----------------------------------------------------------------------------------------------------
+//----------------------------------------------------------------------------------------------------
+pub struct Ledger {
+    storage : Arc<dyn Storage>,
+    lock : Mutex<()>,
+}
+
+//----------------------------------------------------------------------------------------------------
+//----------------------------------------------------------------------------------------------------
+pub fn csv_field(field : &str) -> String {
+    format!("\"{}\"", field.replace('"', "\"\""))
+}
+
+//------------------------------------------------------------------------------------------------------
+//------------------------------------------------------------------------------------------------------
+impl LedgerEntry {
+
+    //--------------------------------------------------------------------------------------------------
+    //--------------------------------------------------------------------------------------------------
+    pub fn new(
+        payment : &util::Payment,
+        sale : &util::Sale,
+        result : &Result<Transaction, gateway::Error>) -> LedgerEntry {
+
+        LedgerEntry {
+            timestamp: Utc::now(),
+            payment_type: sale.payment_type.as_str().to_string(),
+            description: sale.description.to_string(),
+            amount: sale.amount,
+            first_name: payment.first_name.clone(),
+            last_name: payment.last_name.clone(),
+            email: payment.email.clone(),
+            transaction_id: result.as_ref().ok().map(|transaction| transaction.id.clone()),
+            status: match result {
+                Ok(_) => LedgerStatus::Succeeded,
+                //the charge may have gone through, the treasurer settles it against braintree
+                Err(gateway::Error::Pending) => LedgerStatus::Pending,
+                Err(_) => LedgerStatus::Failed,
+            },
+            error: result.as_ref().err().map(|error| format!("{:?}", error)),
+            tax: sale.tax.clone(),
+        }
+    }
+
+    //--------------------------------------------------------------------------------------------------
+    //--------------------------------------------------------------------------------------------------
+    pub fn to_csv(&self) -> String {
+        [
+            self.timestamp.to_rfc3339(),
+            self.payment_type.clone(),
+            self.description.clone(),
+            self.amount.to_string(),
+            self.first_name.clone(),
+            self.last_name.clone(),
+            self.email.clone(),
+            self.transaction_id.clone().unwrap_or_default(),
+            format!("{:?}", self.status),
+            self.error.clone().unwrap_or_default(),
+            self.tax.as_ref().map_or(String::new(), |tax| tax.total().map_or_else(|error| error.to_string(), |total| total.to_string())),
+            self.tax.as_ref().and_then(|tax| tax.certificate.clone()).unwrap_or_default(),
+        ].iter().map(|field| csv_field(field)).collect::<Vec<String>>().join(",")
+    }
+}
+
+//------------------------------------------------------------------------------------------------------
+//------------------------------------------------------------------------------------------------------
+impl Ledger {
+
+    //--------------------------------------------------------------------------------------------------
+    //--------------------------------------------------------------------------------------------------
+    pub fn new(storage : Arc<dyn Storage>) -> Ledger {
+        Ledger { storage, lock: Mutex::new(()) }
+    }
+
+    //--------------------------------------------------------------------------------------------------
+    //--------------------------------------------------------------------------------------------------
+    pub fn record(&self, entry : &LedgerEntry) {
+        let _lock = self.lock.lock().unwrap();
+
+        //a missing ledger line is bad but the buyer has already been charged at this point
+        if let Err(error) = self.storage.append_ledger(entry) {
+            error!("Error: unable to write ledger entry {:?} {:?}\n", entry, error);
+        }
+    }
+
+    //--------------------------------------------------------------------------------------------------
+    //--------------------------------------------------------------------------------------------------
+    pub fn entries(&self) -> Result<Vec<LedgerEntry>, storage::Error> {
+        let _lock = self.lock.lock().unwrap();
+        self.storage.load_ledger()
+    }
+
+    //--------------------------------------------------------------------------------------------------
+    //--------------------------------------------------------------------------------------------------
+    pub fn write_csv<W : Write>(&self, writer : &mut W) -> io::Result<()> {
+        let entries = self.entries().map_err(|error| io::Error::new(io::ErrorKind::Other, format!("{:?}", error)))?;
+
+        writeln!(writer, "{}", CSV_HEADER)?;
+        for entry in entries.iter() {
+            writeln!(writer, "{}", entry.to_csv())?;
+        }
+        Ok(())
+    }
+}
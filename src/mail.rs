@@ -0,0 +1,349 @@
+use askama::Template;
+use lettre::address::Address;
+use lettre::message::Mailbox;
+use lettre::message::header::ContentType;
+use lettre::transport::smtp::authentication::Credentials;
+use lettre::{FileTransport, Message, SmtpTransport, Transport};
+use log::{error, info};
+use std::path::Path;
+use std::sync::{mpsc, Mutex};
+use std::thread;
+
+use crate::acknowledgement::Acknowledgement;
+use crate::config::MailConfig;
+use crate::gateway::Transaction;
+use crate::money::Money;
+use crate::statement::DonorStatement;
+use crate::util;
+
+#[derive(Debug)]
+pub enum Error {
+    Address(String),
+    Template(askama::Error),
+    Message(lettre::error::Error),
+    Transport(String),
+    Config(String),
+}
+
+//----------------------------------------------------------------------------------------------------
+//----------------------------------------------------------------------------------------------------
+#[derive(Debug, Clone)]
+pub struct Receipt {
+    pub payment_type : String,
+    pub description : String,
+    pub dates : String,
+    pub amount : Money,
+    pub tax : Money,
+    pub transaction_id : String,
+    pub first_name : String,
+    pub last_name : String,
+    pub email : String,
+}
+
+#[derive(Template)]
+#[template(path = "receipt.txt")]
+struct ReceiptEmail<'a> {
+    receipt : &'a Receipt,
+}
+
+#[derive(Template)]
+#[template(path = "organizer_notification.txt")]
+struct OrganizerEmail<'a> {
+    receipt : &'a Receipt,
+}
+
+enum Outgoing {
+    Smtp(SmtpTransport),
+    Outbox(FileTransport),
+    Disabled,
+}
+
+//plain text replacements for the built in emails, read from mail.templates at startup
+#[derive(Default)]
+struct Templates {
+    receipt : Option<String>,
+    organizer_notification : Option<String>,
+}
+
+struct Envelope {
+    message : Message,
+    about : String,
+}
+
+//----------------------------------------------------------------------------------------------------
+//----------------------------------------------------------------------------------------------------
+pub struct Mailer {
+    from : Mailbox,
+    organizers : Vec<Mailbox>,
+    templates : Templates,
+    //smtp blocks so the messages are handed to a worker thread rather than sent from a request
+    queue : Mutex<Option<mpsc::Sender<Envelope>>>,
+    worker : Mutex<Option<thread::JoinHandle<()>>>,
+}
+
+//------------------------------------------------------------------------------------------------------
+//------------------------------------------------------------------------------------------------------
+impl Receipt {
+
+    //--------------------------------------------------------------------------------------------------
+    //--------------------------------------------------------------------------------------------------
+    pub fn new(
+        payment : &util::Payment,
+        payment_type : &util::PaymentType,
+        description : &str,
+        amount : Money,
+        transaction : &Transaction) -> Receipt {
+
+        Receipt {
+            payment_type: payment_type.as_str().to_string(),
+            description: description.to_string(),
+            dates: String::new(),
+            amount,
+            tax: Money::zero(),
+            transaction_id: transaction.id.clone(),
+            first_name: payment.first_name.clone(),
+            last_name: payment.last_name.clone(),
+            email: payment.email.clone(),
+        }
+    }
+
+    //--------------------------------------------------------------------------------------------------
+    //--------------------------------------------------------------------------------------------------
+    fn field(&self, name : &str) -> Option<String> {
+        match name {
+            "receipt.payment_type" => Some(self.payment_type.clone()),
+            "receipt.description" => Some(self.description.clone()),
+            "receipt.dates" => Some(self.dates.clone()),
+            "receipt.amount" => Some(self.amount.to_string()),
+            "receipt.tax" => Some(self.tax.to_string()),
+            "receipt.transaction_id" => Some(self.transaction_id.clone()),
+            "receipt.first_name" => Some(self.first_name.clone()),
+            "receipt.last_name" => Some(self.last_name.clone()),
+            "receipt.email" => Some(self.email.clone()),
+            _ => None,
+        }
+    }
+}
+
+//------------------------------------------------------------------------------------------------------
+//------------------------------------------------------------------------------------------------------
+impl Mailer {
+
+    //--------------------------------------------------------------------------------------------------
+    //--------------------------------------------------------------------------------------------------
+    pub fn send_receipt(&self, receipt : &Receipt) {
+        //the payment has already gone through so a failed email is only logged
+        let name = format!("{} {}", receipt.first_name, receipt.last_name);
+        let about = format!("receipt for {} to {}", receipt.transaction_id, receipt.email);
+        let result = recipient(&name, &receipt.email).and_then(|to| {
+            let body = match self.templates.receipt.as_ref() {
+                Some(template) => fill(template, receipt),
+                None => ReceiptEmail{ receipt }.render().map_err(Error::Template)?,
+            };
+            self.send(to, &format!("Your SBHX {} receipt", receipt.payment_type), ContentType::TEXT_PLAIN, body, about)
+        });
+
+        if let Err(error) = result {
+            error!("Error: unable to send receipt for {} to {} {:?}\n", receipt.transaction_id, receipt.email, error);
+        }
+
+        for organizer in self.organizers.iter() {
+            let body = match self.templates.organizer_notification.as_ref() {
+                Some(template) => Ok(fill(template, receipt)),
+                None => OrganizerEmail{ receipt }.render().map_err(Error::Template),
+            };
+            let result = body.and_then(|body| self.send(
+                organizer.clone(),
+                &format!("SBHX {}: {} ${}", receipt.payment_type, receipt.description, receipt.amount),
+                ContentType::TEXT_PLAIN,
+                body,
+                format!("notice of {} to {}", receipt.transaction_id, organizer)));
+
+            if let Err(error) = result {
+                error!("Error: unable to notify {} of {} {:?}\n", organizer, receipt.transaction_id, error);
+            }
+        }
+    }
+
+    //--------------------------------------------------------------------------------------------------
+    //--------------------------------------------------------------------------------------------------
+    pub fn send_acknowledgement(&self, acknowledgement : &Acknowledgement) {
+        let result = recipient(&acknowledgement.donor_name, &acknowledgement.email).and_then(|to| {
+            let body = acknowledgement.render().map_err(Error::Template)?;
+            self.send(
+                to,
+                &format!("Your {} donation acknowledgement", acknowledgement.organization.name),
+                ContentType::TEXT_HTML,
+                body,
+                format!("acknowledgement for {} to {}", acknowledgement.transaction_id, acknowledgement.email))
+        });
+
+        if let Err(error) = result {
+            error!("Error: unable to send acknowledgement for {} to {} {:?}\n", acknowledgement.transaction_id, acknowledgement.email, error);
+        }
+    }
+
+    //--------------------------------------------------------------------------------------------------
+    //--------------------------------------------------------------------------------------------------
+    pub fn send_statement(&self, statement : &DonorStatement) {
+        let result = recipient(&statement.donor_name, &statement.email).and_then(|to| {
+            let body = statement.render().map_err(Error::Template)?;
+            self.send(
+                to,
+                &format!("Your {} {} donation statement", statement.year, statement.organization.name),
+                ContentType::TEXT_HTML,
+                body,
+                format!("{} statement to {}", statement.year, statement.email))
+        });
+
+        if let Err(error) = result {
+            error!("Error: unable to send {} statement to {} {:?}\n", statement.year, statement.email, error);
+        }
+    }
+
+    //--------------------------------------------------------------------------------------------------
+    //--------------------------------------------------------------------------------------------------
+    //waits for the queued emails to go out, only needed by commands that exit straight after sending
+    pub fn finish(&self) {
+        self.queue.lock().unwrap().take();
+        if let Some(worker) = self.worker.lock().unwrap().take() {
+            if worker.join().is_err() {
+                error!("Error: the mail worker stopped unexpectedly\n");
+            }
+        }
+    }
+
+    //--------------------------------------------------------------------------------------------------
+    //--------------------------------------------------------------------------------------------------
+    fn send(&self, to : Mailbox, subject : &str, content_type : ContentType, body : String, about : String) -> Result<(), Error> {
+        let message = Message::builder()
+            .from(self.from.clone())
+            .to(to)
+            .subject(subject)
+            .header(content_type)
+            .body(body)
+            .map_err(Error::Message)?;
+
+        match self.queue.lock().unwrap().as_ref() {
+            Some(queue) => queue.send(Envelope{ message, about }).map_err(|_| Error::Transport("the mail worker has stopped".to_string())),
+            None => Err(Error::Transport("the mailer has been finished".to_string())),
+        }
+    }
+}
+
+//----------------------------------------------------------------------------------------------------
+//----------------------------------------------------------------------------------------------------
+fn deliver(outgoing : Outgoing, queue : mpsc::Receiver<Envelope>) {
+    for envelope in queue {
+        let result = match &outgoing {
+            Outgoing::Smtp(transport) => transport.send(&envelope.message).map(|_| ()).map_err(|error| Error::Transport(format!("{:?}", error))),
+            Outgoing::Outbox(transport) => transport.send(&envelope.message).map(|_| ()).map_err(|error| Error::Transport(format!("{:?}", error))),
+            Outgoing::Disabled => {
+                info!("{} not sent, no transport configured\n", envelope.about);
+                Ok(())
+            },
+        };
+
+        if let Err(error) = result {
+            error!("Error: unable to send {} {:?}\n", envelope.about, error);
+        }
+    }
+}
+
+//----------------------------------------------------------------------------------------------------
+//----------------------------------------------------------------------------------------------------
+//fills in the {{ receipt.field }} placeholders of a template from mail.templates in one pass
+fn fill(template : &str, receipt : &Receipt) -> String {
+    let mut filled = String::with_capacity(template.len());
+    let mut rest = template;
+
+    while let Some(start) = rest.find("{{") {
+        let end = match rest[start..].find("}}") {
+            Some(end) => start + end,
+            None => break,
+        };
+        filled.push_str(&rest[..start]);
+        match receipt.field(rest[start + 2..end].trim()) {
+            Some(value) => filled.push_str(&value),
+            None => filled.push_str(&rest[start..end + 2]),
+        }
+        rest = &rest[end + 2..];
+    }
+
+    filled.push_str(rest);
+    filled
+}
+
+//----------------------------------------------------------------------------------------------------
+//----------------------------------------------------------------------------------------------------
+fn load_template(directory : &str, file_name : &str) -> Result<Option<String>, Error> {
+    let path = Path::new(directory).join(file_name);
+    if !path.exists() {
+        return Ok(None);
+    }
+
+    info!("using email template {}\n", path.display());
+    std::fs::read_to_string(&path)
+        .map(Some)
+        .map_err(|error| Error::Config(format!("unable to read {}: {}", path.display(), error)))
+}
+
+//----------------------------------------------------------------------------------------------------
+//----------------------------------------------------------------------------------------------------
+fn recipient(name : &str, email : &str) -> Result<Mailbox, Error> {
+    let address = email.trim().parse::<Address>().map_err(|error| Error::Address(format!("{} {:?}", email, error)))?;
+    Ok(Mailbox::new(Some(name.to_string()), address))
+}
+
+//----------------------------------------------------------------------------------------------------
+//----------------------------------------------------------------------------------------------------
+fn parse_mailbox(mailbox : &str) -> Result<Mailbox, Error> {
+    mailbox.trim().parse().map_err(|error| Error::Address(format!("{} {:?}", mailbox, error)))
+}
+
+//----------------------------------------------------------------------------------------------------
+//----------------------------------------------------------------------------------------------------
+pub fn from_config(config : &MailConfig) -> Result<Mailer, Error> {
+    let from = parse_mailbox(&config.from)?;
+
+    let organizers = config.organizers.iter()
+        .map(|organizer| parse_mailbox(organizer))
+        .collect::<Result<Vec<Mailbox>, Error>>()?;
+
+    let templates = match config.templates.as_ref() {
+        Some(directory) => Templates {
+            receipt: load_template(directory, "receipt.txt")?,
+            organizer_notification: load_template(directory, "organizer_notification.txt")?,
+        },
+        None => Templates::default(),
+    };
+
+    //an outbox directory takes priority so development never talks to a real mail server
+    let outgoing = if let Some(outbox) = config.outbox.as_ref() {
+        info!("writing emails to {}", outbox);
+        std::fs::create_dir_all(outbox).map_err(|error| Error::Config(format!("unable to create mail outbox {}: {}", outbox, error)))?;
+        Outgoing::Outbox(FileTransport::new(outbox))
+    } else if let Some(host) = config.smtp_host.as_ref() {
+        info!("sending emails through {}", host);
+        let mut builder = SmtpTransport::relay(host).map_err(|error| Error::Config(format!("invalid mail.smtp_host {}: {:?}", host, error)))?;
+        if let Some(port) = config.smtp_port {
+            builder = builder.port(port);
+        }
+        //the password is a secret so it only ever comes from the environment
+        if let (Some(user), Ok(password)) = (config.smtp_user.as_ref(), std::env::var("SMTP_PASSWORD")) {
+            builder = builder.credentials(Credentials::new(user.clone(), password));
+        }
+        Outgoing::Smtp(builder.build())
+    } else {
+        info!("no mail.smtp_host or mail.outbox set, emails will not be sent");
+        Outgoing::Disabled
+    };
+
+    let (queue, receiver) = mpsc::channel();
+    let worker = thread::Builder::new()
+        .name("mail".to_string())
+        .spawn(move || deliver(outgoing, receiver))
+        .map_err(|error| Error::Config(format!("unable to start the mail worker: {}", error)))?;
+
+    Ok(Mailer { from, organizers, templates, queue: Mutex::new(Some(queue)), worker: Mutex::new(Some(worker)) })
+}
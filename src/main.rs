@@ -1,67 +1,229 @@
 use actix_web::{web, App, HttpServer};
 use actix_web::middleware::Logger;
-use braintree::{Braintree, Environment};
 use log::{info};
-use std::collections::BTreeMap;
-use std::sync::{Mutex};
 
 pub mod util;
+pub mod acknowledgement;
+pub mod admin;
+pub mod cart;
+pub mod checkout;
+pub mod config;
+pub mod error;
+pub mod gateway;
+pub mod ledger;
+pub mod mail;
+pub mod membership;
+pub mod money;
+pub mod pending;
+pub mod persist;
+pub mod promo;
 pub mod quote;
 pub mod fundraise;
+pub mod state;
+pub mod statement;
+pub mod storage;
+pub mod tax;
+#[cfg(feature = "sqlite")]
+pub mod sqlite;
 pub mod store;
+#[cfg(test)]
+mod testing;
 //----------------------------------------------------------------------------------------------------
 //----------------------------------------------------------------------------------------------------
+//----------------------------------------------------------------------------------------------------
+//----------------------------------------------------------------------------------------------------
+//the command line tools report bad arguments and unreadable data instead of panicking
+fn usage_error(usage : &str, problem : &str) -> ! {
+    eprintln!("Error: {}\n{}", problem, usage);
+    std::process::exit(2);
+}
+
+//----------------------------------------------------------------------------------------------------
+//----------------------------------------------------------------------------------------------------
+fn or_exit<T, E : std::fmt::Debug>(result : Result<T, E>, problem : &str) -> T {
+    match result {
+        Ok(value) => value,
+        Err(error) => {
+            eprintln!("Error: {} {:?}", problem, error);
+            std::process::exit(1);
+        },
+    }
+}
+
 //----------------------------------------------------------------------------------------------------
 //----------------------------------------------------------------------------------------------------
 #[actix_web::main]
 async fn main() -> std::io::Result<()> {
     env_logger::init();
-    info!("setting up braintree");
 
-    info!("starting server on 7777!");
+    let config = match config::Config::load() {
+        Ok(config) => config,
+        Err(error) => {
+            eprintln!("Error: {}", error);
+            std::process::exit(1);
+        },
+    };
 
-    HttpServer::new(move || {
-        let braintree = web::Data::new(Mutex::new(Braintree::new(
-                    Environment::from_str(&std::env::var("ENVIRONMENT").expect("environment variable ENVIRONMENT is not defined")).unwrap(),
-                    std::env::var("MERCHANT_ID").expect("environment variable MERCHANT_ID is not defined"),
-                    std::env::var("PUBLIC_KEY").expect("environment variable PUBLIC_KEY is not defined"),
-                    std::env::var("PRIVATE_KEY").expect("environment variable PRIVATE_KEY is not defined"),
-                    )));
+    let args : Vec<String> = std::env::args().collect();
+
+    let storage = match storage::from_config(&config) {
+        Ok(storage) => storage,
+        Err(error) => {
+            eprintln!("Error: unable to open database {:?}", error);
+            std::process::exit(1);
+        },
+    };
 
-        let inventory = util::get_file::<BTreeMap<String, store::Item>>("inventory.json".to_string());
+    if args.len() == 2 && args[1] == "ledger" {
+        let ledger = ledger::Ledger::new(storage);
+        return ledger.write_csv(&mut std::io::stdout());
+    }
 
-        let item_names :Vec<String> = inventory.keys().map(|x| String::clone(x)).collect();
+    //statements <year> <directory> [--email]
+    if args.len() >= 2 && args[1] == "statements" {
+        let usage = "usage: statements <year> <directory> [--email]";
+        if !(args.len() == 4 || (args.len() == 5 && args[4] == "--email")) {
+            usage_error(usage, "wrong arguments");
+        }
+        let year = match args[2].parse::<i32>() {
+            Ok(year) => year,
+            Err(_) => usage_error(usage, &format!("{} is not a year", args[2])),
+        };
+        let entries = or_exit(ledger::Ledger::new(storage.clone()).entries(), "unable to read ledger");
+        let fundraisers = or_exit(storage.load_fundraisers(), "unable to load fundraisers");
+        let statements = or_exit(statement::statements(&entries, &fundraisers, &config.organization, year, &config.timezone), "unable to add up donations");
 
-        let fundraisers = util::get_file::<BTreeMap<String, fundraise::Fundraiser>>("fundraising_goals.json".to_string());
+        let mailer = if args.len() == 5 {
+            match mail::from_config(&config.mail) {
+                Ok(mailer) => Some(mailer),
+                Err(error) => {
+                    eprintln!("Error: invalid mail configuration {:?}", error);
+                    std::process::exit(1);
+                },
+            }
+        } else {
+            None
+        };
+        let result = statement::write_statements(&statements, &args[3], mailer.as_ref());
+        if let Some(mailer) = mailer {
+            mailer.finish();
+        }
+        return result;
+    }
 
-        let fundraiser_names : Vec<String> = fundraisers.keys().map(|x| String::clone(x)).collect();
+    //tax-report <2026 | 2026-Q3 | 2026-07>
+    if args.len() >= 2 && args[1] == "tax-report" {
+        let usage = "usage: tax-report <2026 | 2026-Q3 | 2026-07>";
+        if args.len() != 3 {
+            usage_error(usage, "wrong arguments");
+        }
+        let period = match args[2].parse::<tax::Period>() {
+            Ok(period) => period,
+            Err(error) => usage_error(usage, &error),
+        };
+        let entries = or_exit(ledger::Ledger::new(storage).entries(), "unable to read ledger");
+        return tax::write_liability(&entries, &period, &config.timezone, &mut std::io::stdout());
+    }
+
+    #[cfg(feature = "sqlite")]
+    {
+        if args.len() >= 2 && args[1] == "import" {
+            if args.len() != 3 {
+                usage_error("usage: import <database>", "wrong arguments");
+            }
+            info!("importing json files into {}", args[2]);
+            or_exit(sqlite::SqliteStorage::open(&args[2])
+                .and_then(|database| database.import(&storage::json_storage(&config))), "unable to import json files");
+            return Ok(());
+        }
+    }
 
-        let mut app = App::new()
+    let address = format!("{}:{}", config.bind_address, config.port);
+    info!("starting server on {}!", address);
+
+    let invoices = web::Data::new(quote::InvoiceRepository::new(storage.clone(), config.tax.clone()));
+
+    let ledger = web::Data::new(ledger::Ledger::new(storage.clone()));
+
+    let mailer = match mail::from_config(&config.mail) {
+        Ok(mailer) => web::Data::new(mailer),
+        Err(error) => {
+            eprintln!("Error: invalid mail configuration {:?}", error);
+            std::process::exit(1);
+        },
+    };
+
+    let checkouts = web::Data::new(checkout::Checkouts::new());
+
+    let pending = match pending::PendingCharges::load(storage.clone()) {
+        Ok(pending) => web::Data::new(pending),
+        Err(error) => {
+            eprintln!("Error: unable to load pending charges {:?}", error);
+            std::process::exit(1);
+        },
+    };
+
+    let state = match state::State::load(storage) {
+        Ok(state) => web::Data::new(state),
+        Err(error) => {
+            eprintln!("Error: unable to load inventory and fundraisers {:?}", error);
+            std::process::exit(1);
+        },
+    };
+    state.restore_holds(&pending.charges());
+
+    info!("setting up payment gateway");
+    let gateway : web::Data<gateway::Gateway> = match gateway::from_config(&config) {
+        Ok(gateway) => web::Data::new(gateway),
+        Err(error) => {
+            eprintln!("Error: unable to set up payment gateway {:?}", error);
+            std::process::exit(1);
+        },
+    };
+
+    let config = web::Data::new(config);
+
+    HttpServer::new(move || {
+        App::new()
             .wrap(Logger::new("%a \"%r\" %s %b \"%{Referer}i\" \"%{User-Agent}i\" %T"))
-            .app_data(braintree)
+            .app_data(checkouts.clone())
+            .app_data(config.clone())
+            .app_data(gateway.clone())
+            .app_data(invoices.clone())
+            .app_data(ledger.clone())
+            .app_data(mailer.clone())
+            .app_data(pending.clone())
+            .app_data(state.clone())
             .service(actix_files::Files::new("/assets", "assets").show_files_listing())
             .service(actix_files::Files::new("/donate/assets", "assets").show_files_listing())
             .service(actix_files::Files::new("/store/assets", "assets").show_files_listing())
             .route("/store/", web::get().to(store::store))
             .route("/store/signup", web::post().to(store::course_signup))
+            .route("/store/cart", web::get().to(cart::cart_page))
+            .route("/store/cart/add", web::post().to(cart::add_to_cart))
+            .route("/store/cart/update", web::post().to(cart::update_cart))
+            .route("/store/cart/checkout", web::post().to(cart::checkout_cart))
             .route("/quote/process_invoice", web::post().to(quote::process_invoice))
             .route("/quote/invoice", web::get().to(quote::invoice))
             .route("/donate/process_donation", web::post().to(fundraise::process_donation))
             .route("/donate/fundraise", web::get().to(fundraise::fundraisers_page))
-            .route("/donate/", web::get().to(fundraise::fundraisers_page));
-
-        for fundraiser_name in fundraiser_names.into_iter() {
-            app = app.route(format!("/donate/{}", fundraiser_name).as_str(), web::get().to(
-                    move |braintree| fundraise::fundraiser_page(braintree, fundraiser_name.clone())));
-        }
-
-        for item_name in item_names.into_iter() {
-            app = app.route(format!("/store/{}", item_name).as_str(), web::get().to(
-                    move |braintree| store::item_page(braintree, item_name.clone())));
-        }
-        app
+            .route("/donate/", web::get().to(fundraise::fundraisers_page))
+            .route("/donate/{name}", web::get().to(fundraise::fundraiser_page))
+            .route("/store/{item}", web::get().to(store::item_page))
+            .service(web::scope("/admin")
+                .route("/", web::get().to(admin::index))
+                .route("/ledger.csv", web::get().to(admin::ledger_csv))
+                .route("/pending/{id}", web::post().to(admin::resolve_pending))
+                .route("/items", web::post().to(admin::save_item))
+                .route("/items/{key}", web::get().to(admin::edit_item))
+                .route("/items/{key}/{action}", web::post().to(admin::item_action))
+                .route("/fundraisers", web::post().to(admin::save_fundraiser))
+                .route("/fundraisers/{key}", web::get().to(admin::edit_fundraiser))
+                .route("/fundraisers/{key}/{action}", web::post().to(admin::fundraiser_action))
+                .route("/promo_codes", web::post().to(admin::save_promo_code))
+                .route("/promo_codes/{code}", web::get().to(admin::edit_promo_code)))
     })
-    .bind("0.0.0.0:7777")?
+    .bind(address)?
         .run()
         .await
 }
@@ -0,0 +1,168 @@
+use actix_web::http::StatusCode;
+use chrono::{NaiveDate, Utc};
+use log::{info};
+use serde::{Serialize, Deserialize};
+use std::collections::BTreeMap;
+use std::io;
+
+use crate::util;
+
+//----------------------------------------------------------------------------------------------------
+//----------------------------------------------------------------------------------------------------
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum Tier {
+    #[default]
+    Public,
+    Member,
+}
+
+//----------------------------------------------------------------------------------------------------
+//----------------------------------------------------------------------------------------------------
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Member {
+    pub name : String,
+    #[serde(default)]
+    pub expires : Option<NaiveDate>,
+}
+
+//----------------------------------------------------------------------------------------------------
+//----------------------------------------------------------------------------------------------------
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct Members {
+    pub file : String,
+    #[serde(skip)]
+    pub members : BTreeMap<String, Member>,
+    #[serde(skip)]
+    pub code : Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum MembershipError {
+    InvalidCode,
+}
+
+//------------------------------------------------------------------------------------------------------
+//------------------------------------------------------------------------------------------------------
+impl Tier {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Tier::Public => "public",
+            Tier::Member => "member",
+        }
+    }
+}
+
+//------------------------------------------------------------------------------------------------------
+//------------------------------------------------------------------------------------------------------
+impl Default for Members {
+    fn default() -> Members {
+        Members {
+            file: "members.json".to_string(),
+            members: BTreeMap::new(),
+            code: None,
+        }
+    }
+}
+
+//------------------------------------------------------------------------------------------------------
+//------------------------------------------------------------------------------------------------------
+impl Members {
+
+    //--------------------------------------------------------------------------------------------------
+    //--------------------------------------------------------------------------------------------------
+    pub fn load(&mut self) -> io::Result<()> {
+        //the member code is handed out at the space so like the passwords it only comes from the environment
+        self.code = std::env::var("MEMBER_CODE").ok()
+            .map(|code| code.trim().to_string())
+            .filter(|code| !code.is_empty());
+
+        let contents = match std::fs::read_to_string(&self.file) {
+            Ok(contents) => contents,
+            Err(error) if error.kind() == io::ErrorKind::NotFound => return Ok(()),
+            Err(error) => return Err(error),
+        };
+
+        let members : BTreeMap<String, Member> = serde_json::from_str(&contents).map_err(io::Error::from)?;
+        self.members = members.into_iter()
+            .map(|(email, member)| (email.trim().to_lowercase(), member))
+            .collect();
+
+        info!("loaded {} members from {}", self.members.len(), self.file);
+        Ok(())
+    }
+
+    //--------------------------------------------------------------------------------------------------
+    //--------------------------------------------------------------------------------------------------
+    pub fn member(&self, email : &str) -> Option<&Member> {
+        let today = Utc::now().date_naive();
+        self.members.get(&email.trim().to_lowercase())
+            .filter(|member| member.expires.map_or(true, |expires| expires >= today))
+    }
+
+    //--------------------------------------------------------------------------------------------------
+    //--------------------------------------------------------------------------------------------------
+    // anyone can type a member's email, so only the member code handed out at the space gets the
+    // member price, the member list just tells the form to ask for it
+    pub fn tier(&self, code : &str) -> Result<Tier, MembershipError> {
+        let code = code.trim();
+        if code.is_empty() {
+            return Ok(Tier::Public);
+        }
+
+        match self.code.as_deref() {
+            Some(member_code) if util::constant_time_eq(member_code.as_bytes(), code.as_bytes()) => Ok(Tier::Member),
+            _ => Err(MembershipError::InvalidCode),
+        }
+    }
+}
+
+//------------------------------------------------------------------------------------------------------
+//------------------------------------------------------------------------------------------------------
+impl util::Failure for MembershipError {
+
+    //--------------------------------------------------------------------------------------------------
+    //--------------------------------------------------------------------------------------------------
+    fn status_code(&self) -> StatusCode {
+        StatusCode::UNPROCESSABLE_ENTITY
+    }
+
+    //--------------------------------------------------------------------------------------------------
+    //--------------------------------------------------------------------------------------------------
+    fn buyer_message(&self) -> &'static str {
+        match self {
+            MembershipError::InvalidCode => "That member code was not recognised. Please check it, or leave it blank to pay the public price.",
+        }
+    }
+}
+
+//----------------------------------------------------------------------------------------------------
+//----------------------------------------------------------------------------------------------------
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    //--------------------------------------------------------------------------------------------------
+    //--------------------------------------------------------------------------------------------------
+    fn members() -> Members {
+        let mut members = Members{ code: Some("hackthe-planet".to_string()), ..Members::default() };
+        members.members.insert("ada@example.org".to_string(), Member{ name: "Ada".to_string(), expires: None });
+        members
+    }
+
+    //--------------------------------------------------------------------------------------------------
+    //--------------------------------------------------------------------------------------------------
+    #[test]
+    fn member_price_needs_the_code() {
+        let members = members();
+        assert!(members.member(" ADA@example.org ").is_some());
+        assert_eq!(members.tier(""), Ok(Tier::Public));
+        assert_eq!(members.tier(" hackthe-planet "), Ok(Tier::Member));
+        assert_eq!(members.tier("hackthe-planeT"), Err(MembershipError::InvalidCode));
+        assert_eq!(members.tier("hackthe"), Err(MembershipError::InvalidCode));
+
+        let no_code = Members{ code: None, ..members };
+        assert_eq!(no_code.tier("hackthe-planet"), Err(MembershipError::InvalidCode));
+    }
+}
@@ -0,0 +1,597 @@
+use serde::de::{self, Visitor};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::fmt;
+use std::str::FromStr;
+
+//rates are kept to the millionth so 8.75% is exactly 87500
+const RATE_SCALE : i64 = 1_000_000;
+const RATE_PLACES : usize = 6;
+
+//----------------------------------------------------------------------------------------------------
+//----------------------------------------------------------------------------------------------------
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Currency {
+    Usd,
+}
+
+//----------------------------------------------------------------------------------------------------
+//----------------------------------------------------------------------------------------------------
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Money {
+    cents : i64,
+    currency : Currency,
+}
+
+//----------------------------------------------------------------------------------------------------
+//----------------------------------------------------------------------------------------------------
+//a share of an amount, e.g. a tax rate or a percent off, kept as a whole number of millionths
+//so no float ever reaches the money math
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+pub struct Fraction {
+    millionths : i64,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParseError(String);
+
+//----------------------------------------------------------------------------------------------------
+//----------------------------------------------------------------------------------------------------
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MoneyError {
+    Overflow,
+    CurrencyMismatch(Currency, Currency),
+}
+
+//----------------------------------------------------------------------------------------------------
+//----------------------------------------------------------------------------------------------------
+enum DecimalError {
+    Invalid,
+    TooManyPlaces,
+}
+
+//------------------------------------------------------------------------------------------------------
+//------------------------------------------------------------------------------------------------------
+impl Currency {
+    pub fn code(&self) -> &'static str {
+        match self {
+            Currency::Usd => "USD",
+        }
+    }
+}
+
+//------------------------------------------------------------------------------------------------------
+//------------------------------------------------------------------------------------------------------
+impl Money {
+
+    //--------------------------------------------------------------------------------------------------
+    //--------------------------------------------------------------------------------------------------
+    pub const fn from_cents(cents : i64) -> Money {
+        Money { cents, currency: Currency::Usd }
+    }
+
+    //--------------------------------------------------------------------------------------------------
+    //--------------------------------------------------------------------------------------------------
+    pub const fn zero() -> Money {
+        Money::from_cents(0)
+    }
+
+    //--------------------------------------------------------------------------------------------------
+    //--------------------------------------------------------------------------------------------------
+    // for the old float amounts in json files, rounded to the nearest cent
+    pub fn from_dollars(dollars : f64) -> Money {
+        Money::from_cents((dollars * 100.0).round() as i64)
+    }
+
+    //--------------------------------------------------------------------------------------------------
+    //--------------------------------------------------------------------------------------------------
+    pub fn cents(&self) -> i64 {
+        self.cents
+    }
+
+    //--------------------------------------------------------------------------------------------------
+    //--------------------------------------------------------------------------------------------------
+    pub fn currency(&self) -> Currency {
+        self.currency
+    }
+
+    //--------------------------------------------------------------------------------------------------
+    //--------------------------------------------------------------------------------------------------
+    pub fn whole_dollars(&self) -> i64 {
+        self.cents / 100
+    }
+
+    //--------------------------------------------------------------------------------------------------
+    //--------------------------------------------------------------------------------------------------
+    pub fn is_zero(&self) -> bool {
+        self.cents == 0
+    }
+
+    //--------------------------------------------------------------------------------------------------
+    //--------------------------------------------------------------------------------------------------
+    pub fn is_positive(&self) -> bool {
+        self.cents > 0
+    }
+
+    //--------------------------------------------------------------------------------------------------
+    //--------------------------------------------------------------------------------------------------
+    pub fn checked_add(&self, other : Money) -> Result<Money, MoneyError> {
+        self.same_currency(&other)?;
+        let cents = self.cents.checked_add(other.cents).ok_or(MoneyError::Overflow)?;
+        Ok(Money { cents, currency: self.currency })
+    }
+
+    //--------------------------------------------------------------------------------------------------
+    //--------------------------------------------------------------------------------------------------
+    pub fn checked_sub(&self, other : Money) -> Result<Money, MoneyError> {
+        self.same_currency(&other)?;
+        let cents = self.cents.checked_sub(other.cents).ok_or(MoneyError::Overflow)?;
+        Ok(Money { cents, currency: self.currency })
+    }
+
+    //--------------------------------------------------------------------------------------------------
+    //--------------------------------------------------------------------------------------------------
+    pub fn checked_times(&self, quantity : u32) -> Result<Money, MoneyError> {
+        let cents = self.cents.checked_mul(quantity as i64).ok_or(MoneyError::Overflow)?;
+        Ok(Money { cents, currency: self.currency })
+    }
+
+    //--------------------------------------------------------------------------------------------------
+    //--------------------------------------------------------------------------------------------------
+    pub fn total<I : IntoIterator<Item = Money>>(amounts : I) -> Result<Money, MoneyError> {
+        amounts.into_iter().try_fold(Money::zero(), |total, amount| total.checked_add(amount))
+    }
+
+    //--------------------------------------------------------------------------------------------------
+    //--------------------------------------------------------------------------------------------------
+    // tax and other percentages are worked out once on the whole amount and rounded half away
+    // from zero to the cent, so $0.40 at 8.75% is $0.04
+    pub fn times_rate(&self, rate : Fraction) -> Result<Money, MoneyError> {
+        let scale = RATE_SCALE as i128;
+        let scaled = self.cents as i128 * rate.millionths as i128;
+        let rounded = (scaled.abs() + scale / 2) / scale * scaled.signum();
+        let cents = i64::try_from(rounded).map_err(|_| MoneyError::Overflow)?;
+        Ok(Money { cents, currency: self.currency })
+    }
+
+    //--------------------------------------------------------------------------------------------------
+    //--------------------------------------------------------------------------------------------------
+    pub fn percent_of(&self, whole : Money) -> i64 {
+        if whole.cents <= 0 {
+            return 0;
+        }
+        self.cents * 100 / whole.cents
+    }
+
+    //--------------------------------------------------------------------------------------------------
+    //--------------------------------------------------------------------------------------------------
+    fn same_currency(&self, other : &Money) -> Result<(), MoneyError> {
+        if self.currency != other.currency {
+            return Err(MoneyError::CurrencyMismatch(self.currency, other.currency));
+        }
+        Ok(())
+    }
+}
+
+//------------------------------------------------------------------------------------------------------
+//------------------------------------------------------------------------------------------------------
+impl Default for Money {
+    fn default() -> Money {
+        Money::zero()
+    }
+}
+
+//------------------------------------------------------------------------------------------------------
+//------------------------------------------------------------------------------------------------------
+impl Fraction {
+
+    //--------------------------------------------------------------------------------------------------
+    //--------------------------------------------------------------------------------------------------
+    pub const fn from_millionths(millionths : i64) -> Fraction {
+        Fraction { millionths }
+    }
+
+    //--------------------------------------------------------------------------------------------------
+    //--------------------------------------------------------------------------------------------------
+    pub const fn whole() -> Fraction {
+        Fraction::from_millionths(RATE_SCALE)
+    }
+
+    //--------------------------------------------------------------------------------------------------
+    //--------------------------------------------------------------------------------------------------
+    pub fn millionths(&self) -> i64 {
+        self.millionths
+    }
+
+    //--------------------------------------------------------------------------------------------------
+    //--------------------------------------------------------------------------------------------------
+    pub fn is_positive(&self) -> bool {
+        self.millionths > 0
+    }
+
+    //--------------------------------------------------------------------------------------------------
+    //--------------------------------------------------------------------------------------------------
+    //what an admin types for a percent off, e.g. "10" or "12.5%"
+    pub fn from_percent(text : &str) -> Result<Fraction, ParseError> {
+        let percent = text.trim();
+        let percent = percent.strip_suffix('%').unwrap_or(percent).trim_end();
+        match parse_decimal(percent, RATE_PLACES - 2) {
+            Ok(millionths) => Ok(Fraction::from_millionths(millionths)),
+            Err(DecimalError::Invalid) => Err(ParseError(format!("{} is not a percentage", text))),
+            Err(DecimalError::TooManyPlaces) => Err(ParseError(format!("{} has more than four decimal places", text))),
+        }
+    }
+
+    //--------------------------------------------------------------------------------------------------
+    //--------------------------------------------------------------------------------------------------
+    pub fn percent(&self) -> String {
+        format_decimal(self.millionths, RATE_PLACES - 2)
+    }
+
+    //--------------------------------------------------------------------------------------------------
+    //--------------------------------------------------------------------------------------------------
+    pub fn checked_add(&self, other : Fraction) -> Result<Fraction, MoneyError> {
+        self.millionths.checked_add(other.millionths).map(Fraction::from_millionths).ok_or(MoneyError::Overflow)
+    }
+}
+
+//----------------------------------------------------------------------------------------------------
+//----------------------------------------------------------------------------------------------------
+//digits with an optional decimal point as a whole number of the given decimal places, e.g. "1.5" with
+//two places is 150, a sign is never accepted
+fn parse_decimal(text : &str, places : usize) -> Result<i64, DecimalError> {
+    let (whole, part) = match text.split_once('.') {
+        Some((whole, part)) => (whole, part),
+        None => (text, ""),
+    };
+    if whole.is_empty() && part.is_empty() {
+        return Err(DecimalError::Invalid);
+    }
+    if !whole.chars().chain(part.chars()).all(|c| c.is_ascii_digit()) {
+        return Err(DecimalError::Invalid);
+    }
+    if part.len() > places {
+        return Err(DecimalError::TooManyPlaces);
+    }
+
+    let scale = 10i64.pow(places as u32);
+    let whole : i64 = if whole.is_empty() { 0 } else { whole.parse().map_err(|_| DecimalError::Invalid)? };
+    let part : i64 = if places == 0 { 0 } else { format!("{:0<width$}", part, width = places).parse().map_err(|_| DecimalError::Invalid)? };
+    whole.checked_mul(scale).and_then(|whole| whole.checked_add(part)).ok_or(DecimalError::Invalid)
+}
+
+//----------------------------------------------------------------------------------------------------
+//----------------------------------------------------------------------------------------------------
+//the shortest decimal for a whole number of the given decimal places, 87500 with six places is 0.0875
+fn format_decimal(value : i64, places : usize) -> String {
+    let scale = 10i64.pow(places as u32);
+    let sign = if value < 0 { "-" } else { "" };
+    let part = format!("{:0width$}", (value % scale).abs(), width = places);
+    let part = part.trim_end_matches('0');
+    if part.is_empty() {
+        format!("{}{}", sign, (value / scale).abs())
+    } else {
+        format!("{}{}.{}", sign, (value / scale).abs(), part)
+    }
+}
+
+//------------------------------------------------------------------------------------------------------
+//------------------------------------------------------------------------------------------------------
+impl fmt::Display for Money {
+    fn fmt(&self, f : &mut fmt::Formatter) -> fmt::Result {
+        let sign = if self.cents < 0 { "-" } else { "" };
+        write!(f, "{}{}.{:02}", sign, self.cents.abs() / 100, self.cents.abs() % 100)
+    }
+}
+
+//------------------------------------------------------------------------------------------------------
+//------------------------------------------------------------------------------------------------------
+impl fmt::Display for ParseError {
+    fn fmt(&self, f : &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+//------------------------------------------------------------------------------------------------------
+//------------------------------------------------------------------------------------------------------
+impl fmt::Display for MoneyError {
+    fn fmt(&self, f : &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            MoneyError::Overflow => write!(f, "amount is too large"),
+            MoneyError::CurrencyMismatch(first, second) => write!(f, "can not mix {} and {}", first.code(), second.code()),
+        }
+    }
+}
+
+//------------------------------------------------------------------------------------------------------
+//------------------------------------------------------------------------------------------------------
+impl fmt::Display for Fraction {
+    fn fmt(&self, f : &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", format_decimal(self.millionths, RATE_PLACES))
+    }
+}
+
+//------------------------------------------------------------------------------------------------------
+//------------------------------------------------------------------------------------------------------
+impl FromStr for Money {
+    type Err = ParseError;
+
+    //accepts what people type into a form, e.g. "25", "25.5", "$1250.00" or "10.00 USD",
+    //nobody is ever asked for a negative amount so a minus sign is refused
+    fn from_str(text : &str) -> Result<Money, ParseError> {
+        let invalid = || ParseError(format!("{} is not an amount of money", text));
+
+        let amount = text.trim();
+        let amount = amount.strip_suffix(Currency::Usd.code()).unwrap_or(amount).trim_end();
+        if amount.starts_with('-') {
+            return Err(ParseError(format!("{} is a negative amount", text)));
+        }
+        let amount = amount.strip_prefix('$').unwrap_or(amount);
+
+        match parse_decimal(amount, 2) {
+            Ok(cents) => Ok(Money::from_cents(cents)),
+            Err(DecimalError::Invalid) => Err(invalid()),
+            Err(DecimalError::TooManyPlaces) => Err(ParseError(format!("{} has more than two decimal places", text))),
+        }
+    }
+}
+
+//------------------------------------------------------------------------------------------------------
+//------------------------------------------------------------------------------------------------------
+impl FromStr for Fraction {
+    type Err = ParseError;
+
+    //a rate written as a fraction of one, e.g. "0.0875" for 8.75%
+    fn from_str(text : &str) -> Result<Fraction, ParseError> {
+        match parse_decimal(text.trim(), RATE_PLACES) {
+            Ok(millionths) => Ok(Fraction::from_millionths(millionths)),
+            Err(DecimalError::Invalid) => Err(ParseError(format!("{} is not a rate", text))),
+            Err(DecimalError::TooManyPlaces) => Err(ParseError(format!("{} has more than six decimal places", text))),
+        }
+    }
+}
+
+//------------------------------------------------------------------------------------------------------
+//------------------------------------------------------------------------------------------------------
+impl Serialize for Money {
+    fn serialize<S : Serializer>(&self, serializer : S) -> Result<S::Ok, S::Error> {
+        //written as a string so the files hold the exact amount rather than a float
+        serializer.serialize_str(&self.to_string())
+    }
+}
+
+//----------------------------------------------------------------------------------------------------
+//----------------------------------------------------------------------------------------------------
+struct MoneyVisitor;
+
+//------------------------------------------------------------------------------------------------------
+//------------------------------------------------------------------------------------------------------
+impl<'de> Visitor<'de> for MoneyVisitor {
+    type Value = Money;
+
+    fn expecting(&self, f : &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "an amount of money as a number of dollars or a string like \"12.50\"")
+    }
+
+    //amounts are never negative, the same as what FromStr accepts from a form
+    fn visit_i64<E : de::Error>(self, dollars : i64) -> Result<Money, E> {
+        if dollars < 0 {
+            return Err(E::custom(format!("{} is a negative amount", dollars)));
+        }
+        dollars.checked_mul(100).map(Money::from_cents).ok_or_else(|| E::custom(format!("{} dollars is too large", dollars)))
+    }
+
+    fn visit_u64<E : de::Error>(self, dollars : u64) -> Result<Money, E> {
+        i64::try_from(dollars).map_err(|_| E::custom(format!("{} dollars is too large", dollars)))
+            .and_then(|dollars| self.visit_i64(dollars))
+    }
+
+    //the json files written before amounts were kept in cents hold plain floats
+    fn visit_f64<E : de::Error>(self, dollars : f64) -> Result<Money, E> {
+        if !dollars.is_finite() || dollars.abs() * 100.0 >= i64::MAX as f64 {
+            return Err(E::custom(format!("{} is not an amount of money", dollars)));
+        }
+        if dollars < 0.0 {
+            return Err(E::custom(format!("{} is a negative amount", dollars)));
+        }
+        Ok(Money::from_dollars(dollars))
+    }
+
+    fn visit_str<E : de::Error>(self, text : &str) -> Result<Money, E> {
+        text.parse().map_err(E::custom)
+    }
+}
+
+//------------------------------------------------------------------------------------------------------
+//------------------------------------------------------------------------------------------------------
+impl<'de> Deserialize<'de> for Money {
+    fn deserialize<D : Deserializer<'de>>(deserializer : D) -> Result<Money, D::Error> {
+        deserializer.deserialize_any(MoneyVisitor)
+    }
+}
+
+//------------------------------------------------------------------------------------------------------
+//------------------------------------------------------------------------------------------------------
+impl Serialize for Fraction {
+    fn serialize<S : Serializer>(&self, serializer : S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.to_string())
+    }
+}
+
+//----------------------------------------------------------------------------------------------------
+//----------------------------------------------------------------------------------------------------
+//a fraction of one, or when percent is set a percentage, the way promo codes are stored
+struct FractionVisitor {
+    percent : bool,
+}
+
+//------------------------------------------------------------------------------------------------------
+//------------------------------------------------------------------------------------------------------
+impl<'de> Visitor<'de> for FractionVisitor {
+    type Value = Fraction;
+
+    fn expecting(&self, f : &mut fmt::Formatter) -> fmt::Result {
+        if self.percent {
+            write!(f, "a percentage as a number or a string like \"12.5\"")
+        } else {
+            write!(f, "a rate as a number or a string like \"0.0875\"")
+        }
+    }
+
+    fn visit_i64<E : de::Error>(self, value : i64) -> Result<Fraction, E> {
+        let scale = if self.percent { RATE_SCALE / 100 } else { RATE_SCALE };
+        match value.checked_mul(scale) {
+            Some(millionths) if millionths >= 0 => Ok(Fraction::from_millionths(millionths)),
+            _ => Err(E::custom(format!("{} is not a rate", value))),
+        }
+    }
+
+    fn visit_u64<E : de::Error>(self, value : u64) -> Result<Fraction, E> {
+        i64::try_from(value).map_err(|_| E::custom(format!("{} is not a rate", value)))
+            .and_then(|value| self.visit_i64(value))
+    }
+
+    //config files and promo codes written before rates were kept in millionths hold plain floats,
+    //they are rounded to the millionth once when read
+    fn visit_f64<E : de::Error>(self, value : f64) -> Result<Fraction, E> {
+        let millionths = if self.percent { value * (RATE_SCALE / 100) as f64 } else { value * RATE_SCALE as f64 }.round();
+        if !millionths.is_finite() || millionths < 0.0 || millionths >= i64::MAX as f64 {
+            return Err(E::custom(format!("{} is not a rate", value)));
+        }
+        Ok(Fraction::from_millionths(millionths as i64))
+    }
+
+    fn visit_str<E : de::Error>(self, text : &str) -> Result<Fraction, E> {
+        let fraction = if self.percent { Fraction::from_percent(text) } else { text.parse() };
+        fraction.map_err(E::custom)
+    }
+}
+
+//------------------------------------------------------------------------------------------------------
+//------------------------------------------------------------------------------------------------------
+impl<'de> Deserialize<'de> for Fraction {
+    fn deserialize<D : Deserializer<'de>>(deserializer : D) -> Result<Fraction, D::Error> {
+        deserializer.deserialize_any(FractionVisitor{ percent: false })
+    }
+}
+
+//----------------------------------------------------------------------------------------------------
+//----------------------------------------------------------------------------------------------------
+//serde with for a fraction kept as a percentage, 10 is 10% off
+pub mod percent {
+    use super::*;
+
+    pub fn serialize<S : Serializer>(fraction : &Fraction, serializer : S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&fraction.percent())
+    }
+
+    pub fn deserialize<'de, D : Deserializer<'de>>(deserializer : D) -> Result<Fraction, D::Error> {
+        deserializer.deserialize_any(FractionVisitor{ percent: true })
+    }
+}
+
+//----------------------------------------------------------------------------------------------------
+//----------------------------------------------------------------------------------------------------
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    //--------------------------------------------------------------------------------------------------
+    //--------------------------------------------------------------------------------------------------
+    fn money(text : &str) -> Money {
+        text.parse().unwrap()
+    }
+
+    //--------------------------------------------------------------------------------------------------
+    //--------------------------------------------------------------------------------------------------
+    #[test]
+    fn parses_what_people_type() {
+        assert_eq!(money("25"), Money::from_cents(2500));
+        assert_eq!(money("25.5"), Money::from_cents(2550));
+        assert_eq!(money(" $1250.00 "), Money::from_cents(125000));
+        assert_eq!(money("10.00 USD"), Money::from_cents(1000));
+        assert_eq!(money(".75"), Money::from_cents(75));
+
+        for text in ["", ".", "-5", "$-5", "1.234", "1,000", "ten", "1e3", "99999999999999999999"] {
+            assert!(text.parse::<Money>().is_err(), "{} parsed", text);
+        }
+    }
+
+    //--------------------------------------------------------------------------------------------------
+    //--------------------------------------------------------------------------------------------------
+    #[test]
+    fn old_float_amounts_round_to_the_nearest_cent() {
+        assert_eq!(Money::from_dollars(0.1 + 0.2), Money::from_cents(30));
+        assert_eq!(Money::from_dollars(19.999), Money::from_cents(2000));
+        assert_eq!(Money::from_dollars(1.005), Money::from_cents(100));
+    }
+
+    //--------------------------------------------------------------------------------------------------
+    //--------------------------------------------------------------------------------------------------
+    #[test]
+    fn rates_round_half_away_from_zero_once() {
+        let rate : Fraction = "0.0875".parse().unwrap();
+        assert_eq!(rate, Fraction::from_millionths(87500));
+        assert_eq!(Money::from_cents(40).times_rate(rate), Ok(Money::from_cents(4)));
+        assert_eq!(Money::from_cents(39).times_rate(rate), Ok(Money::from_cents(3)));
+        assert_eq!(Money::from_cents(-40).times_rate(rate), Ok(Money::from_cents(-4)));
+        assert_eq!(Money::from_cents(12500).times_rate(Fraction::from_percent("10").unwrap()), Ok(Money::from_cents(1250)));
+        assert_eq!(Money::from_cents(i64::MAX).times_rate(Fraction::from_millionths(2 * RATE_SCALE)), Err(MoneyError::Overflow));
+    }
+
+    //--------------------------------------------------------------------------------------------------
+    //--------------------------------------------------------------------------------------------------
+    #[test]
+    fn arithmetic_reports_overflow() {
+        let most = Money::from_cents(i64::MAX);
+        assert_eq!(Money::from_cents(150).checked_add(Money::from_cents(50)), Ok(Money::from_cents(200)));
+        assert_eq!(most.checked_add(Money::from_cents(1)), Err(MoneyError::Overflow));
+        assert_eq!(Money::from_cents(i64::MIN).checked_sub(Money::from_cents(1)), Err(MoneyError::Overflow));
+        assert_eq!(Money::from_cents(1050).checked_times(3), Ok(Money::from_cents(3150)));
+        assert_eq!(most.checked_times(2), Err(MoneyError::Overflow));
+        assert_eq!(Money::total([Money::from_cents(1), Money::from_cents(2)]), Ok(Money::from_cents(3)));
+        assert_eq!(Money::total([most, most]), Err(MoneyError::Overflow));
+    }
+
+    //--------------------------------------------------------------------------------------------------
+    //--------------------------------------------------------------------------------------------------
+    #[test]
+    fn displays_dollars_and_cents() {
+        assert_eq!(Money::from_cents(125005).to_string(), "1250.05");
+        assert_eq!(Money::zero().to_string(), "0.00");
+        assert_eq!(Money::from_cents(-5).to_string(), "-0.05");
+        assert_eq!(Fraction::from_millionths(87500).to_string(), "0.0875");
+        assert_eq!(Fraction::from_millionths(87500).percent(), "8.75");
+        assert_eq!(Fraction::whole().percent(), "100");
+    }
+
+    //--------------------------------------------------------------------------------------------------
+    //--------------------------------------------------------------------------------------------------
+    #[test]
+    fn serde_keeps_exact_amounts_and_refuses_negative_ones() {
+        assert_eq!(serde_json::to_string(&Money::from_cents(1999)).unwrap(), "\"19.99\"");
+        assert_eq!(serde_json::from_str::<Money>("\"19.99\"").unwrap(), Money::from_cents(1999));
+        assert_eq!(serde_json::from_str::<Money>("19.99").unwrap(), Money::from_cents(1999));
+        assert_eq!(serde_json::from_str::<Money>("20").unwrap(), Money::from_cents(2000));
+
+        for json in ["\"-1.00\"", "-1", "-0.5", "1e300", "\"ten\"", "null"] {
+            assert!(serde_json::from_str::<Money>(json).is_err(), "{} deserialized", json);
+        }
+    }
+
+    //--------------------------------------------------------------------------------------------------
+    //--------------------------------------------------------------------------------------------------
+    #[test]
+    fn serde_reads_rates_and_percentages() {
+        #[derive(Serialize, Deserialize, Debug, PartialEq)]
+        struct Percent(#[serde(with = "percent")] Fraction);
+
+        assert_eq!(serde_json::from_str::<Fraction>("0.0725").unwrap(), Fraction::from_millionths(72500));
+        assert_eq!(serde_json::from_str::<Fraction>("\"0.0725\"").unwrap(), Fraction::from_millionths(72500));
+        assert_eq!(serde_json::to_string(&Fraction::from_millionths(72500)).unwrap(), "\"0.0725\"");
+        assert!(serde_json::from_str::<Fraction>("-0.01").is_err());
+
+        assert_eq!(serde_json::from_str::<Percent>("10.0").unwrap(), Percent(Fraction::from_millionths(100000)));
+        assert_eq!(serde_json::from_str::<Percent>("\"12.5\"").unwrap(), Percent(Fraction::from_millionths(125000)));
+        assert_eq!(serde_json::to_string(&Percent(Fraction::from_millionths(125000))).unwrap(), "\"12.5\"");
+    }
+}
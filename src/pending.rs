@@ -0,0 +1,240 @@
+use chrono::{DateTime, Utc};
+use log::{error, info};
+use serde::{Serialize, Deserialize};
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex};
+
+use crate::gateway::{self, Transaction};
+use crate::ledger::{Ledger, LedgerEntry, LedgerStatus};
+use crate::mail::{Mailer, Receipt};
+use crate::money::Money;
+use crate::promo::PromoReservation;
+use crate::quote::InvoiceRepository;
+use crate::state::{Reservation, State};
+use crate::storage::{self, DonationRecord, Order, Storage};
+use crate::tax::TaxQuote;
+use crate::util;
+
+//----------------------------------------------------------------------------------------------------
+//----------------------------------------------------------------------------------------------------
+//what a checkout was holding when its charge came back with an unknown result
+#[derive(Deserialize,Debug, Serialize, Clone)]
+#[serde(rename_all = "snake_case")]
+pub enum Hold {
+    Seats {
+        reservation : Reservation,
+        promo : Option<PromoReservation>,
+        orders : Vec<Order>,
+        cart_id : Option<String>,
+        dates : String,
+    },
+    Donation {
+        record : DonationRecord,
+    },
+    Invoice {
+        invoice_id : String,
+    },
+}
+
+//----------------------------------------------------------------------------------------------------
+//----------------------------------------------------------------------------------------------------
+#[derive(Deserialize,Debug, Serialize, Clone)]
+pub struct PendingCharge {
+    pub timestamp : DateTime<Utc>,
+    pub payment : util::Payment,
+    pub description : String,
+    pub amount : Money,
+    #[serde(default)]
+    pub tax : Option<TaxQuote>,
+    pub hold : Hold,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Resolution {
+    Charged(String),
+    NotCharged,
+}
+
+//----------------------------------------------------------------------------------------------------
+//----------------------------------------------------------------------------------------------------
+// charges braintree did not answer for, keyed by their checkout token, kept until the treasurer
+// has looked them up and said whether the card was charged
+pub struct PendingCharges {
+    storage : Arc<dyn Storage>,
+    charges : Mutex<BTreeMap<String, PendingCharge>>,
+}
+
+//------------------------------------------------------------------------------------------------------
+//------------------------------------------------------------------------------------------------------
+impl Hold {
+
+    //--------------------------------------------------------------------------------------------------
+    //--------------------------------------------------------------------------------------------------
+    pub fn payment_type(&self) -> util::PaymentType {
+        match self {
+            Hold::Seats{ .. } => util::PaymentType::CourseSignup,
+            Hold::Donation{ .. } => util::PaymentType::Donation,
+            Hold::Invoice{ .. } => util::PaymentType::Invoice,
+        }
+    }
+}
+
+//------------------------------------------------------------------------------------------------------
+//------------------------------------------------------------------------------------------------------
+impl PendingCharge {
+
+    //--------------------------------------------------------------------------------------------------
+    //--------------------------------------------------------------------------------------------------
+    pub fn new(payment : &util::Payment, sale : &util::Sale, hold : Hold) -> PendingCharge {
+        //the nonce was used up by the charge, there is no reason to keep it on disk
+        let mut payment = payment.clone();
+        payment.payment_method_nonce.clear();
+
+        PendingCharge {
+            timestamp: Utc::now(),
+            payment,
+            description: sale.description.to_string(),
+            amount: sale.amount,
+            tax: sale.tax.clone(),
+            hold,
+        }
+    }
+}
+
+//------------------------------------------------------------------------------------------------------
+//------------------------------------------------------------------------------------------------------
+impl PendingCharges {
+
+    //--------------------------------------------------------------------------------------------------
+    //--------------------------------------------------------------------------------------------------
+    pub fn load(storage : Arc<dyn Storage>) -> Result<PendingCharges, storage::Error> {
+        Ok(PendingCharges {
+            charges: Mutex::new(storage.load_pending()?),
+            storage,
+        })
+    }
+
+    //--------------------------------------------------------------------------------------------------
+    //--------------------------------------------------------------------------------------------------
+    pub fn charges(&self) -> BTreeMap<String, PendingCharge> {
+        self.charges.lock().unwrap().clone()
+    }
+
+    //--------------------------------------------------------------------------------------------------
+    //--------------------------------------------------------------------------------------------------
+    pub fn hold(&self, id : &str, charge : PendingCharge) {
+        error!("Error: {} of {} for {} is pending, resolve it on the admin page once it is checked in braintree\n",
+            charge.hold.payment_type().as_str(), charge.amount, charge.payment.email);
+
+        let mut charges = self.charges.lock().unwrap();
+        charges.insert(id.to_string(), charge);
+
+        //still held in memory so it can be resolved until the next restart
+        if let Err(error) = self.storage.save_pending(&charges) {
+            error!("Error: unable to save pending charge {} {:?}\n", id, error);
+        }
+    }
+
+    //--------------------------------------------------------------------------------------------------
+    //--------------------------------------------------------------------------------------------------
+    pub fn take(&self, id : &str) -> Result<Option<PendingCharge>, storage::Error> {
+        let mut charges = self.charges.lock().unwrap();
+        let charge = match charges.remove(id) {
+            Some(charge) => charge,
+            None => return Ok(None),
+        };
+
+        if let Err(error) = self.storage.save_pending(&charges) {
+            charges.insert(id.to_string(), charge);
+            return Err(error);
+        }
+        Ok(Some(charge))
+    }
+}
+
+//----------------------------------------------------------------------------------------------------
+//----------------------------------------------------------------------------------------------------
+//finishes a checkout the way it would have if braintree had answered
+pub fn resolve(
+    charge : PendingCharge,
+    resolution : &Resolution,
+    state : &State,
+    invoices : &InvoiceRepository,
+    ledger : &Ledger,
+    mailer : &Mailer) {
+    let sale = util::Sale {
+        payment_type: charge.hold.payment_type(),
+        description: &charge.description,
+        amount: charge.amount,
+        tax: charge.tax.clone(),
+        promo_code: None,
+    };
+
+    let transaction = match resolution {
+        Resolution::Charged(transaction_id) => Transaction{ id: transaction_id.clone(), amount: charge.amount.to_string() },
+        Resolution::NotCharged => {
+            info!("pending {} for {} was not charged, releasing it\n", sale.payment_type.as_str(), charge.payment.email);
+            match charge.hold {
+                Hold::Seats{ reservation, promo, .. } => {
+                    state.release_seat(reservation);
+                    if let Some(promo) = promo {
+                        state.release_promo(promo);
+                    }
+                },
+                Hold::Donation{ .. } => {},
+                Hold::Invoice{ invoice_id } => {
+                    if let Err(error) = invoices.release(&invoice_id) {
+                        error!("Error: unable to release invoice {} {:?}\n", invoice_id, error);
+                    }
+                },
+            }
+
+            let mut entry = LedgerEntry::new(&charge.payment, &sale, &Err(gateway::Error::Pending));
+            entry.status = LedgerStatus::Failed;
+            entry.error = Some("checked in braintree, the card was not charged".to_string());
+            ledger.record(&entry);
+            return;
+        },
+    };
+
+    info!("pending {} for {} was charged as {}\n", sale.payment_type.as_str(), charge.payment.email, transaction.id);
+    let mut receipt = Receipt::new(&charge.payment, &sale.payment_type, &charge.description, charge.amount, &transaction);
+    //the tax was added up before the charge was made so it still adds up
+    receipt.tax = match charge.tax.as_ref().map_or(Ok(Money::zero()), |tax| tax.total()) {
+        Ok(tax) => tax,
+        Err(error) => {
+            error!("Error: unable to add up the tax of pending {} {}\n", transaction.id, error);
+            Money::zero()
+        },
+    };
+
+    match charge.hold {
+        Hold::Seats{ reservation, promo, mut orders, cart_id, dates } => {
+            for order in orders.iter_mut() {
+                order.transaction_id = transaction.id.clone();
+            }
+            state.commit_seats(reservation, &orders);
+            if let Some(promo) = promo {
+                state.commit_promo(promo, &transaction.id);
+            }
+            if let Some(cart_id) = cart_id {
+                state.clear_cart(&cart_id);
+            }
+            receipt.dates = dates;
+        },
+        Hold::Donation{ mut record } => {
+            record.transaction_id = transaction.id.clone();
+            if state.add_donation(&record).is_none() {
+                error!("Error: fundraiser {} disappeared before donation {} was recorded\n", record.fundraiser, transaction.id);
+            }
+        },
+        Hold::Invoice{ invoice_id } => match invoices.record_payment(&invoice_id, &transaction, charge.amount) {
+            Ok(Some(_)) => {},
+            Ok(None) => error!("Error: invoice {} disappeared before payment {} was recorded\n", invoice_id, transaction.id),
+            Err(error) => error!("Error: unable to record payment {} for invoice {} {:?}\n", transaction.id, invoice_id, error),
+        },
+    }
+
+    ledger.record(&LedgerEntry::new(&charge.payment, &sale, &Ok(transaction)));
+    mailer.send_receipt(&receipt);
+}
@@ -0,0 +1,114 @@
+use log::{error, warn};
+use serde::{Serialize};
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter, Write};
+use std::path::{Path};
+
+pub const BACKUP_COUNT : usize = 5;
+
+//----------------------------------------------------------------------------------------------------
+//----------------------------------------------------------------------------------------------------
+fn backup_name(file_name : &str, index : usize) -> String {
+    format!("{}.{}", file_name, index)
+}
+
+//----------------------------------------------------------------------------------------------------
+//----------------------------------------------------------------------------------------------------
+fn sync_directory(file_name : &str) -> io::Result<()> {
+    let directory = match Path::new(file_name).parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    };
+
+    File::open(directory)?.sync_all()
+}
+
+//----------------------------------------------------------------------------------------------------
+//----------------------------------------------------------------------------------------------------
+fn rotate_backups(file_name : &str) -> io::Result<()> {
+    if !Path::new(file_name).exists() {
+        return Ok(());
+    }
+
+    for index in (1..BACKUP_COUNT).rev() {
+        let backup = backup_name(file_name, index);
+        if Path::new(&backup).exists() {
+            fs::rename(&backup, backup_name(file_name, index + 1))?;
+        }
+    }
+
+    //copy rather than rename so the live file is never missing
+    fs::copy(file_name, backup_name(file_name, 1))?;
+    Ok(())
+}
+
+//----------------------------------------------------------------------------------------------------
+//----------------------------------------------------------------------------------------------------
+fn read<T: serde::de::DeserializeOwned>(file_name : &str) -> io::Result<T> {
+    let reader = BufReader::new(File::open(file_name)?);
+    Ok(serde_json::from_reader(reader)?)
+}
+
+//----------------------------------------------------------------------------------------------------
+//----------------------------------------------------------------------------------------------------
+fn write_file<T: Serialize>(file_name : &str, data : &T) -> io::Result<()> {
+    let file = File::create(file_name)?;
+    let mut writer = BufWriter::new(&file);
+    serde_json::to_writer_pretty(&mut writer, data)?;
+    writer.flush()?;
+    drop(writer);
+    file.sync_all()
+}
+
+//----------------------------------------------------------------------------------------------------
+//----------------------------------------------------------------------------------------------------
+fn replace(temp_name : &str, file_name : &str) -> io::Result<()> {
+    fs::rename(temp_name, file_name)?;
+    sync_directory(file_name)
+}
+
+//----------------------------------------------------------------------------------------------------
+//----------------------------------------------------------------------------------------------------
+pub fn save<T: Serialize>(file_name : &str, data : &T) -> io::Result<()> {
+    let temp_name = format!("{}.tmp", file_name);
+
+    write_file(&temp_name, data)?;
+    rotate_backups(file_name)?;
+    replace(&temp_name, file_name)
+}
+
+//----------------------------------------------------------------------------------------------------
+//----------------------------------------------------------------------------------------------------
+pub fn load<T: serde::de::DeserializeOwned>(file_name : &str) -> io::Result<T> {
+    let error = match read::<T>(file_name) {
+        Ok(data) => return Ok(data),
+        Err(error) => error,
+    };
+
+    error!("Error: unable to read {} {:?}, trying backups\n", file_name, error);
+
+    for index in 1..=BACKUP_COUNT {
+        let backup = backup_name(file_name, index);
+        match read::<T>(&backup) {
+            Ok(data) => {
+                warn!("recovered {} from {}\n", file_name, backup);
+                if Path::new(file_name).exists() {
+                    fs::copy(file_name, format!("{}.corrupt", file_name))?;
+                }
+
+                let temp_name = format!("{}.tmp", file_name);
+                fs::copy(&backup, &temp_name)?;
+                File::open(&temp_name)?.sync_all()?;
+                replace(&temp_name, file_name)?;
+                return Ok(data);
+            },
+            Err(backup_error) => {
+                if Path::new(&backup).exists() {
+                    error!("Error: unable to read backup {} {:?}\n", backup, backup_error);
+                }
+            },
+        }
+    }
+
+    Err(error)
+}
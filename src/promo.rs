@@ -0,0 +1,242 @@
+use actix_web::http::StatusCode;
+use chrono::{DateTime, NaiveDate, Utc};
+use serde::{Serialize, Deserialize};
+use std::collections::BTreeMap;
+use std::fmt;
+
+use crate::money::{self, Fraction, Money, MoneyError};
+use crate::util;
+
+//----------------------------------------------------------------------------------------------------
+//----------------------------------------------------------------------------------------------------
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Discount {
+    Percent(#[serde(with = "money::percent")] Fraction),
+    Fixed(Money),
+}
+
+//----------------------------------------------------------------------------------------------------
+//----------------------------------------------------------------------------------------------------
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Redemption {
+    pub email : String,
+    pub transaction_id : String,
+    pub timestamp : DateTime<Utc>,
+}
+
+//----------------------------------------------------------------------------------------------------
+//----------------------------------------------------------------------------------------------------
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct PromoCode {
+    pub discount : Discount,
+    #[serde(default)]
+    pub item : Option<String>,
+    #[serde(default)]
+    pub expires : Option<NaiveDate>,
+    #[serde(default)]
+    pub max_uses : Option<u32>,
+    #[serde(default)]
+    pub max_uses_per_email : Option<u32>,
+    #[serde(default)]
+    pub redemptions : Vec<Redemption>,
+}
+
+//----------------------------------------------------------------------------------------------------
+//----------------------------------------------------------------------------------------------------
+#[derive(Debug, Clone, PartialEq)]
+pub enum PromoError {
+    Unknown,
+    Expired,
+    WrongItem,
+    UsedUp,
+    EmailLimit,
+}
+
+//----------------------------------------------------------------------------------------------------
+//----------------------------------------------------------------------------------------------------
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct PromoReservation {
+    pub code : String,
+    pub email : String,
+    pub discount : Discount,
+    pub item : Option<String>,
+}
+
+//----------------------------------------------------------------------------------------------------
+//----------------------------------------------------------------------------------------------------
+// codes being used by a checkout that is still charging count against the limits
+// so two buyers can't both take the last use
+pub struct Promotions {
+    pub codes : BTreeMap<String, PromoCode>,
+    reserved : Vec<(String, String)>,
+}
+
+//----------------------------------------------------------------------------------------------------
+//----------------------------------------------------------------------------------------------------
+pub fn normalize_code(code : &str) -> String {
+    code.trim().to_uppercase()
+}
+
+//----------------------------------------------------------------------------------------------------
+//----------------------------------------------------------------------------------------------------
+fn normalize_email(email : &str) -> String {
+    email.trim().to_lowercase()
+}
+
+//------------------------------------------------------------------------------------------------------
+//------------------------------------------------------------------------------------------------------
+impl Discount {
+
+    //--------------------------------------------------------------------------------------------------
+    //--------------------------------------------------------------------------------------------------
+    pub fn amount_off(&self, price : Money) -> Result<Money, MoneyError> {
+        let amount = match self {
+            Discount::Percent(percent) => price.times_rate(*percent)?,
+            Discount::Fixed(amount) => *amount,
+        };
+        //a discount never takes the price below zero
+        Ok(amount.min(price))
+    }
+
+    //--------------------------------------------------------------------------------------------------
+    //--------------------------------------------------------------------------------------------------
+    pub fn is_valid(&self) -> bool {
+        match self {
+            Discount::Percent(percent) => percent.is_positive() && *percent <= Fraction::whole(),
+            Discount::Fixed(amount) => amount.is_positive(),
+        }
+    }
+}
+
+//------------------------------------------------------------------------------------------------------
+//------------------------------------------------------------------------------------------------------
+impl fmt::Display for Discount {
+    fn fmt(&self, f : &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Discount::Percent(percent) => write!(f, "{}% off", percent.percent()),
+            Discount::Fixed(amount) => write!(f, "${} off", amount),
+        }
+    }
+}
+
+//------------------------------------------------------------------------------------------------------
+//------------------------------------------------------------------------------------------------------
+impl PromoCode {
+
+    //--------------------------------------------------------------------------------------------------
+    //--------------------------------------------------------------------------------------------------
+    pub fn uses(&self) -> usize {
+        self.redemptions.len()
+    }
+
+    //--------------------------------------------------------------------------------------------------
+    //--------------------------------------------------------------------------------------------------
+    pub fn uses_by(&self, email : &str) -> usize {
+        let email = normalize_email(email);
+        self.redemptions.iter().filter(|redemption| normalize_email(&redemption.email) == email).count()
+    }
+
+    //--------------------------------------------------------------------------------------------------
+    //--------------------------------------------------------------------------------------------------
+    pub fn is_expired(&self) -> bool {
+        let today = Utc::now().date_naive();
+        self.expires.map_or(false, |expires| expires < today)
+    }
+}
+
+//------------------------------------------------------------------------------------------------------
+//------------------------------------------------------------------------------------------------------
+impl util::Failure for PromoError {
+
+    //--------------------------------------------------------------------------------------------------
+    //--------------------------------------------------------------------------------------------------
+    fn status_code(&self) -> StatusCode {
+        StatusCode::UNPROCESSABLE_ENTITY
+    }
+
+    //--------------------------------------------------------------------------------------------------
+    //--------------------------------------------------------------------------------------------------
+    fn buyer_message(&self) -> &'static str {
+        match self {
+            PromoError::Unknown => "That promo code was not recognised. Please check it and try again, or leave it blank.",
+            PromoError::Expired => "That promo code has expired. Please remove it to check out at the regular price.",
+            PromoError::WrongItem => "That promo code can not be used for this class. Please remove it to check out at the regular price.",
+            PromoError::UsedUp => "That promo code has already been used the maximum number of times.",
+            PromoError::EmailLimit => "That promo code has already been used with your email address.",
+        }
+    }
+}
+
+//------------------------------------------------------------------------------------------------------
+//------------------------------------------------------------------------------------------------------
+impl Promotions {
+
+    //--------------------------------------------------------------------------------------------------
+    //--------------------------------------------------------------------------------------------------
+    pub fn new(codes : BTreeMap<String, PromoCode>) -> Promotions {
+        Promotions { codes, reserved: Vec::new() }
+    }
+
+    //--------------------------------------------------------------------------------------------------
+    //--------------------------------------------------------------------------------------------------
+    pub fn reserve(&mut self, code : &str, item_names : &[&str], email : &str) -> Result<PromoReservation, PromoError> {
+        let code = normalize_code(code);
+        let email = normalize_email(email);
+        let promo = self.codes.get(&code).ok_or(PromoError::Unknown)?;
+
+        if promo.is_expired() {
+            return Err(PromoError::Expired);
+        }
+        if promo.item.as_ref().map_or(false, |item| !item_names.contains(&item.as_str())) {
+            return Err(PromoError::WrongItem);
+        }
+
+        let pending = self.reserved.iter().filter(|(reserved, _)| *reserved == code);
+        let pending_by_email = pending.clone().filter(|(_, reserved)| *reserved == email).count();
+        if let Some(max_uses) = promo.max_uses {
+            if promo.uses() + pending.count() >= max_uses as usize {
+                return Err(PromoError::UsedUp);
+            }
+        }
+        if let Some(max_uses_per_email) = promo.max_uses_per_email {
+            if promo.uses_by(&email) + pending_by_email >= max_uses_per_email as usize {
+                return Err(PromoError::EmailLimit);
+            }
+        }
+
+        let discount = promo.discount.clone();
+        let item = promo.item.clone();
+        self.reserved.push((code.clone(), email.clone()));
+        Ok(PromoReservation { code, email, discount, item })
+    }
+
+    //--------------------------------------------------------------------------------------------------
+    //--------------------------------------------------------------------------------------------------
+    pub fn release(&mut self, reservation : &PromoReservation) {
+        if let Some(index) = self.reserved.iter()
+            .position(|(code, email)| *code == reservation.code && *email == reservation.email) {
+            self.reserved.remove(index);
+        }
+    }
+
+    //--------------------------------------------------------------------------------------------------
+    //--------------------------------------------------------------------------------------------------
+    //puts back a use held by a charge that was still pending when the store restarted
+    pub fn restore(&mut self, reservation : &PromoReservation) {
+        self.reserved.push((reservation.code.clone(), reservation.email.clone()));
+    }
+
+    //--------------------------------------------------------------------------------------------------
+    //--------------------------------------------------------------------------------------------------
+    pub fn redeem(&mut self, reservation : &PromoReservation, transaction_id : &str) {
+        self.release(reservation);
+        if let Some(promo) = self.codes.get_mut(&reservation.code) {
+            promo.redemptions.push(Redemption {
+                email: reservation.email.clone(),
+                transaction_id: transaction_id.to_string(),
+                timestamp: Utc::now(),
+            });
+        }
+    }
+}
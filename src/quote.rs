@@ -1,65 +1,586 @@
 use actix_web::{web, HttpResponse};
-use braintree::{Braintree};
+use actix_web::http::StatusCode;
+use askama::Template;
 use serde::{Serialize, Deserialize};
-use log::{error, info};
-use std::sync::{Mutex};
+use log::{error, info, warn};
+use std::sync::{Arc, Mutex};
 
+use crate::checkout::Checkouts;
+use crate::config::Config;
+use crate::error::{Error, Page};
+use crate::gateway::{self, Gateway, Transaction};
+use crate::ledger::Ledger;
+use crate::mail::{Mailer, Receipt};
+use crate::money::{Money, MoneyError};
+use crate::pending::{Hold, PendingCharge, PendingCharges};
+use crate::storage::{self, Storage};
+use crate::tax::{TaxError, TaxQuote, TaxTable};
 use crate::util;
 
 //----------------------------------------------------------------------------------------------------
 //----------------------------------------------------------------------------------------------------
-#[derive(Deserialize,Debug, Serialize)]
+#[derive(Deserialize,Debug, Serialize, Clone)]
+pub struct LineItem {
+    pub description : String,
+    pub quantity : u32,
+    pub unit_price : Money,
+    #[serde(default = "taxable_by_default")]
+    pub taxable : bool,
+}
+
+//----------------------------------------------------------------------------------------------------
+//----------------------------------------------------------------------------------------------------
+#[derive(Deserialize,Debug, Serialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum InvoiceStatus {
+    Draft,
+    Sent,
+    PartiallyPaid,
+    Processing,
+    Paid,
+    Void,
+}
+
+//----------------------------------------------------------------------------------------------------
+//----------------------------------------------------------------------------------------------------
+#[derive(Deserialize,Debug, Serialize, Clone)]
 pub struct Invoice {
-    pub price : f32,
     pub invoice_id : String,
+    pub line_items : Vec<LineItem>,
     pub due_date : Option<String>,
-    pub disable_sales_tax : Option<bool>,
-    pub fees : Option<f32>,
+    #[serde(default)]
+    pub disable_sales_tax : bool,
+    #[serde(default)]
+    pub fees : Money,
+    #[serde(default)]
+    pub status : InvoiceStatus,
+    #[serde(default)]
+    pub amount_paid : Money,
+    #[serde(default)]
+    pub transaction_ids : Vec<String>,
+    #[serde(default)]
+    pub jurisdiction : Option<String>,
+    #[serde(default)]
+    pub tax_exempt_certificate : Option<String>,
+}
+
+//----------------------------------------------------------------------------------------------------
+//----------------------------------------------------------------------------------------------------
+pub struct InvoiceRepository {
+    storage : Arc<dyn Storage>,
+    tax : TaxTable,
+    lock : Mutex<()>,
+}
+
+//----------------------------------------------------------------------------------------------------
+//----------------------------------------------------------------------------------------------------
+#[derive(Deserialize,Debug, Serialize)]
+pub struct InvoicePayment {
+    pub invoice_id : String,
+    #[serde(default)]
+    pub checkout_token : String,
     #[serde(flatten)]
     payment : util::Payment,
 }
 
+//----------------------------------------------------------------------------------------------------
+//----------------------------------------------------------------------------------------------------
+#[derive(Deserialize,Debug, Serialize)]
+pub struct InvoiceQuery {
+    pub invoice_id : String,
+}
+
+#[derive(Template)]
+#[template(path = "invoice.html")]
+struct InvoicePage<'a> {
+    invoice : &'a Invoice,
+    lines : &'a [InvoiceLine<'a>],
+    due_date : &'a str,
+    tax : String,
+    paid : String,
+    balance : String,
+    client_token : String,
+    checkout_token : String,
+    buyer : &'a util::Payment,
+    message : &'a str,
+}
+
+struct InvoiceLine<'a> {
+    line_item : &'a LineItem,
+    amount : Money,
+}
+
+#[derive(Template)]
+#[template(path = "invoice_status.html")]
+struct InvoiceStatusPage<'a> {
+    invoice : &'a Invoice,
+}
+
+//----------------------------------------------------------------------------------------------------
+//----------------------------------------------------------------------------------------------------
+//invoice lines were always taxed before they had a flag
+fn taxable_by_default() -> bool {
+    true
+}
+
+//------------------------------------------------------------------------------------------------------
+//------------------------------------------------------------------------------------------------------
+impl LineItem {
+
+    //--------------------------------------------------------------------------------------------------
+    //--------------------------------------------------------------------------------------------------
+    pub fn amount(&self) -> Result<Money, MoneyError> {
+        self.unit_price.checked_times(self.quantity)
+    }
+}
+
+//------------------------------------------------------------------------------------------------------
+//------------------------------------------------------------------------------------------------------
+impl Invoice {
+
+    //--------------------------------------------------------------------------------------------------
+    //--------------------------------------------------------------------------------------------------
+    pub fn subtotal(&self) -> Result<Money, MoneyError> {
+        let amounts = self.line_items.iter().map(|line_item| line_item.amount()).collect::<Result<Vec<Money>, MoneyError>>()?;
+        Money::total(amounts)
+    }
+
+    //--------------------------------------------------------------------------------------------------
+    //--------------------------------------------------------------------------------------------------
+    pub fn tax(&self, tax : &TaxTable) -> Result<TaxQuote, TaxError> {
+        let lines = self.line_items.iter()
+            .map(|line_item| Ok((line_item.amount()?, line_item.taxable && !self.disable_sales_tax)))
+            .collect::<Result<Vec<(Money, bool)>, MoneyError>>()?;
+
+        tax.quote(self.jurisdiction.as_deref(), &lines, self.tax_exempt_certificate.as_deref())
+    }
+
+    //--------------------------------------------------------------------------------------------------
+    //--------------------------------------------------------------------------------------------------
+    pub fn taxes_and_fees(&self, tax : &TaxTable) -> Result<Money, TaxError> {
+        Ok(self.tax(tax)?.total()?.checked_add(self.fees)?)
+    }
+
+    //--------------------------------------------------------------------------------------------------
+    //--------------------------------------------------------------------------------------------------
+    pub fn total(&self, tax : &TaxTable) -> Result<Money, TaxError> {
+        Ok(self.subtotal()?.checked_add(self.taxes_and_fees(tax)?)?)
+    }
+
+    //--------------------------------------------------------------------------------------------------
+    //--------------------------------------------------------------------------------------------------
+    pub fn balance(&self, tax : &TaxTable) -> Result<Money, TaxError> {
+        Ok(self.total(tax)?.checked_sub(self.amount_paid)?)
+    }
+
+    //--------------------------------------------------------------------------------------------------
+    //--------------------------------------------------------------------------------------------------
+    pub fn is_payable(&self) -> bool {
+        match self.status {
+            InvoiceStatus::Sent | InvoiceStatus::PartiallyPaid => true,
+            InvoiceStatus::Draft | InvoiceStatus::Processing | InvoiceStatus::Paid | InvoiceStatus::Void => false,
+        }
+    }
+
+    //--------------------------------------------------------------------------------------------------
+    //--------------------------------------------------------------------------------------------------
+    pub fn status_message(&self) -> &'static str {
+        match self.status {
+            InvoiceStatus::Draft => "is not ready for payment yet",
+            InvoiceStatus::Sent => "is awaiting payment",
+            InvoiceStatus::PartiallyPaid => "has been partially paid",
+            InvoiceStatus::Processing => "has a payment being processed, please do not pay it again",
+            InvoiceStatus::Paid => "has already been paid in full",
+            InvoiceStatus::Void => "has been voided and can no longer be paid",
+        }
+    }
+}
+
+//------------------------------------------------------------------------------------------------------
+//------------------------------------------------------------------------------------------------------
+impl Default for InvoiceStatus {
+    fn default() -> InvoiceStatus {
+        InvoiceStatus::Draft
+    }
+}
+
+//------------------------------------------------------------------------------------------------------
+//------------------------------------------------------------------------------------------------------
+impl InvoiceRepository {
+
+    //--------------------------------------------------------------------------------------------------
+    //--------------------------------------------------------------------------------------------------
+    pub fn new(storage : Arc<dyn Storage>, tax : TaxTable) -> InvoiceRepository {
+        InvoiceRepository { storage, tax, lock: Mutex::new(()) }
+    }
+
+    //--------------------------------------------------------------------------------------------------
+    //--------------------------------------------------------------------------------------------------
+    pub fn get(&self, invoice_id : &str) -> Result<Option<Invoice>, storage::Error> {
+        let _lock = self.lock.lock().unwrap();
+        Ok(self.storage.load_invoices()?.remove(invoice_id))
+    }
+
+    //--------------------------------------------------------------------------------------------------
+    //--------------------------------------------------------------------------------------------------
+    //moves a payable invoice to processing so a second checkout of it is turned away while the card is charged,
+    //the invoice comes back as it was before so the caller can tell whether it got the hold
+    pub fn hold(&self, invoice_id : &str) -> Result<Option<Invoice>, storage::Error> {
+        let _lock = self.lock.lock().unwrap();
+        let mut invoices = self.storage.load_invoices()?;
+
+        let invoice = match invoices.get_mut(invoice_id) {
+            Some(invoice) => invoice,
+            None => return Ok(None),
+        };
+        let before = invoice.clone();
+
+        if before.is_payable() {
+            invoice.status = InvoiceStatus::Processing;
+            info!("invoice {} is now {:?}\n", invoice_id, invoice.status);
+            self.storage.save_invoices(&invoices)?;
+        }
+
+        Ok(Some(before))
+    }
+
+    //--------------------------------------------------------------------------------------------------
+    //--------------------------------------------------------------------------------------------------
+    //puts an invoice whose charge failed back to what it was owed before the hold
+    pub fn release(&self, invoice_id : &str) -> Result<Option<Invoice>, storage::Error> {
+        self.update(invoice_id, |invoice| {
+            if invoice.status == InvoiceStatus::Processing {
+                invoice.status = if invoice.amount_paid.is_zero() { InvoiceStatus::Sent } else { InvoiceStatus::PartiallyPaid };
+            }
+        })
+    }
+
+    //--------------------------------------------------------------------------------------------------
+    //--------------------------------------------------------------------------------------------------
+    pub fn record_payment(&self, invoice_id : &str, transaction : &Transaction, amount : Money) -> Result<Option<Invoice>, storage::Error> {
+        let tax = self.tax.clone();
+        self.update(invoice_id, |invoice| {
+            invoice.transaction_ids.push(transaction.id.clone());
+            //the amount charged was the balance, so neither of these can overflow unless the file was edited
+            //by hand, the payment is still recorded for the treasurer to sort out
+            invoice.status = match invoice.amount_paid.checked_add(amount) {
+                Ok(amount_paid) => {
+                    invoice.amount_paid = amount_paid;
+                    match invoice.balance(&tax) {
+                        Ok(balance) if !balance.is_positive() => InvoiceStatus::Paid,
+                        Ok(_) => InvoiceStatus::PartiallyPaid,
+                        Err(error) => {
+                            error!("Error: unable to work out the balance of invoice {} {}\n", invoice.invoice_id, error);
+                            InvoiceStatus::PartiallyPaid
+                        },
+                    }
+                },
+                Err(error) => {
+                    error!("Error: unable to add payment {} to invoice {} {}\n", transaction.id, invoice.invoice_id, error);
+                    InvoiceStatus::PartiallyPaid
+                },
+            };
+        })
+    }
+
+    //--------------------------------------------------------------------------------------------------
+    //--------------------------------------------------------------------------------------------------
+    fn update<F : FnOnce(&mut Invoice)>(&self, invoice_id : &str, update : F) -> Result<Option<Invoice>, storage::Error> {
+        let _lock = self.lock.lock().unwrap();
+        let mut invoices = self.storage.load_invoices()?;
+
+        let invoice = match invoices.get_mut(invoice_id) {
+            Some(invoice) => invoice,
+            None => return Ok(None),
+        };
+        update(invoice);
+        let invoice = invoice.clone();
+
+        info!("invoice {} is now {:?}\n", invoice_id, invoice.status);
+        self.storage.save_invoices(&invoices)?;
+
+        Ok(Some(invoice))
+    }
+}
+
 //----------------------------------------------------------------------------------------------------
 //----------------------------------------------------------------------------------------------------
 pub async fn process_invoice(
-    invoice : web::Form<Invoice>,
-    braintree : web::Data<Mutex<Braintree>>) -> HttpResponse {
+    invoice_payment : web::Form<InvoicePayment>,
+    config : web::Data<Config>,
+    gateway : web::Data<Gateway>,
+    ledger : web::Data<Ledger>,
+    mailer : web::Data<Mailer>,
+    invoices : web::Data<InvoiceRepository>,
+    pending : web::Data<PendingCharges>,
+    checkouts : web::Data<Checkouts>) -> Result<HttpResponse, Error> {
+    let page = Page::new(&config, util::PaymentType::Invoice);
 
-    let result = util::process_payment(
-        &invoice.payment,
-        invoice.price,
-        braintree,
-        util::PaymentType::Invoice,
-        &format!("Invoice ID #{}", invoice.invoice_id).to_string());
+    let invoice_payment = invoice_payment.into_inner();
+    let token = invoice_payment.checkout_token.clone();
+    let form_checkouts = checkouts.clone();
+    checkouts.once(&token, page, async move {
+        invoice_checkout(&invoice_payment, &config, &gateway, &ledger, &mailer, &invoices, &pending, &form_checkouts).await
+    }).await
+}
+
+//----------------------------------------------------------------------------------------------------
+//----------------------------------------------------------------------------------------------------
+async fn invoice_checkout(
+    invoice_payment : &InvoicePayment,
+    config : &Config,
+    gateway : &web::Data<Gateway>,
+    ledger : &Ledger,
+    mailer : &Mailer,
+    invoices : &InvoiceRepository,
+    pending : &PendingCharges,
+    checkouts : &Checkouts) -> Result<HttpResponse, Error> {
+    let page = Page::new(config, util::PaymentType::Invoice);
+
+    //only ever charge the amount on the server side invoice, held so it can't be paid twice at once
+    let invoice = match invoices.hold(&invoice_payment.invoice_id) {
+        Ok(Some(invoice)) => invoice,
+        Ok(None) => return Err(Error::NotFound(page, invoice_payment.invoice_id.clone())),
+        Err(error) => return Err(Error::Storage(page, error)),
+    };
+
+    if !invoice.is_payable() {
+        error!("Error: invoice {} is {:?} and can not be paid\n", invoice.invoice_id, invoice.status);
+        return Ok(invoice_status(&invoice).await);
+    }
+
+    //an invoice too large to add up is refused before the card is charged
+    let totals = invoice.balance(&config.tax).and_then(|amount| {
+        let quote = invoice.tax(&config.tax)?;
+        let quote_total = quote.total()?;
+        Ok((amount, quote, quote_total))
+    });
+    let (amount, quote, quote_total) = match totals {
+        Ok(totals) => totals,
+        Err(error) => {
+            if let Err(release_error) = invoices.release(&invoice.invoice_id) {
+                error!("Error: unable to release invoice {} {:?}\n", invoice.invoice_id, release_error);
+            }
+            return Err(Error::Tax(page, error));
+        },
+    };
 
-    if result.is_err() {
-        error!("Error: payment process {:?}\n", result);
-        return util::error(util::PaymentType::Invoice).await;
+    let description = format!("Invoice ID #{}", invoice.invoice_id);
+
+    //the whole invoice is charged at once so its tax all belongs to this payment
+    let (tax, tax_total) = if invoice.amount_paid.is_zero() {
+        (Some(quote), quote_total)
+    } else {
+        warn!("invoice {} was partly paid before, report its sales tax by hand\n", invoice.invoice_id);
+        (None, Money::zero())
+    };
+    let sale = util::Sale { payment_type: util::PaymentType::Invoice, description: &description, amount, tax, promo_code: None };
+
+    let result = util::process_payment(&invoice_payment.payment, &sale, gateway.clone(), config.gateway_timeout(), ledger).await;
+
+    let transaction = match result {
+        Ok(transaction) => transaction,
+        //the card may still be charged so the invoice stays held until the charge is checked
+        Err(error @ gateway::Error::Pending) => {
+            pending.hold(&invoice_payment.checkout_token, PendingCharge::new(&invoice_payment.payment, &sale, Hold::Invoice{
+                invoice_id: invoice.invoice_id.clone(),
+            }));
+            return Err(Error::Payment(page, error));
+        },
+        Err(error) => {
+            if let Err(release_error) = invoices.release(&invoice.invoice_id) {
+                error!("Error: unable to release invoice {} {:?}\n", invoice.invoice_id, release_error);
+            }
+            return render_invoice_page(config, gateway, checkouts, page, &invoice, &invoice_payment.payment, Some(&error)).await;
+        },
+    };
+
+    info!("invoice number {} payment {} processed for ${}\n", invoice.invoice_id, transaction.id, amount);
+
+    //the buyer has been charged so a failure here is logged for the treasurer rather than shown
+    match invoices.record_payment(&invoice.invoice_id, &transaction, amount) {
+        Ok(Some(_)) => {},
+        Ok(None) => error!("Error: invoice {} disappeared before payment {} was recorded\n", invoice.invoice_id, transaction.id),
+        Err(error) => error!("Error: unable to record payment {} for invoice {} {:?}\n", transaction.id, invoice.invoice_id, error),
     }
 
-    info!("invoice number {} payment processed for ${}\n", invoice.invoice_id, invoice.price);
+    let mut receipt = Receipt::new(&invoice_payment.payment, &sale.payment_type, &description, amount, &transaction);
+    receipt.tax = tax_total;
+    mailer.send_receipt(&receipt);
 
-    util::thanks(util::PaymentType::Invoice).await
+    Ok(util::thanks(config, util::PaymentType::Invoice).await)
 }
 
 //----------------------------------------------------------------------------------------------------
 //----------------------------------------------------------------------------------------------------
-pub async fn invoice(braintree : web::Data<Mutex<Braintree>>, invoice : web::Query<Invoice>) -> HttpResponse {
-    let braintree = braintree.lock().unwrap();
+pub async fn invoice_status(invoice : &Invoice) -> HttpResponse {
+    util::render(StatusCode::OK, &InvoiceStatusPage{ invoice })
+}
 
-    let tax = if invoice.disable_sales_tax.unwrap_or(false) { 0.0 } else { 0.0875 };
+//----------------------------------------------------------------------------------------------------
+//----------------------------------------------------------------------------------------------------
+pub async fn invoice(
+    config : web::Data<Config>,
+    gateway : web::Data<Gateway>,
+    invoices : web::Data<InvoiceRepository>,
+    checkouts : web::Data<Checkouts>,
+    query : web::Query<InvoiceQuery>) -> Result<HttpResponse, Error> {
+    let page = Page::new(&config, util::PaymentType::Invoice);
 
-    let tax = (invoice.price * tax) + invoice.fees.unwrap_or(0.0);
+    let invoice = match invoices.get(&query.invoice_id) {
+        Ok(Some(invoice)) => invoice,
+        Ok(None) => return Err(Error::NotFound(page, query.invoice_id.clone())),
+        Err(error) => return Err(Error::Storage(page, error)),
+    };
 
-    HttpResponse::Ok()
-        .content_type("text/html; charset=utf-8")
-        .body(include_str!("../static/invoice.html")
-            .replace("PRICE", &format!("{:.2}", invoice.price).to_string())
-            .replace("INVOICE_ID", &invoice.invoice_id)
-            .replace(
-                "CLIENT_TOKEN_FROM_SERVER",
-                braintree.client_token().generate(Default::default()).expect("unable to get client token").value.as_str())
-            .replace("TOTAL", &format!("{:.2}", tax+invoice.price).to_string())
-            .replace("TAX", &format!("{:.2}", tax).to_string()))
+    if !invoice.is_payable() {
+        return Ok(invoice_status(&invoice).await);
+    }
+
+    render_invoice_page(&config, &gateway, &checkouts, page, &invoice, &util::Payment::default(), None).await
+}
+
+//----------------------------------------------------------------------------------------------------
+//----------------------------------------------------------------------------------------------------
+async fn render_invoice_page(
+    config : &Config,
+    gateway : &web::Data<Gateway>,
+    checkouts : &Checkouts,
+    page : Page,
+    invoice : &Invoice,
+    buyer : &util::Payment,
+    failure : Option<&gateway::Error>) -> Result<HttpResponse, Error> {
+    let totals = invoice.line_items.iter()
+        .map(|line_item| Ok(InvoiceLine{ line_item, amount: line_item.amount()? }))
+        .collect::<Result<Vec<InvoiceLine>, MoneyError>>()
+        .map_err(TaxError::from)
+        .and_then(|lines| Ok((lines, invoice.taxes_and_fees(&config.tax)?, invoice.balance(&config.tax)?)));
+    let (lines, taxes_and_fees, balance) = match totals {
+        Ok(totals) => totals,
+        Err(error) => return Err(Error::Tax(page, error)),
+    };
+
+    let client_token = gateway::client_token(gateway, config.gateway_timeout()).await
+        .map_err(|error| Error::Payment(page, error))?;
+
+    Ok(util::render(util::checkout_status(failure), &InvoicePage{
+        invoice,
+        lines: &lines,
+        due_date: invoice.due_date.as_deref().unwrap_or("On Receipt"),
+        tax: taxes_and_fees.to_string(),
+        paid: invoice.amount_paid.to_string(),
+        balance: balance.to_string(),
+        client_token,
+        checkout_token: checkouts.issue(),
+        buyer,
+        message: failure.map_or("", |failure| failure.buyer_message()),
+    }))
 }
 
+//----------------------------------------------------------------------------------------------------
+//----------------------------------------------------------------------------------------------------
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::test;
+    use crate::testing::{assert_escaped, checkout_service, invoice, TestStore, PAYLOAD};
+
+    //--------------------------------------------------------------------------------------------------
+    //--------------------------------------------------------------------------------------------------
+    #[test]
+    fn invoice_page_escapes_line_items() {
+        let mut invoice = invoice();
+        invoice.line_items[0].quantity = 2;
+        invoice.line_items[0].unit_price = Money::from_cents(1050);
+        let buyer = util::Payment::default();
+        let lines = [InvoiceLine{ line_item: &invoice.line_items[0], amount: Money::from_cents(2100) }];
+
+        let html = InvoicePage {
+            invoice: &invoice,
+            lines: &lines,
+            due_date: "On Receipt",
+            tax: "0.00".to_string(),
+            paid: "0.00".to_string(),
+            balance: "21.00".to_string(),
+            client_token: String::new(),
+            checkout_token: String::new(),
+            buyer: &buyer,
+            message: "",
+        }.render().unwrap();
+
+        assert_escaped(&html);
+        assert!(html.contains("2 x $10.50"));
+        assert!(html.contains("$21.00"));
+    }
+
+    //--------------------------------------------------------------------------------------------------
+    //--------------------------------------------------------------------------------------------------
+    //the invoice id comes from a link anyone can craft, it must not be reflected into the page
+    #[actix_rt::test]
+    async fn unknown_invoice_link_does_not_reflect_the_id() {
+        let store = TestStore::new();
+        let mut service = test::init_service(actix_web::App::new()
+            .app_data(store.config.clone())
+            .app_data(store.gateway.clone())
+            .app_data(store.invoices.clone())
+            .app_data(store.checkouts.clone())
+            .route("/quote/invoice", web::get().to(super::invoice))).await;
+
+        let uri = format!("/quote/invoice?invoice_id={}", url::form_urlencoded::byte_serialize(PAYLOAD.as_bytes()).collect::<String>());
+        let response = test::call_service(&mut service, test::TestRequest::get().uri(&uri).to_request()).await;
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+        let html = String::from_utf8(test::read_body(response).await.to_vec()).unwrap();
+        assert!(!html.contains("<script>"));
+        assert!(!html.contains("Jerry's"));
+    }
+
+    //--------------------------------------------------------------------------------------------------
+    //--------------------------------------------------------------------------------------------------
+    async fn pay(nonce : &str) -> (TestStore, StatusCode) {
+        let store = TestStore::new();
+        let mut service = checkout_service!(store);
+
+        let response = test::call_service(&mut service, store.checkout("/quote/process_invoice", &[("invoice_id", "1001")], nonce).to_request()).await;
+        let status = response.status();
+        (store, status)
+    }
+
+    //--------------------------------------------------------------------------------------------------
+    //--------------------------------------------------------------------------------------------------
+    #[actix_rt::test]
+    async fn paid_invoice_records_the_transaction() {
+        let (store, status) = pay("fake-valid-nonce").await;
+        assert_eq!(status, StatusCode::OK);
+
+        let invoice = store.invoices.get("1001").unwrap().unwrap();
+        assert_eq!(invoice.status, InvoiceStatus::Paid);
+        assert_eq!(invoice.amount_paid, Money::from_cents(25000));
+        assert_eq!(invoice.transaction_ids.len(), 1);
+    }
+
+    //--------------------------------------------------------------------------------------------------
+    //--------------------------------------------------------------------------------------------------
+    #[actix_rt::test]
+    async fn declined_invoice_can_be_paid_again() {
+        let (store, status) = pay("fake-processor-declined-visa-nonce").await;
+        assert_eq!(status, StatusCode::PAYMENT_REQUIRED);
+
+        let invoice = store.invoices.get("1001").unwrap().unwrap();
+        assert_eq!(invoice.status, InvoiceStatus::Sent);
+        assert!(invoice.amount_paid.is_zero());
+    }
+
+    //--------------------------------------------------------------------------------------------------
+    //--------------------------------------------------------------------------------------------------
+    #[actix_rt::test]
+    async fn timed_out_invoice_stays_held_until_it_is_resolved() {
+        let (store, status) = pay("fake-gateway-timeout-nonce").await;
+        assert_eq!(status, StatusCode::ACCEPTED);
+        assert_eq!(store.invoices.get("1001").unwrap().unwrap().status, InvoiceStatus::Processing);
+
+        let charges = store.pending.charges();
+        assert_eq!(charges.len(), 1);
+        assert!(matches!(&charges.values().next().unwrap().hold, Hold::Invoice{ invoice_id } if invoice_id == "1001"));
+    }
+}
@@ -0,0 +1,675 @@
+use chrono::{DateTime, NaiveDate, Utc};
+use log::{info};
+use rusqlite::types::Type;
+use rusqlite::{params, Connection};
+use std::collections::BTreeMap;
+use std::sync::{Mutex};
+
+use crate::fundraise::Fundraiser;
+use crate::ledger::{LedgerEntry, LedgerStatus};
+use crate::money::Money;
+use crate::pending::PendingCharge;
+use crate::promo::{PromoCode, Redemption};
+use crate::quote::{Invoice, InvoiceStatus, LineItem};
+use crate::storage::{DonationRecord, Error, Order, Storage};
+use crate::store::Item;
+
+//each entry upgrades the schema by one version, never edit an entry once it has shipped
+const MIGRATIONS : &[&str] = &[
+    "CREATE TABLE items (
+        id TEXT PRIMARY KEY,
+        name TEXT NOT NULL,
+        formname TEXT NOT NULL,
+        number_of_items INTEGER,
+        price REAL NOT NULL,
+        discount REAL NOT NULL,
+        image TEXT NOT NULL,
+        dates TEXT NOT NULL
+    );
+    CREATE TABLE fundraisers (
+        id TEXT PRIMARY KEY,
+        name TEXT NOT NULL,
+        formname TEXT NOT NULL,
+        goal REAL NOT NULL,
+        amount_raised REAL NOT NULL,
+        image TEXT NOT NULL,
+        description TEXT NOT NULL
+    );
+    CREATE TABLE invoices (
+        id TEXT PRIMARY KEY,
+        due_date TEXT,
+        disable_sales_tax INTEGER NOT NULL,
+        fees REAL NOT NULL,
+        status TEXT NOT NULL,
+        amount_paid REAL NOT NULL,
+        transaction_ids TEXT NOT NULL
+    );
+    CREATE TABLE invoice_line_items (
+        invoice_id TEXT NOT NULL REFERENCES invoices(id) ON DELETE CASCADE,
+        position INTEGER NOT NULL,
+        description TEXT NOT NULL,
+        quantity INTEGER NOT NULL,
+        unit_price REAL NOT NULL,
+        PRIMARY KEY (invoice_id, position)
+    );
+    CREATE TABLE orders (
+        id INTEGER PRIMARY KEY AUTOINCREMENT,
+        item TEXT NOT NULL,
+        amount REAL NOT NULL,
+        email TEXT NOT NULL,
+        transaction_id TEXT NOT NULL,
+        created_at TEXT NOT NULL DEFAULT CURRENT_TIMESTAMP
+    );
+    CREATE TABLE donations (
+        id INTEGER PRIMARY KEY AUTOINCREMENT,
+        fundraiser TEXT NOT NULL,
+        amount REAL NOT NULL,
+        email TEXT NOT NULL,
+        transaction_id TEXT NOT NULL,
+        created_at TEXT NOT NULL DEFAULT CURRENT_TIMESTAMP
+    );",
+    "CREATE TABLE ledger (
+        id INTEGER PRIMARY KEY AUTOINCREMENT,
+        timestamp TEXT NOT NULL,
+        payment_type TEXT NOT NULL,
+        description TEXT NOT NULL,
+        amount REAL NOT NULL,
+        first_name TEXT NOT NULL,
+        last_name TEXT NOT NULL,
+        email TEXT NOT NULL,
+        transaction_id TEXT,
+        status TEXT NOT NULL,
+        error TEXT
+    );
+    CREATE INDEX ledger_email ON ledger (email);",
+    "ALTER TABLE items ADD COLUMN archived INTEGER NOT NULL DEFAULT 0;
+    ALTER TABLE items ADD COLUMN position INTEGER NOT NULL DEFAULT 0;
+    ALTER TABLE fundraisers ADD COLUMN archived INTEGER NOT NULL DEFAULT 0;
+    ALTER TABLE fundraisers ADD COLUMN position INTEGER NOT NULL DEFAULT 0;",
+    "ALTER TABLE items ADD COLUMN taxable INTEGER NOT NULL DEFAULT 0;
+    ALTER TABLE invoice_line_items ADD COLUMN taxable INTEGER NOT NULL DEFAULT 1;
+    ALTER TABLE invoices ADD COLUMN jurisdiction TEXT;
+    ALTER TABLE invoices ADD COLUMN tax_exempt_certificate TEXT;
+    ALTER TABLE ledger ADD COLUMN tax TEXT;",
+    "CREATE TABLE promo_codes (
+        code TEXT PRIMARY KEY,
+        discount TEXT NOT NULL,
+        item TEXT,
+        expires TEXT,
+        max_uses INTEGER,
+        max_uses_per_email INTEGER
+    );
+    CREATE TABLE promo_redemptions (
+        code TEXT NOT NULL REFERENCES promo_codes(code) ON DELETE CASCADE,
+        position INTEGER NOT NULL,
+        email TEXT NOT NULL,
+        transaction_id TEXT NOT NULL,
+        timestamp TEXT NOT NULL,
+        PRIMARY KEY (code, position)
+    );
+    ALTER TABLE orders ADD COLUMN promo_code TEXT;",
+    "ALTER TABLE items ADD COLUMN tier_prices TEXT NOT NULL DEFAULT '{}';
+    ALTER TABLE orders ADD COLUMN tier TEXT NOT NULL DEFAULT 'public';",
+    "ALTER TABLE orders ADD COLUMN quantity INTEGER NOT NULL DEFAULT 1;",
+    //amounts were REAL dollars, they become INTEGER cents so no float ever touches them
+    "ALTER TABLE items ADD COLUMN price_cents INTEGER NOT NULL DEFAULT 0;
+    UPDATE items SET price_cents = CAST(ROUND(price * 100) AS INTEGER);
+    ALTER TABLE items DROP COLUMN price;
+    ALTER TABLE items RENAME COLUMN price_cents TO price;
+    ALTER TABLE items ADD COLUMN discount_cents INTEGER NOT NULL DEFAULT 0;
+    UPDATE items SET discount_cents = CAST(ROUND(discount * 100) AS INTEGER);
+    ALTER TABLE items DROP COLUMN discount;
+    ALTER TABLE items RENAME COLUMN discount_cents TO discount;
+    ALTER TABLE fundraisers ADD COLUMN goal_cents INTEGER NOT NULL DEFAULT 0;
+    UPDATE fundraisers SET goal_cents = CAST(ROUND(goal * 100) AS INTEGER);
+    ALTER TABLE fundraisers DROP COLUMN goal;
+    ALTER TABLE fundraisers RENAME COLUMN goal_cents TO goal;
+    ALTER TABLE fundraisers ADD COLUMN amount_raised_cents INTEGER NOT NULL DEFAULT 0;
+    UPDATE fundraisers SET amount_raised_cents = CAST(ROUND(amount_raised * 100) AS INTEGER);
+    ALTER TABLE fundraisers DROP COLUMN amount_raised;
+    ALTER TABLE fundraisers RENAME COLUMN amount_raised_cents TO amount_raised;
+    ALTER TABLE invoices ADD COLUMN fees_cents INTEGER NOT NULL DEFAULT 0;
+    UPDATE invoices SET fees_cents = CAST(ROUND(fees * 100) AS INTEGER);
+    ALTER TABLE invoices DROP COLUMN fees;
+    ALTER TABLE invoices RENAME COLUMN fees_cents TO fees;
+    ALTER TABLE invoices ADD COLUMN amount_paid_cents INTEGER NOT NULL DEFAULT 0;
+    UPDATE invoices SET amount_paid_cents = CAST(ROUND(amount_paid * 100) AS INTEGER);
+    ALTER TABLE invoices DROP COLUMN amount_paid;
+    ALTER TABLE invoices RENAME COLUMN amount_paid_cents TO amount_paid;
+    ALTER TABLE invoice_line_items ADD COLUMN unit_price_cents INTEGER NOT NULL DEFAULT 0;
+    UPDATE invoice_line_items SET unit_price_cents = CAST(ROUND(unit_price * 100) AS INTEGER);
+    ALTER TABLE invoice_line_items DROP COLUMN unit_price;
+    ALTER TABLE invoice_line_items RENAME COLUMN unit_price_cents TO unit_price;
+    ALTER TABLE orders ADD COLUMN amount_cents INTEGER NOT NULL DEFAULT 0;
+    UPDATE orders SET amount_cents = CAST(ROUND(amount * 100) AS INTEGER);
+    ALTER TABLE orders DROP COLUMN amount;
+    ALTER TABLE orders RENAME COLUMN amount_cents TO amount;
+    ALTER TABLE donations ADD COLUMN amount_cents INTEGER NOT NULL DEFAULT 0;
+    UPDATE donations SET amount_cents = CAST(ROUND(amount * 100) AS INTEGER);
+    ALTER TABLE donations DROP COLUMN amount;
+    ALTER TABLE donations RENAME COLUMN amount_cents TO amount;
+    ALTER TABLE ledger ADD COLUMN amount_cents INTEGER NOT NULL DEFAULT 0;
+    UPDATE ledger SET amount_cents = CAST(ROUND(amount * 100) AS INTEGER);
+    ALTER TABLE ledger DROP COLUMN amount;
+    ALTER TABLE ledger RENAME COLUMN amount_cents TO amount;",
+    //a pending charge is only ever read back whole so it is kept as json
+    "CREATE TABLE pending_charges (
+        id TEXT PRIMARY KEY,
+        charge TEXT NOT NULL
+    );",
+];
+
+//----------------------------------------------------------------------------------------------------
+//----------------------------------------------------------------------------------------------------
+pub struct SqliteStorage {
+    connection : Mutex<Connection>,
+}
+
+//----------------------------------------------------------------------------------------------------
+//----------------------------------------------------------------------------------------------------
+fn migrate(connection : &mut Connection) -> Result<(), Error> {
+    let version : i64 = connection.query_row("PRAGMA user_version", [], |row| row.get(0))?;
+
+    for (index, migration) in MIGRATIONS.iter().enumerate().skip(version as usize) {
+        info!("applying database migration {}\n", index + 1);
+        let transaction = connection.transaction()?;
+        transaction.execute_batch(migration)?;
+        transaction.execute_batch(&format!("PRAGMA user_version = {}", index + 1))?;
+        transaction.commit()?;
+    }
+    Ok(())
+}
+
+//----------------------------------------------------------------------------------------------------
+//----------------------------------------------------------------------------------------------------
+//a column that doesn't parse fails the load, a default would be saved back over what was there
+fn json_column<T>(index : usize, parsed : serde_json::Result<T>) -> rusqlite::Result<T> {
+    parsed.map_err(|error| rusqlite::Error::FromSqlConversionFailure(index, Type::Text, Box::new(error)))
+}
+
+//----------------------------------------------------------------------------------------------------
+//----------------------------------------------------------------------------------------------------
+fn write_items(connection : &Connection, items : &BTreeMap<String, Item>) -> Result<(), Error> {
+    connection.execute("DELETE FROM items", [])?;
+    for (id, item) in items.iter() {
+        connection.execute(
+            "INSERT INTO items (id, name, formname, number_of_items, price, discount, image, dates, archived, position, taxable, tier_prices)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12)",
+            params![
+                id,
+                item.name,
+                item.formname,
+                item.number_of_items,
+                item.price.cents(),
+                item.discount.cents(),
+                item.image,
+                item.dates,
+                item.archived,
+                item.position,
+                item.taxable,
+                serde_json::to_string(&item.tier_prices).unwrap_or_default()])?;
+    }
+    Ok(())
+}
+
+//----------------------------------------------------------------------------------------------------
+//----------------------------------------------------------------------------------------------------
+fn write_fundraisers(connection : &Connection, fundraisers : &BTreeMap<String, Fundraiser>) -> Result<(), Error> {
+    connection.execute("DELETE FROM fundraisers", [])?;
+    for (id, fundraiser) in fundraisers.iter() {
+        connection.execute(
+            "INSERT INTO fundraisers (id, name, formname, goal, amount_raised, image, description, archived, position)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)",
+            params![id, fundraiser.name, fundraiser.formname, fundraiser.goal.cents(), fundraiser.amount_raised.cents(), fundraiser.image, fundraiser.description, fundraiser.archived, fundraiser.position])?;
+    }
+    Ok(())
+}
+
+//----------------------------------------------------------------------------------------------------
+//----------------------------------------------------------------------------------------------------
+fn write_order(connection : &Connection, order : &Order) -> Result<(), Error> {
+    connection.execute(
+        "INSERT INTO orders (item, amount, email, transaction_id, promo_code, tier, quantity) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+        params![order.item, order.amount.cents(), order.email, order.transaction_id, order.promo_code, order.tier.as_str(), order.quantity])?;
+    Ok(())
+}
+
+//----------------------------------------------------------------------------------------------------
+//----------------------------------------------------------------------------------------------------
+fn write_donation(connection : &Connection, donation : &DonationRecord) -> Result<(), Error> {
+    connection.execute(
+        "INSERT INTO donations (fundraiser, amount, email, transaction_id) VALUES (?1, ?2, ?3, ?4)",
+        params![donation.fundraiser, donation.amount.cents(), donation.email, donation.transaction_id])?;
+    Ok(())
+}
+
+//----------------------------------------------------------------------------------------------------
+//----------------------------------------------------------------------------------------------------
+fn write_invoices(connection : &Connection, invoices : &BTreeMap<String, Invoice>) -> Result<(), Error> {
+    connection.execute("DELETE FROM invoices", [])?;
+    for (id, invoice) in invoices.iter() {
+        let status = serde_json::to_value(&invoice.status).unwrap_or_default();
+        connection.execute(
+            "INSERT INTO invoices (id, due_date, disable_sales_tax, fees, status, amount_paid, transaction_ids, jurisdiction, tax_exempt_certificate)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)",
+            params![
+                id,
+                invoice.due_date,
+                invoice.disable_sales_tax,
+                invoice.fees.cents(),
+                status.as_str().unwrap_or("draft"),
+                invoice.amount_paid.cents(),
+                serde_json::to_string(&invoice.transaction_ids).unwrap_or_default(),
+                invoice.jurisdiction,
+                invoice.tax_exempt_certificate])?;
+
+        for (position, line_item) in invoice.line_items.iter().enumerate() {
+            connection.execute(
+                "INSERT INTO invoice_line_items (invoice_id, position, description, quantity, unit_price, taxable)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+                params![id, position as i64, line_item.description, line_item.quantity, line_item.unit_price.cents(), line_item.taxable])?;
+        }
+    }
+    Ok(())
+}
+
+//----------------------------------------------------------------------------------------------------
+//----------------------------------------------------------------------------------------------------
+fn write_promo_codes(connection : &Connection, promo_codes : &BTreeMap<String, PromoCode>) -> Result<(), Error> {
+    connection.execute("DELETE FROM promo_codes", [])?;
+    for (code, promo) in promo_codes.iter() {
+        //the discount is an enum that is only read back whole so it is kept as json
+        connection.execute(
+            "INSERT INTO promo_codes (code, discount, item, expires, max_uses, max_uses_per_email)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+            params![
+                code,
+                serde_json::to_string(&promo.discount).unwrap_or_default(),
+                promo.item,
+                promo.expires,
+                promo.max_uses,
+                promo.max_uses_per_email])?;
+
+        for (position, redemption) in promo.redemptions.iter().enumerate() {
+            connection.execute(
+                "INSERT INTO promo_redemptions (code, position, email, transaction_id, timestamp)
+                 VALUES (?1, ?2, ?3, ?4, ?5)",
+                params![code, position as i64, redemption.email, redemption.transaction_id, redemption.timestamp])?;
+        }
+    }
+    Ok(())
+}
+
+//----------------------------------------------------------------------------------------------------
+//----------------------------------------------------------------------------------------------------
+fn write_pending(connection : &Connection, charges : &BTreeMap<String, PendingCharge>) -> Result<(), Error> {
+    connection.execute("DELETE FROM pending_charges", [])?;
+    for (id, charge) in charges.iter() {
+        let charge = serde_json::to_string(charge)
+            .map_err(|error| rusqlite::Error::ToSqlConversionFailure(Box::new(error)))?;
+        connection.execute("INSERT INTO pending_charges (id, charge) VALUES (?1, ?2)", params![id, charge])?;
+    }
+    Ok(())
+}
+
+//------------------------------------------------------------------------------------------------------
+//------------------------------------------------------------------------------------------------------
+impl SqliteStorage {
+
+    //--------------------------------------------------------------------------------------------------
+    //--------------------------------------------------------------------------------------------------
+    pub fn open(path : &str) -> Result<SqliteStorage, Error> {
+        let mut connection = Connection::open(path)?;
+        connection.execute_batch("PRAGMA foreign_keys = ON")?;
+        migrate(&mut connection)?;
+        Ok(SqliteStorage { connection: Mutex::new(connection) })
+    }
+
+    //--------------------------------------------------------------------------------------------------
+    //--------------------------------------------------------------------------------------------------
+    pub fn import(&self, source : &dyn Storage) -> Result<(), Error> {
+        let items = source.load_items()?;
+        let fundraisers = source.load_fundraisers()?;
+        let invoices = source.load_invoices()?;
+        let promo_codes = source.load_promo_codes()?;
+        let ledger = source.load_ledger()?;
+        let pending = source.load_pending()?;
+
+        {
+            let mut connection = self.connection.lock().unwrap();
+            let transaction = connection.transaction()?;
+            write_items(&transaction, &items)?;
+            write_fundraisers(&transaction, &fundraisers)?;
+            write_invoices(&transaction, &invoices)?;
+            write_promo_codes(&transaction, &promo_codes)?;
+            write_pending(&transaction, &pending)?;
+            transaction.commit()?;
+        }
+
+        for entry in ledger.iter() {
+            self.append_ledger(entry)?;
+        }
+
+        info!("imported {} items, {} fundraisers, {} invoices, {} promo codes, {} pending charges and {} ledger entries\n",
+              items.len(), fundraisers.len(), invoices.len(), promo_codes.len(), pending.len(), ledger.len());
+        Ok(())
+    }
+}
+
+//------------------------------------------------------------------------------------------------------
+//------------------------------------------------------------------------------------------------------
+impl Storage for SqliteStorage {
+
+    //--------------------------------------------------------------------------------------------------
+    //--------------------------------------------------------------------------------------------------
+    fn load_items(&self) -> Result<BTreeMap<String, Item>, Error> {
+        let connection = self.connection.lock().unwrap();
+        let mut statement = connection.prepare(
+            "SELECT id, name, formname, number_of_items, price, discount, image, dates, archived, position, taxable, tier_prices FROM items")?;
+
+        let items = statement.query_map([], |row| {
+            let tier_prices : String = row.get(11)?;
+            Ok((row.get::<_, String>(0)?, Item {
+                name: row.get(1)?,
+                formname: row.get(2)?,
+                number_of_items: row.get(3)?,
+                price: Money::from_cents(row.get(4)?),
+                discount: Money::from_cents(row.get(5)?),
+                image: row.get(6)?,
+                dates: row.get(7)?,
+                archived: row.get(8)?,
+                position: row.get(9)?,
+                taxable: row.get(10)?,
+                tier_prices: json_column(11, serde_json::from_str(&tier_prices))?,
+            }))
+        })?.collect::<Result<BTreeMap<String, Item>, rusqlite::Error>>()?;
+
+        Ok(items)
+    }
+
+    //--------------------------------------------------------------------------------------------------
+    //--------------------------------------------------------------------------------------------------
+    fn save_items(&self, items : &BTreeMap<String, Item>) -> Result<(), Error> {
+        let mut connection = self.connection.lock().unwrap();
+        let transaction = connection.transaction()?;
+        write_items(&transaction, items)?;
+        Ok(transaction.commit()?)
+    }
+
+    //--------------------------------------------------------------------------------------------------
+    //--------------------------------------------------------------------------------------------------
+    fn load_fundraisers(&self) -> Result<BTreeMap<String, Fundraiser>, Error> {
+        let connection = self.connection.lock().unwrap();
+        let mut statement = connection.prepare(
+            "SELECT id, name, formname, goal, amount_raised, image, description, archived, position FROM fundraisers")?;
+
+        let fundraisers = statement.query_map([], |row| {
+            Ok((row.get::<_, String>(0)?, Fundraiser {
+                name: row.get(1)?,
+                formname: row.get(2)?,
+                goal: Money::from_cents(row.get(3)?),
+                amount_raised: Money::from_cents(row.get(4)?),
+                image: row.get(5)?,
+                description: row.get(6)?,
+                archived: row.get(7)?,
+                position: row.get(8)?,
+            }))
+        })?.collect::<Result<BTreeMap<String, Fundraiser>, rusqlite::Error>>()?;
+
+        Ok(fundraisers)
+    }
+
+    //--------------------------------------------------------------------------------------------------
+    //--------------------------------------------------------------------------------------------------
+    fn save_fundraisers(&self, fundraisers : &BTreeMap<String, Fundraiser>) -> Result<(), Error> {
+        let mut connection = self.connection.lock().unwrap();
+        let transaction = connection.transaction()?;
+        write_fundraisers(&transaction, fundraisers)?;
+        Ok(transaction.commit()?)
+    }
+
+    //--------------------------------------------------------------------------------------------------
+    //--------------------------------------------------------------------------------------------------
+    fn load_invoices(&self) -> Result<BTreeMap<String, Invoice>, Error> {
+        let connection = self.connection.lock().unwrap();
+        let mut statement = connection.prepare(
+            "SELECT id, due_date, disable_sales_tax, fees, status, amount_paid, transaction_ids, jurisdiction, tax_exempt_certificate FROM invoices")?;
+
+        let mut invoices = statement.query_map([], |row| {
+            let status : String = row.get(4)?;
+            let transaction_ids : String = row.get(6)?;
+            Ok((row.get::<_, String>(0)?, Invoice {
+                invoice_id: row.get(0)?,
+                line_items: Vec::new(),
+                due_date: row.get(1)?,
+                disable_sales_tax: row.get(2)?,
+                fees: Money::from_cents(row.get(3)?),
+                status: json_column(4, serde_json::from_value::<InvoiceStatus>(serde_json::Value::String(status)))?,
+                amount_paid: Money::from_cents(row.get(5)?),
+                transaction_ids: json_column(6, serde_json::from_str(&transaction_ids))?,
+                jurisdiction: row.get(7)?,
+                tax_exempt_certificate: row.get(8)?,
+            }))
+        })?.collect::<Result<BTreeMap<String, Invoice>, rusqlite::Error>>()?;
+
+        let mut statement = connection.prepare(
+            "SELECT invoice_id, description, quantity, unit_price, taxable FROM invoice_line_items ORDER BY invoice_id, position")?;
+
+        let line_items = statement.query_map([], |row| {
+            Ok((row.get::<_, String>(0)?, LineItem {
+                description: row.get(1)?,
+                quantity: row.get(2)?,
+                unit_price: Money::from_cents(row.get(3)?),
+                taxable: row.get(4)?,
+            }))
+        })?;
+
+        for line_item in line_items {
+            let (invoice_id, line_item) = line_item?;
+            if let Some(invoice) = invoices.get_mut(&invoice_id) {
+                invoice.line_items.push(line_item);
+            }
+        }
+
+        Ok(invoices)
+    }
+
+    //--------------------------------------------------------------------------------------------------
+    //--------------------------------------------------------------------------------------------------
+    fn save_invoices(&self, invoices : &BTreeMap<String, Invoice>) -> Result<(), Error> {
+        let mut connection = self.connection.lock().unwrap();
+        let transaction = connection.transaction()?;
+        write_invoices(&transaction, invoices)?;
+        Ok(transaction.commit()?)
+    }
+
+    //--------------------------------------------------------------------------------------------------
+    //--------------------------------------------------------------------------------------------------
+    fn load_promo_codes(&self) -> Result<BTreeMap<String, PromoCode>, Error> {
+        let connection = self.connection.lock().unwrap();
+        let mut statement = connection.prepare(
+            "SELECT code, discount, item, expires, max_uses, max_uses_per_email FROM promo_codes")?;
+
+        let mut promo_codes = statement.query_map([], |row| {
+            let discount : String = row.get(1)?;
+            let discount = json_column(1, serde_json::from_str(&discount))?;
+            Ok((row.get::<_, String>(0)?, PromoCode {
+                discount,
+                item: row.get(2)?,
+                expires: row.get::<_, Option<NaiveDate>>(3)?,
+                max_uses: row.get(4)?,
+                max_uses_per_email: row.get(5)?,
+                redemptions: Vec::new(),
+            }))
+        })?.collect::<Result<BTreeMap<String, PromoCode>, rusqlite::Error>>()?;
+
+        let mut statement = connection.prepare(
+            "SELECT code, email, transaction_id, timestamp FROM promo_redemptions ORDER BY code, position")?;
+
+        let redemptions = statement.query_map([], |row| {
+            Ok((row.get::<_, String>(0)?, Redemption {
+                email: row.get(1)?,
+                transaction_id: row.get(2)?,
+                timestamp: row.get::<_, DateTime<Utc>>(3)?,
+            }))
+        })?;
+
+        for redemption in redemptions {
+            let (code, redemption) = redemption?;
+            if let Some(promo) = promo_codes.get_mut(&code) {
+                promo.redemptions.push(redemption);
+            }
+        }
+
+        Ok(promo_codes)
+    }
+
+    //--------------------------------------------------------------------------------------------------
+    //--------------------------------------------------------------------------------------------------
+    fn save_promo_codes(&self, promo_codes : &BTreeMap<String, PromoCode>) -> Result<(), Error> {
+        let mut connection = self.connection.lock().unwrap();
+        let transaction = connection.transaction()?;
+        write_promo_codes(&transaction, promo_codes)?;
+        Ok(transaction.commit()?)
+    }
+
+    //--------------------------------------------------------------------------------------------------
+    //--------------------------------------------------------------------------------------------------
+    fn record_order(&self, order : &Order) -> Result<(), Error> {
+        write_order(&self.connection.lock().unwrap(), order)
+    }
+
+    //--------------------------------------------------------------------------------------------------
+    //--------------------------------------------------------------------------------------------------
+    fn record_donation(&self, donation : &DonationRecord) -> Result<(), Error> {
+        write_donation(&self.connection.lock().unwrap(), donation)
+    }
+
+    //--------------------------------------------------------------------------------------------------
+    //--------------------------------------------------------------------------------------------------
+    fn append_ledger(&self, entry : &LedgerEntry) -> Result<(), Error> {
+        let status = serde_json::to_value(&entry.status).unwrap_or_default();
+        //the tax breakdown is only read back whole so it is kept as json
+        let tax = entry.tax.as_ref().and_then(|tax| serde_json::to_string(tax).ok());
+        self.connection.lock().unwrap().execute(
+            "INSERT INTO ledger (timestamp, payment_type, description, amount, first_name, last_name, email, transaction_id, status, error, tax)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11)",
+            params![
+                entry.timestamp,
+                entry.payment_type,
+                entry.description,
+                entry.amount.cents(),
+                entry.first_name,
+                entry.last_name,
+                entry.email,
+                entry.transaction_id,
+                status.as_str().unwrap_or("failed"),
+                entry.error,
+                tax])?;
+        Ok(())
+    }
+
+    //--------------------------------------------------------------------------------------------------
+    //--------------------------------------------------------------------------------------------------
+    fn load_ledger(&self) -> Result<Vec<LedgerEntry>, Error> {
+        let connection = self.connection.lock().unwrap();
+        let mut statement = connection.prepare(
+            "SELECT timestamp, payment_type, description, amount, first_name, last_name, email, transaction_id, status, error, tax
+             FROM ledger ORDER BY id")?;
+
+        let entries = statement.query_map([], |row| {
+            let status : String = row.get(8)?;
+            let tax : Option<String> = row.get(10)?;
+            Ok(LedgerEntry {
+                timestamp: row.get::<_, DateTime<Utc>>(0)?,
+                payment_type: row.get(1)?,
+                description: row.get(2)?,
+                amount: Money::from_cents(row.get(3)?),
+                first_name: row.get(4)?,
+                last_name: row.get(5)?,
+                email: row.get(6)?,
+                transaction_id: row.get(7)?,
+                status: json_column(8, serde_json::from_value::<LedgerStatus>(serde_json::Value::String(status)))?,
+                error: row.get(9)?,
+                tax: tax.map(|tax| json_column(10, serde_json::from_str(&tax))).transpose()?,
+            })
+        })?.collect::<Result<Vec<LedgerEntry>, rusqlite::Error>>()?;
+
+        Ok(entries)
+    }
+
+    //--------------------------------------------------------------------------------------------------
+    //--------------------------------------------------------------------------------------------------
+    fn load_pending(&self) -> Result<BTreeMap<String, PendingCharge>, Error> {
+        let connection = self.connection.lock().unwrap();
+        let mut statement = connection.prepare("SELECT id, charge FROM pending_charges")?;
+
+        let charges = statement.query_map([], |row| {
+            let charge : String = row.get(1)?;
+            let charge = json_column(1, serde_json::from_str(&charge))?;
+            Ok((row.get::<_, String>(0)?, charge))
+        })?.collect::<Result<BTreeMap<String, PendingCharge>, rusqlite::Error>>()?;
+
+        Ok(charges)
+    }
+
+    //--------------------------------------------------------------------------------------------------
+    //--------------------------------------------------------------------------------------------------
+    fn save_pending(&self, charges : &BTreeMap<String, PendingCharge>) -> Result<(), Error> {
+        let mut connection = self.connection.lock().unwrap();
+        let transaction = connection.transaction()?;
+        write_pending(&transaction, charges)?;
+        Ok(transaction.commit()?)
+    }
+
+    //--------------------------------------------------------------------------------------------------
+    //--------------------------------------------------------------------------------------------------
+    fn commit_orders(&self, items : &BTreeMap<String, Item>, orders : &[Order]) -> Result<(), Error> {
+        let mut connection = self.connection.lock().unwrap();
+        let transaction = connection.transaction()?;
+        write_items(&transaction, items)?;
+        for order in orders.iter() {
+            write_order(&transaction, order)?;
+        }
+        Ok(transaction.commit()?)
+    }
+
+    //--------------------------------------------------------------------------------------------------
+    //--------------------------------------------------------------------------------------------------
+    fn commit_donation(&self, fundraisers : &BTreeMap<String, Fundraiser>, donation : &DonationRecord) -> Result<(), Error> {
+        let mut connection = self.connection.lock().unwrap();
+        let transaction = connection.transaction()?;
+        write_fundraisers(&transaction, fundraisers)?;
+        write_donation(&transaction, donation)?;
+        Ok(transaction.commit()?)
+    }
+}
+
+//----------------------------------------------------------------------------------------------------
+//----------------------------------------------------------------------------------------------------
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{invoice, item};
+
+    //--------------------------------------------------------------------------------------------------
+    //--------------------------------------------------------------------------------------------------
+    #[test]
+    fn bad_columns_fail_the_load() {
+        let storage = SqliteStorage::open(":memory:").unwrap();
+        storage.save_items(&BTreeMap::from([("intro".to_string(), item())])).unwrap();
+        storage.save_invoices(&BTreeMap::from([("1001".to_string(), invoice())])).unwrap();
+        assert_eq!(storage.load_items().unwrap().len(), 1);
+        assert_eq!(storage.load_invoices().unwrap().len(), 1);
+
+        let corrupt = |sql : &str| { storage.connection.lock().unwrap().execute(sql, []).unwrap(); };
+        corrupt("UPDATE items SET tier_prices = '{\"member\": '");
+        assert!(storage.load_items().is_err());
+
+        corrupt("UPDATE invoices SET status = 'refunded'");
+        assert!(storage.load_invoices().is_err());
+
+        corrupt("UPDATE invoices SET status = 'sent', transaction_ids = 'txn1'");
+        assert!(storage.load_invoices().is_err());
+    }
+}
@@ -0,0 +1,658 @@
+use actix_web::http::StatusCode;
+use log::{error, info};
+use serde::{Serialize, Deserialize};
+use std::collections::{BTreeMap, HashMap};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+
+use crate::cart::Cart;
+use crate::fundraise::Fundraiser;
+use crate::money::Money;
+use crate::pending::{Hold, PendingCharge};
+use crate::promo::{PromoCode, PromoError, PromoReservation, Promotions};
+use crate::storage::{self, DonationRecord, Order, Storage};
+use crate::store::Item;
+use crate::util;
+
+//----------------------------------------------------------------------------------------------------
+//----------------------------------------------------------------------------------------------------
+#[derive(Deserialize,Debug, Serialize, Clone)]
+pub struct Reservation {
+    pub seats : Vec<(String, i32)>,
+}
+
+#[derive(Debug)]
+pub enum ReservationError {
+    UnknownItem,
+    Archived,
+    SoldOut,
+}
+
+//----------------------------------------------------------------------------------------------------
+//----------------------------------------------------------------------------------------------------
+pub trait Listing {
+    fn position(&self) -> i32;
+
+    fn set_position(&mut self, position : i32);
+
+    fn archived(&self) -> bool;
+
+    fn set_archived(&mut self, archived : bool);
+}
+
+struct Inventory {
+    items : BTreeMap<String, Item>,
+    reserved : HashMap<String, i32>,
+}
+
+//----------------------------------------------------------------------------------------------------
+//----------------------------------------------------------------------------------------------------
+// writes one collection after its lock is let go so a slow disk never holds up the store, a snapshot
+// older than the one already written is not written over it
+#[derive(Default)]
+struct Saver {
+    changes : AtomicU64,
+    saved : Mutex<u64>,
+}
+
+//----------------------------------------------------------------------------------------------------
+//----------------------------------------------------------------------------------------------------
+pub struct State {
+    storage : Arc<dyn Storage>,
+    inventory : Mutex<Inventory>,
+    fundraisers : Mutex<BTreeMap<String, Fundraiser>>,
+    promotions : Mutex<Promotions>,
+    carts : Mutex<HashMap<String, Cart>>,
+    items_saver : Saver,
+    fundraisers_saver : Saver,
+    promo_codes_saver : Saver,
+    //sales already charged that could not be written, tried again with the next one
+    unsaved_orders : Mutex<Vec<Order>>,
+    unsaved_donations : Mutex<Vec<DonationRecord>>,
+}
+
+//----------------------------------------------------------------------------------------------------
+//----------------------------------------------------------------------------------------------------
+fn ordered_keys<T : Listing>(entries : &BTreeMap<String, T>) -> Vec<String> {
+    let mut keys : Vec<String> = entries.keys().cloned().collect();
+    keys.sort_by_key(|key| entries[key].position());
+    keys
+}
+
+//----------------------------------------------------------------------------------------------------
+//----------------------------------------------------------------------------------------------------
+pub fn sorted<T : Listing + Clone>(entries : &BTreeMap<String, T>) -> Vec<(String, T)> {
+    ordered_keys(entries).into_iter()
+        .map(|key| { let entry = entries[&key].clone(); (key, entry) })
+        .collect()
+}
+
+//----------------------------------------------------------------------------------------------------
+//----------------------------------------------------------------------------------------------------
+pub fn listed<T : Listing + Clone>(entries : &BTreeMap<String, T>) -> Vec<(String, T)> {
+    sorted(entries).into_iter().filter(|(_, entry)| !entry.archived()).collect()
+}
+
+//----------------------------------------------------------------------------------------------------
+//----------------------------------------------------------------------------------------------------
+fn move_entry<T : Listing>(entries : &mut BTreeMap<String, T>, key : &str, offset : i32) -> bool {
+    let mut keys = ordered_keys(entries);
+
+    let index = match keys.iter().position(|entry_key| entry_key == key) {
+        Some(index) => index as i32,
+        None => return false,
+    };
+
+    //the first entry can't go up or the last down, the key was still found
+    if index + offset < 0 || index + offset >= keys.len() as i32 {
+        return true;
+    }
+
+    keys.swap(index as usize, (index + offset) as usize);
+    for (position, key) in keys.iter().enumerate() {
+        if let Some(entry) = entries.get_mut(key) {
+            entry.set_position(position as i32);
+        }
+    }
+    true
+}
+
+//------------------------------------------------------------------------------------------------------
+//------------------------------------------------------------------------------------------------------
+impl Inventory {
+
+    //--------------------------------------------------------------------------------------------------
+    //--------------------------------------------------------------------------------------------------
+    fn reserved(&self, item_name : &str) -> i32 {
+        *self.reserved.get(item_name).unwrap_or(&0)
+    }
+
+    //--------------------------------------------------------------------------------------------------
+    //--------------------------------------------------------------------------------------------------
+    fn available(&self, item_name : &str) -> Option<Item> {
+        let mut item = self.items.get(item_name)?.clone();
+        if let Some(number_of_items) = item.number_of_items.as_mut() {
+            *number_of_items -= self.reserved(item_name);
+        }
+        Some(item)
+    }
+
+    //--------------------------------------------------------------------------------------------------
+    //--------------------------------------------------------------------------------------------------
+    fn release(&mut self, item_name : &str, quantity : i32) {
+        if let Some(reserved) = self.reserved.get_mut(item_name) {
+            *reserved -= quantity;
+            if *reserved <= 0 {
+                self.reserved.remove(item_name);
+            }
+        }
+    }
+}
+
+//------------------------------------------------------------------------------------------------------
+//------------------------------------------------------------------------------------------------------
+impl Saver {
+
+    //--------------------------------------------------------------------------------------------------
+    //--------------------------------------------------------------------------------------------------
+    //taken under the lock of the collection so the versions follow the order of the changes
+    fn changed(&self) -> u64 {
+        self.changes.fetch_add(1, Ordering::SeqCst) + 1
+    }
+
+    //--------------------------------------------------------------------------------------------------
+    //--------------------------------------------------------------------------------------------------
+    // save is told whether its snapshot is still the newest, if not a later change has written it
+    // already and only what goes with it, like the orders of a sale, is left to write
+    fn save<F>(&self, version : u64, save : F) -> Result<(), storage::Error>
+        where F : FnOnce(bool) -> Result<(), storage::Error> {
+        let mut saved = self.saved.lock().unwrap();
+        if *saved >= version {
+            return save(false);
+        }
+
+        //a failed save leaves saved behind so the next change writes the whole collection again
+        save(true)?;
+        *saved = version;
+        Ok(())
+    }
+}
+
+//------------------------------------------------------------------------------------------------------
+//------------------------------------------------------------------------------------------------------
+impl util::Failure for ReservationError {
+
+    //--------------------------------------------------------------------------------------------------
+    //--------------------------------------------------------------------------------------------------
+    fn status_code(&self) -> StatusCode {
+        match self {
+            ReservationError::UnknownItem => StatusCode::NOT_FOUND,
+            ReservationError::Archived | ReservationError::SoldOut => StatusCode::CONFLICT,
+        }
+    }
+
+    //--------------------------------------------------------------------------------------------------
+    //--------------------------------------------------------------------------------------------------
+    fn buyer_message(&self) -> &'static str {
+        match self {
+            ReservationError::UnknownItem | ReservationError::Archived =>
+                "One of the classes in your cart is no longer offered. Please remove it and try again, your card has not been charged.",
+            ReservationError::SoldOut =>
+                "There are not enough seats left in one of the classes in your cart. Please lower the number of seats and try again, your card has not been charged.",
+        }
+    }
+}
+
+//------------------------------------------------------------------------------------------------------
+//------------------------------------------------------------------------------------------------------
+impl State {
+
+    //--------------------------------------------------------------------------------------------------
+    //--------------------------------------------------------------------------------------------------
+    pub fn load(storage : Arc<dyn Storage>) -> Result<State, storage::Error> {
+        Ok(State {
+            inventory: Mutex::new(Inventory{
+                items: storage.load_items()?,
+                reserved: HashMap::new(),
+            }),
+            fundraisers: Mutex::new(storage.load_fundraisers()?),
+            promotions: Mutex::new(Promotions::new(storage.load_promo_codes()?)),
+            carts: Mutex::new(HashMap::new()),
+            items_saver: Saver::default(),
+            fundraisers_saver: Saver::default(),
+            promo_codes_saver: Saver::default(),
+            unsaved_orders: Mutex::new(Vec::new()),
+            unsaved_donations: Mutex::new(Vec::new()),
+            storage,
+        })
+    }
+
+    //--------------------------------------------------------------------------------------------------
+    //--------------------------------------------------------------------------------------------------
+    //seats and promo code uses held by charges still pending stay held across a restart
+    pub fn restore_holds(&self, charges : &BTreeMap<String, PendingCharge>) {
+        let mut inventory = self.inventory.lock().unwrap();
+        let mut promotions = self.promotions.lock().unwrap();
+
+        for charge in charges.values() {
+            if let Hold::Seats{ reservation, promo, .. } = &charge.hold {
+                for (item_name, quantity) in reservation.seats.iter() {
+                    if inventory.items.get(item_name).map_or(false, |item| item.number_of_items.is_some()) {
+                        *inventory.reserved.entry(item_name.clone()).or_insert(0) += quantity;
+                    }
+                }
+                if let Some(promo) = promo {
+                    promotions.restore(promo);
+                }
+            }
+        }
+    }
+
+    //--------------------------------------------------------------------------------------------------
+    //--------------------------------------------------------------------------------------------------
+    pub fn items(&self) -> BTreeMap<String, Item> {
+        let inventory = self.inventory.lock().unwrap();
+        inventory.items.keys()
+            .filter_map(|name| inventory.available(name).map(|item| (name.clone(), item)))
+            .collect()
+    }
+
+    //--------------------------------------------------------------------------------------------------
+    //--------------------------------------------------------------------------------------------------
+    pub fn item(&self, item_name : &str) -> Option<Item> {
+        self.inventory.lock().unwrap().available(item_name)
+    }
+
+    //--------------------------------------------------------------------------------------------------
+    //--------------------------------------------------------------------------------------------------
+    //the seats as stocked, still counting the ones held by checkouts, this is what admin edits
+    pub fn stocked_items(&self) -> BTreeMap<String, Item> {
+        self.inventory.lock().unwrap().items.clone()
+    }
+
+    //--------------------------------------------------------------------------------------------------
+    //--------------------------------------------------------------------------------------------------
+    pub fn reserve_seat(&self, item_name : &str) -> Result<Reservation, ReservationError> {
+        self.reserve_seats(&[(item_name.to_string(), 1)]).map_err(|(_, error)| error)
+    }
+
+    //--------------------------------------------------------------------------------------------------
+    //--------------------------------------------------------------------------------------------------
+    pub fn reserve_seats(&self, seats : &[(String, i32)]) -> Result<Reservation, (String, ReservationError)> {
+        let mut inventory = self.inventory.lock().unwrap();
+
+        //every line is checked before any seat is held so one sold out class rejects the whole order
+        for (item_name, quantity) in seats.iter() {
+            let item = inventory.available(item_name).ok_or_else(|| (item_name.clone(), ReservationError::UnknownItem))?;
+
+            if item.archived {
+                return Err((item_name.clone(), ReservationError::Archived));
+            }
+
+            //dont reserve if no inventory available
+            if item.number_of_items.map_or(false, |number_of_items| number_of_items < *quantity) {
+                return Err((item_name.clone(), ReservationError::SoldOut));
+            }
+        }
+
+        for (item_name, quantity) in seats.iter() {
+            if inventory.items[item_name].number_of_items.is_some() {
+                *inventory.reserved.entry(item_name.clone()).or_insert(0) += quantity;
+            }
+        }
+
+        Ok(Reservation{ seats: seats.to_vec() })
+    }
+
+    //--------------------------------------------------------------------------------------------------
+    //--------------------------------------------------------------------------------------------------
+    pub fn release_seat(&self, reservation : Reservation) {
+        info!("releasing seats {:?}\n", reservation.seats);
+        let mut inventory = self.inventory.lock().unwrap();
+        for (item_name, quantity) in reservation.seats.iter() {
+            inventory.release(item_name, *quantity);
+        }
+    }
+
+    //--------------------------------------------------------------------------------------------------
+    //--------------------------------------------------------------------------------------------------
+    pub fn commit_seat(&self, reservation : Reservation, order : &Order) {
+        self.commit_seats(reservation, std::slice::from_ref(order));
+    }
+
+    //--------------------------------------------------------------------------------------------------
+    //--------------------------------------------------------------------------------------------------
+    pub fn commit_seats(&self, reservation : Reservation, orders : &[Order]) {
+        let (items, version) = {
+            let mut inventory = self.inventory.lock().unwrap();
+
+            for (item_name, quantity) in reservation.seats.iter() {
+                inventory.release(item_name, *quantity);
+
+                if let Some(item) = inventory.items.get_mut(item_name) {
+                    if let Some(number_of_items) = item.number_of_items.as_mut() {
+                        *number_of_items -= quantity;
+                    }
+                }
+            }
+
+            info!("inventory after course signup {:#?}\n", inventory.items);
+            (inventory.items.clone(), self.items_saver.changed())
+        };
+
+        //the card has been charged so the sale stands, orders that can't be written wait for the next one
+        let mut unsaved = std::mem::take(&mut *self.unsaved_orders.lock().unwrap());
+        let saved = self.items_saver.save(version, |newest| {
+            while let Some(earlier) = unsaved.first() {
+                self.storage.record_order(earlier)?;
+                unsaved.remove(0);
+            }
+            match newest {
+                true => self.storage.commit_orders(&items, orders),
+                false => orders.iter().try_for_each(|order| self.storage.record_order(order)),
+            }
+        });
+
+        if let Err(error) = saved {
+            unsaved.extend_from_slice(orders);
+            error!("Error: unable to save orders {:?}, trying again with the next sale {:?}\n", unsaved, error);
+            self.unsaved_orders.lock().unwrap().extend(unsaved);
+        }
+    }
+
+    //--------------------------------------------------------------------------------------------------
+    //--------------------------------------------------------------------------------------------------
+    pub fn cart(&self, cart_id : &str) -> BTreeMap<String, u32> {
+        let carts = self.carts.lock().unwrap();
+        carts.get(cart_id)
+            .filter(|cart| !cart.is_expired())
+            .map_or(BTreeMap::new(), |cart| cart.lines.clone())
+    }
+
+    //--------------------------------------------------------------------------------------------------
+    //--------------------------------------------------------------------------------------------------
+    pub fn add_to_cart(&self, cart_id : &str, item_name : &str, quantity : u32) -> bool {
+        let current = self.cart(cart_id).get(item_name).copied().unwrap_or(0);
+        self.set_cart_quantity(cart_id, item_name, current.saturating_add(quantity))
+    }
+
+    //--------------------------------------------------------------------------------------------------
+    //--------------------------------------------------------------------------------------------------
+    pub fn set_cart_quantity(&self, cart_id : &str, item_name : &str, quantity : u32) -> bool {
+        let item = self.item(item_name);
+
+        //a class can always be taken out of the cart, only listed classes can be put in
+        if quantity > 0 && !item.as_ref().map_or(false, |item| !item.archived) {
+            return false;
+        }
+
+        //never more than the seats still free, the cart caps it at the per class limit too
+        let quantity = match item.and_then(|item| item.number_of_items) {
+            Some(number_of_items) => quantity.min(number_of_items.max(0) as u32),
+            None => quantity,
+        };
+
+        let mut carts = self.carts.lock().unwrap();
+        carts.retain(|_, cart| !cart.is_expired());
+        carts.entry(cart_id.to_string()).or_insert_with(Cart::new).set(item_name, quantity);
+        true
+    }
+
+    //--------------------------------------------------------------------------------------------------
+    //--------------------------------------------------------------------------------------------------
+    pub fn clear_cart(&self, cart_id : &str) {
+        self.carts.lock().unwrap().remove(cart_id);
+    }
+
+    //--------------------------------------------------------------------------------------------------
+    //--------------------------------------------------------------------------------------------------
+    pub fn fundraisers(&self) -> BTreeMap<String, Fundraiser> {
+        self.fundraisers.lock().unwrap().clone()
+    }
+
+    //--------------------------------------------------------------------------------------------------
+    //--------------------------------------------------------------------------------------------------
+    pub fn fundraiser(&self, name : &str) -> Option<Fundraiser> {
+        self.fundraisers.lock().unwrap().get(name).cloned()
+    }
+
+    //--------------------------------------------------------------------------------------------------
+    //--------------------------------------------------------------------------------------------------
+    pub fn add_donation(&self, donation : &DonationRecord) -> Option<Fundraiser> {
+        let (fundraisers, fundraiser, version) = {
+            let mut fundraisers = self.fundraisers.lock().unwrap();
+
+            let fundraiser = fundraisers.get_mut(&donation.fundraiser)?;
+            //checked before the card was charged, the donation is still recorded if the total has grown since
+            match fundraiser.amount_raised.checked_add(donation.amount) {
+                Ok(amount_raised) => fundraiser.amount_raised = amount_raised,
+                Err(error) => error!("Error: unable to add donation {} to the total of {} {}\n", donation.transaction_id, donation.fundraiser, error),
+            }
+            info!("amount_raised = {:#?}\n", fundraiser.amount_raised);
+            let fundraiser = fundraiser.clone();
+            (fundraisers.clone(), fundraiser, self.fundraisers_saver.changed())
+        };
+
+        //like orders a donation that can't be written waits for the next one
+        let mut unsaved = std::mem::take(&mut *self.unsaved_donations.lock().unwrap());
+        let saved = self.fundraisers_saver.save(version, |newest| {
+            while let Some(earlier) = unsaved.first() {
+                self.storage.record_donation(earlier)?;
+                unsaved.remove(0);
+            }
+            match newest {
+                true => self.storage.commit_donation(&fundraisers, donation),
+                false => self.storage.record_donation(donation),
+            }
+        });
+
+        if let Err(error) = saved {
+            unsaved.push(donation.clone());
+            error!("Error: unable to save donations {:?}, trying again with the next one {:?}\n", unsaved, error);
+            self.unsaved_donations.lock().unwrap().extend(unsaved);
+        }
+
+        Some(fundraiser)
+    }
+
+    //--------------------------------------------------------------------------------------------------
+    //--------------------------------------------------------------------------------------------------
+    pub fn promo_codes(&self) -> BTreeMap<String, PromoCode> {
+        self.promotions.lock().unwrap().codes.clone()
+    }
+
+    //--------------------------------------------------------------------------------------------------
+    //--------------------------------------------------------------------------------------------------
+    pub fn reserve_promo(&self, code : &str, item_names : &[&str], email : &str) -> Result<PromoReservation, PromoError> {
+        self.promotions.lock().unwrap().reserve(code, item_names, email)
+    }
+
+    //--------------------------------------------------------------------------------------------------
+    //--------------------------------------------------------------------------------------------------
+    pub fn release_promo(&self, reservation : PromoReservation) {
+        info!("releasing promo code {}\n", reservation.code);
+        self.promotions.lock().unwrap().release(&reservation);
+    }
+
+    //--------------------------------------------------------------------------------------------------
+    //--------------------------------------------------------------------------------------------------
+    pub fn commit_promo(&self, reservation : PromoReservation, transaction_id : &str) {
+        let (codes, version) = {
+            let mut promotions = self.promotions.lock().unwrap();
+            promotions.redeem(&reservation, transaction_id);
+            (promotions.codes.clone(), self.promo_codes_saver.changed())
+        };
+
+        //the redemption is held in memory and written with the next change to the codes
+        if let Err(error) = self.save_promo_codes(&codes, version) {
+            error!("Error: unable to save redemption of promo code {}, trying again with the next change {:?}\n", reservation.code, error);
+        }
+    }
+
+    //--------------------------------------------------------------------------------------------------
+    //--------------------------------------------------------------------------------------------------
+    pub fn save_promo_code(&self, code : &str, mut promo : PromoCode) -> Result<(), storage::Error> {
+        let (codes, version) = {
+            let mut promotions = self.promotions.lock().unwrap();
+
+            //the admin form only edits the terms, past redemptions are kept
+            if let Some(existing) = promotions.codes.get(code) {
+                promo.redemptions = existing.redemptions.clone();
+            }
+            promotions.codes.insert(code.to_string(), promo);
+            (promotions.codes.clone(), self.promo_codes_saver.changed())
+        };
+
+        self.save_promo_codes(&codes, version)
+    }
+
+    //--------------------------------------------------------------------------------------------------
+    //--------------------------------------------------------------------------------------------------
+    fn save_promo_codes(&self, codes : &BTreeMap<String, PromoCode>, version : u64) -> Result<(), storage::Error> {
+        self.promo_codes_saver.save(version, |newest| match newest {
+            true => self.storage.save_promo_codes(codes),
+            false => Ok(()),
+        })
+    }
+
+    //--------------------------------------------------------------------------------------------------
+    //--------------------------------------------------------------------------------------------------
+    //the form carries the seats it was loaded with, if a sale has changed them since nothing is saved
+    //so the seats sold in between aren't put back on sale
+    pub fn save_item(&self, key : &str, item : Item, loaded_seats : Option<i32>) -> Result<bool, storage::Error> {
+        self.update_items(|items| {
+            if items.get(key).map_or(false, |existing| existing.number_of_items != loaded_seats) {
+                return false;
+            }
+            items.insert(key.to_string(), item);
+            true
+        })
+    }
+
+    //--------------------------------------------------------------------------------------------------
+    //--------------------------------------------------------------------------------------------------
+    pub fn archive_item(&self, key : &str, archived : bool) -> Result<bool, storage::Error> {
+        self.update_items(|items| match items.get_mut(key) {
+            Some(item) => { item.set_archived(archived); true },
+            None => false,
+        })
+    }
+
+    //--------------------------------------------------------------------------------------------------
+    //--------------------------------------------------------------------------------------------------
+    pub fn move_item(&self, key : &str, offset : i32) -> Result<bool, storage::Error> {
+        self.update_items(|items| move_entry(items, key, offset))
+    }
+
+    //--------------------------------------------------------------------------------------------------
+    //--------------------------------------------------------------------------------------------------
+    pub fn save_fundraiser(&self, key : &str, mut fundraiser : Fundraiser) -> Result<(), storage::Error> {
+        self.update_fundraisers(|fundraisers| {
+            //only donations change the amount raised, read under the lock so one coming in now is kept
+            fundraiser.amount_raised = fundraisers.get(key).map_or(Money::zero(), |existing| existing.amount_raised);
+            fundraisers.insert(key.to_string(), fundraiser);
+            true
+        })?;
+        Ok(())
+    }
+
+    //--------------------------------------------------------------------------------------------------
+    //--------------------------------------------------------------------------------------------------
+    pub fn archive_fundraiser(&self, key : &str, archived : bool) -> Result<bool, storage::Error> {
+        self.update_fundraisers(|fundraisers| match fundraisers.get_mut(key) {
+            Some(fundraiser) => { fundraiser.set_archived(archived); true },
+            None => false,
+        })
+    }
+
+    //--------------------------------------------------------------------------------------------------
+    //--------------------------------------------------------------------------------------------------
+    pub fn move_fundraiser(&self, key : &str, offset : i32) -> Result<bool, storage::Error> {
+        self.update_fundraisers(|fundraisers| move_entry(fundraisers, key, offset))
+    }
+
+    //--------------------------------------------------------------------------------------------------
+    //--------------------------------------------------------------------------------------------------
+    //a change that can't be saved stays in memory and is written with the next one
+    fn update_items<F : FnOnce(&mut BTreeMap<String, Item>) -> bool>(&self, update : F) -> Result<bool, storage::Error> {
+        let (items, version) = {
+            let mut inventory = self.inventory.lock().unwrap();
+            if !update(&mut inventory.items) {
+                return Ok(false);
+            }
+            (inventory.items.clone(), self.items_saver.changed())
+        };
+
+        self.items_saver.save(version, |newest| match newest {
+            true => self.storage.save_items(&items),
+            false => Ok(()),
+        })?;
+        Ok(true)
+    }
+
+    //--------------------------------------------------------------------------------------------------
+    //--------------------------------------------------------------------------------------------------
+    fn update_fundraisers<F : FnOnce(&mut BTreeMap<String, Fundraiser>) -> bool>(&self, update : F) -> Result<bool, storage::Error> {
+        let (fundraisers, version) = {
+            let mut fundraisers = self.fundraisers.lock().unwrap();
+            if !update(&mut *fundraisers) {
+                return Ok(false);
+            }
+            (fundraisers.clone(), self.fundraisers_saver.changed())
+        };
+
+        self.fundraisers_saver.save(version, |newest| match newest {
+            true => self.storage.save_fundraisers(&fundraisers),
+            false => Ok(()),
+        })?;
+        Ok(true)
+    }
+}
+
+//----------------------------------------------------------------------------------------------------
+//----------------------------------------------------------------------------------------------------
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    //--------------------------------------------------------------------------------------------------
+    //--------------------------------------------------------------------------------------------------
+    fn failed() -> storage::Error {
+        storage::Error::Io(std::io::Error::new(std::io::ErrorKind::Other, "disk full"))
+    }
+
+    //--------------------------------------------------------------------------------------------------
+    //--------------------------------------------------------------------------------------------------
+    #[test]
+    fn saver_never_writes_an_older_snapshot_over_a_newer_one() {
+        let saver = Saver::default();
+        let first = saver.changed();
+        let second = saver.changed();
+
+        //the second change gets to the disk first, the first one only writes what goes with it
+        let mut written = Vec::new();
+        saver.save(second, |newest| { written.push((second, newest)); Ok(()) }).unwrap();
+        saver.save(first, |newest| { written.push((first, newest)); Ok(()) }).unwrap();
+        assert_eq!(written, [(second, true), (first, false)]);
+    }
+
+    //--------------------------------------------------------------------------------------------------
+    //--------------------------------------------------------------------------------------------------
+    #[test]
+    fn saver_writes_everything_again_after_a_failure() {
+        let saver = Saver::default();
+        let first = saver.changed();
+        let second = saver.changed();
+
+        assert!(saver.save(second, |_| Err(failed())).is_err());
+        //the newest snapshot was never written so even an older one goes out in full
+        let mut newest_written = None;
+        saver.save(first, |newest| { newest_written = Some(newest); Ok(()) }).unwrap();
+        assert_eq!(newest_written, Some(true));
+
+        let third = saver.changed();
+        let mut newest_written = None;
+        saver.save(third, |newest| { newest_written = Some(newest); Ok(()) }).unwrap();
+        assert_eq!(newest_written, Some(true));
+    }
+}
@@ -0,0 +1,142 @@
+use askama::Template;
+use chrono::{DateTime, Datelike};
+use chrono_tz::Tz;
+use log::{info};
+use std::collections::BTreeMap;
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::path::Path;
+
+use crate::acknowledgement::Organization;
+use crate::fundraise::Fundraiser;
+use crate::ledger::{csv_field, LedgerEntry, LedgerStatus};
+use crate::mail::Mailer;
+use crate::money::{Money, MoneyError};
+use crate::util;
+
+const CSV_HEADER : &str = "year,email,donor_name,date,fundraiser,amount,transaction_id";
+
+//----------------------------------------------------------------------------------------------------
+//----------------------------------------------------------------------------------------------------
+#[derive(Debug, Clone)]
+pub struct StatementLine {
+    pub date : DateTime<Tz>,
+    pub fundraiser : String,
+    pub amount : Money,
+    pub transaction_id : String,
+}
+
+//----------------------------------------------------------------------------------------------------
+//----------------------------------------------------------------------------------------------------
+#[derive(Template, Debug, Clone)]
+#[template(path = "statement.html")]
+pub struct DonorStatement {
+    pub organization : Organization,
+    pub year : i32,
+    pub donor_name : String,
+    pub email : String,
+    pub donations : Vec<StatementLine>,
+    pub total : Money,
+}
+
+//------------------------------------------------------------------------------------------------------
+//------------------------------------------------------------------------------------------------------
+impl DonorStatement {
+
+    //--------------------------------------------------------------------------------------------------
+    //--------------------------------------------------------------------------------------------------
+    pub fn csv_lines(&self) -> Vec<String> {
+        self.donations.iter().map(|donation| {
+            [
+                self.year.to_string(),
+                self.email.clone(),
+                self.donor_name.clone(),
+                donation.date.format("%Y-%m-%d").to_string(),
+                donation.fundraiser.clone(),
+                donation.amount.to_string(),
+                donation.transaction_id.clone(),
+            ].iter().map(|field| csv_field(field)).collect::<Vec<String>>().join(",")
+        }).collect()
+    }
+
+    //--------------------------------------------------------------------------------------------------
+    //--------------------------------------------------------------------------------------------------
+    pub fn file_stem(&self) -> String {
+        let email : String = self.email.chars()
+            .map(|c| if c.is_ascii_alphanumeric() || c == '@' || c == '.' || c == '-' { c } else { '_' })
+            .collect();
+        format!("{}-{}", self.year, email)
+    }
+}
+
+//----------------------------------------------------------------------------------------------------
+//----------------------------------------------------------------------------------------------------
+pub fn statements(
+    entries : &[LedgerEntry],
+    fundraisers : &BTreeMap<String, Fundraiser>,
+    organization : &Organization,
+    year : i32,
+    timezone : &Tz) -> Result<Vec<DonorStatement>, MoneyError> {
+
+    let mut donors : BTreeMap<String, DonorStatement> = BTreeMap::new();
+
+    for entry in entries.iter() {
+        //the ledger is in utc but a gift made on new year's eve belongs to the year it was made in at the store
+        let date = entry.timestamp.with_timezone(timezone);
+        if entry.status != LedgerStatus::Succeeded
+            || entry.payment_type != util::PaymentType::Donation.as_str()
+            || date.year() != year {
+            continue;
+        }
+
+        //the same donor can type their email with different capitalisation
+        let statement = donors.entry(entry.email.trim().to_lowercase()).or_insert_with(|| DonorStatement {
+            organization: organization.clone(),
+            year,
+            donor_name: String::new(),
+            email: entry.email.trim().to_string(),
+            donations: Vec::new(),
+            total: Money::zero(),
+        });
+
+        //ledger entries are in order so the latest name the donor gave wins
+        statement.donor_name = format!("{} {}", entry.first_name, entry.last_name);
+        statement.total = statement.total.checked_add(entry.amount)?;
+        statement.donations.push(StatementLine {
+            date,
+            fundraiser: fundraisers.get(&entry.description).map_or(entry.description.clone(), |fundraiser| fundraiser.name.clone()),
+            amount: entry.amount,
+            transaction_id: entry.transaction_id.clone().unwrap_or_default(),
+        });
+    }
+
+    Ok(donors.into_values().collect())
+}
+
+//----------------------------------------------------------------------------------------------------
+//----------------------------------------------------------------------------------------------------
+pub fn write_statements(statements : &[DonorStatement], directory : &str, mailer : Option<&Mailer>) -> io::Result<()> {
+    fs::create_dir_all(directory)?;
+
+    let mut summary = File::create(Path::new(directory).join("statements.csv"))?;
+    writeln!(summary, "{}", CSV_HEADER)?;
+
+    for statement in statements.iter() {
+        let html = statement.render().map_err(|error| io::Error::new(io::ErrorKind::Other, format!("{:?}", error)))?;
+        fs::write(Path::new(directory).join(format!("{}.html", statement.file_stem())), html)?;
+
+        let mut csv = File::create(Path::new(directory).join(format!("{}.csv", statement.file_stem())))?;
+        writeln!(csv, "{}", CSV_HEADER)?;
+        for line in statement.csv_lines().iter() {
+            writeln!(csv, "{}", line)?;
+            writeln!(summary, "{}", line)?;
+        }
+
+        if let Some(mailer) = mailer {
+            mailer.send_statement(statement);
+        }
+    }
+
+    info!("wrote {} donor statements to {}", statements.len(), directory);
+    Ok(())
+}
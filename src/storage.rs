@@ -0,0 +1,348 @@
+use log::{error};
+#[cfg(feature = "sqlite")]
+use log::{info};
+use serde::{Serialize, Deserialize};
+use std::collections::BTreeMap;
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::{Path};
+use std::sync::{Arc};
+
+use crate::config::Config;
+use crate::fundraise::Fundraiser;
+use crate::ledger::LedgerEntry;
+use crate::membership::Tier;
+use crate::money::Money;
+use crate::pending::PendingCharge;
+use crate::persist;
+use crate::promo::PromoCode;
+use crate::quote::Invoice;
+#[cfg(feature = "sqlite")]
+use crate::sqlite::SqliteStorage;
+use crate::store::Item;
+
+//----------------------------------------------------------------------------------------------------
+//----------------------------------------------------------------------------------------------------
+#[derive(Debug)]
+pub enum Error {
+    Io(io::Error),
+    #[cfg(feature = "sqlite")]
+    Sqlite(rusqlite::Error),
+}
+
+#[derive(Deserialize,Debug, Serialize, Clone)]
+pub struct Order {
+    pub item : String,
+    pub amount : Money,
+    pub email : String,
+    pub transaction_id : String,
+    #[serde(default)]
+    pub promo_code : Option<String>,
+    #[serde(default)]
+    pub tier : Tier,
+    #[serde(default = "one_seat")]
+    pub quantity : i32,
+}
+
+#[derive(Deserialize,Debug, Serialize, Clone)]
+pub struct DonationRecord {
+    pub fundraiser : String,
+    pub amount : Money,
+    pub email : String,
+    pub transaction_id : String,
+}
+
+//orders written before the cart was added were always for a single seat
+fn one_seat() -> i32 {
+    1
+}
+
+//----------------------------------------------------------------------------------------------------
+//----------------------------------------------------------------------------------------------------
+pub trait Storage : Send + Sync {
+    fn load_items(&self) -> Result<BTreeMap<String, Item>, Error>;
+
+    fn save_items(&self, items : &BTreeMap<String, Item>) -> Result<(), Error>;
+
+    fn load_fundraisers(&self) -> Result<BTreeMap<String, Fundraiser>, Error>;
+
+    fn save_fundraisers(&self, fundraisers : &BTreeMap<String, Fundraiser>) -> Result<(), Error>;
+
+    fn load_invoices(&self) -> Result<BTreeMap<String, Invoice>, Error>;
+
+    fn save_invoices(&self, invoices : &BTreeMap<String, Invoice>) -> Result<(), Error>;
+
+    fn load_promo_codes(&self) -> Result<BTreeMap<String, PromoCode>, Error>;
+
+    fn save_promo_codes(&self, promo_codes : &BTreeMap<String, PromoCode>) -> Result<(), Error>;
+
+    fn record_order(&self, order : &Order) -> Result<(), Error>;
+
+    fn record_donation(&self, donation : &DonationRecord) -> Result<(), Error>;
+
+    fn append_ledger(&self, entry : &LedgerEntry) -> Result<(), Error>;
+
+    fn load_ledger(&self) -> Result<Vec<LedgerEntry>, Error>;
+
+    fn load_pending(&self) -> Result<BTreeMap<String, PendingCharge>, Error>;
+
+    fn save_pending(&self, charges : &BTreeMap<String, PendingCharge>) -> Result<(), Error>;
+
+    fn commit_orders(&self, items : &BTreeMap<String, Item>, orders : &[Order]) -> Result<(), Error> {
+        self.save_items(items)?;
+        orders.iter().try_for_each(|order| self.record_order(order))
+    }
+
+    fn commit_donation(&self, fundraisers : &BTreeMap<String, Fundraiser>, donation : &DonationRecord) -> Result<(), Error> {
+        self.save_fundraisers(fundraisers)?;
+        self.record_donation(donation)
+    }
+}
+
+//------------------------------------------------------------------------------------------------------
+//------------------------------------------------------------------------------------------------------
+impl From<io::Error> for Error {
+    fn from(error : io::Error) -> Error {
+        Error::Io(error)
+    }
+}
+
+//------------------------------------------------------------------------------------------------------
+//------------------------------------------------------------------------------------------------------
+#[cfg(feature = "sqlite")]
+impl From<rusqlite::Error> for Error {
+    fn from(error : rusqlite::Error) -> Error {
+        Error::Sqlite(error)
+    }
+}
+
+//----------------------------------------------------------------------------------------------------
+//----------------------------------------------------------------------------------------------------
+pub struct JsonStorage {
+    pub inventory_file : String,
+    pub fundraisers_file : String,
+    pub invoices_file : String,
+    pub promo_codes_file : String,
+    pub ledger_file : String,
+    pub orders_file : String,
+    pub donations_file : String,
+    pub pending_file : String,
+}
+
+//------------------------------------------------------------------------------------------------------
+//------------------------------------------------------------------------------------------------------
+impl Storage for JsonStorage {
+
+    //--------------------------------------------------------------------------------------------------
+    //--------------------------------------------------------------------------------------------------
+    fn load_items(&self) -> Result<BTreeMap<String, Item>, Error> {
+        Ok(persist::load(&self.inventory_file)?)
+    }
+
+    //--------------------------------------------------------------------------------------------------
+    //--------------------------------------------------------------------------------------------------
+    fn save_items(&self, items : &BTreeMap<String, Item>) -> Result<(), Error> {
+        Ok(persist::save(&self.inventory_file, items)?)
+    }
+
+    //--------------------------------------------------------------------------------------------------
+    //--------------------------------------------------------------------------------------------------
+    fn load_fundraisers(&self) -> Result<BTreeMap<String, Fundraiser>, Error> {
+        Ok(persist::load(&self.fundraisers_file)?)
+    }
+
+    //--------------------------------------------------------------------------------------------------
+    //--------------------------------------------------------------------------------------------------
+    fn save_fundraisers(&self, fundraisers : &BTreeMap<String, Fundraiser>) -> Result<(), Error> {
+        Ok(persist::save(&self.fundraisers_file, fundraisers)?)
+    }
+
+    //--------------------------------------------------------------------------------------------------
+    //--------------------------------------------------------------------------------------------------
+    fn load_invoices(&self) -> Result<BTreeMap<String, Invoice>, Error> {
+        Ok(persist::load(&self.invoices_file)?)
+    }
+
+    //--------------------------------------------------------------------------------------------------
+    //--------------------------------------------------------------------------------------------------
+    fn save_invoices(&self, invoices : &BTreeMap<String, Invoice>) -> Result<(), Error> {
+        Ok(persist::save(&self.invoices_file, invoices)?)
+    }
+
+    //--------------------------------------------------------------------------------------------------
+    //--------------------------------------------------------------------------------------------------
+    fn load_promo_codes(&self) -> Result<BTreeMap<String, PromoCode>, Error> {
+        //promo codes are optional, most stores start without the file
+        if !Path::new(&self.promo_codes_file).exists() {
+            return Ok(BTreeMap::new());
+        }
+        Ok(persist::load(&self.promo_codes_file)?)
+    }
+
+    //--------------------------------------------------------------------------------------------------
+    //--------------------------------------------------------------------------------------------------
+    fn save_promo_codes(&self, promo_codes : &BTreeMap<String, PromoCode>) -> Result<(), Error> {
+        Ok(persist::save(&self.promo_codes_file, promo_codes)?)
+    }
+
+    //--------------------------------------------------------------------------------------------------
+    //--------------------------------------------------------------------------------------------------
+    fn record_order(&self, order : &Order) -> Result<(), Error> {
+        append_line(&self.orders_file, order)
+    }
+
+    //--------------------------------------------------------------------------------------------------
+    //--------------------------------------------------------------------------------------------------
+    fn record_donation(&self, donation : &DonationRecord) -> Result<(), Error> {
+        append_line(&self.donations_file, donation)
+    }
+
+    //--------------------------------------------------------------------------------------------------
+    //--------------------------------------------------------------------------------------------------
+    fn append_ledger(&self, entry : &LedgerEntry) -> Result<(), Error> {
+        append_line(&self.ledger_file, entry)
+    }
+
+    //--------------------------------------------------------------------------------------------------
+    //--------------------------------------------------------------------------------------------------
+    fn load_ledger(&self) -> Result<Vec<LedgerEntry>, Error> {
+        let file = match File::open(&self.ledger_file) {
+            Ok(file) => file,
+            Err(error) if error.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(error) => return Err(Error::Io(error)),
+        };
+
+        let mut entries = Vec::new();
+        for (number, line) in BufReader::new(file).lines().enumerate() {
+            let line = line?;
+            //a crash mid append can leave a torn last line, skip it rather than losing the rest
+            match serde_json::from_str::<LedgerEntry>(&line) {
+                Ok(entry) => entries.push(entry),
+                Err(parse_error) => error!("Error: bad ledger line {} in {} {:?}\n", number + 1, self.ledger_file, parse_error),
+            }
+        }
+        Ok(entries)
+    }
+
+    //--------------------------------------------------------------------------------------------------
+    //--------------------------------------------------------------------------------------------------
+    fn load_pending(&self) -> Result<BTreeMap<String, PendingCharge>, Error> {
+        //the file is only written once braintree first fails to answer
+        if !Path::new(&self.pending_file).exists() {
+            return Ok(BTreeMap::new());
+        }
+        Ok(persist::load(&self.pending_file)?)
+    }
+
+    //--------------------------------------------------------------------------------------------------
+    //--------------------------------------------------------------------------------------------------
+    fn save_pending(&self, charges : &BTreeMap<String, PendingCharge>) -> Result<(), Error> {
+        Ok(persist::save(&self.pending_file, charges)?)
+    }
+}
+
+//----------------------------------------------------------------------------------------------------
+//----------------------------------------------------------------------------------------------------
+//one json line per record, synced before the sale is reported done
+fn append_line<T : Serialize>(file_name : &str, record : &T) -> Result<(), Error> {
+    let mut line = serde_json::to_string(record).map_err(io::Error::from)?;
+    line.push('\n');
+
+    let mut file = OpenOptions::new().create(true).append(true).open(file_name)?;
+    file.write_all(line.as_bytes())?;
+    Ok(file.sync_data()?)
+}
+
+//----------------------------------------------------------------------------------------------------
+//----------------------------------------------------------------------------------------------------
+pub fn json_storage(config : &Config) -> JsonStorage {
+    JsonStorage {
+        inventory_file: config.inventory_file.clone(),
+        fundraisers_file: config.fundraisers_file.clone(),
+        invoices_file: config.invoices_file.clone(),
+        promo_codes_file: config.promo_codes_file.clone(),
+        ledger_file: config.ledger_file.clone(),
+        orders_file: config.orders_file.clone(),
+        donations_file: config.donations_file.clone(),
+        pending_file: config.pending_file.clone(),
+    }
+}
+
+//----------------------------------------------------------------------------------------------------
+//----------------------------------------------------------------------------------------------------
+#[cfg(feature = "sqlite")]
+pub fn from_config(config : &Config) -> Result<Arc<dyn Storage>, Error> {
+    match config.database.as_ref() {
+        Some(database) => {
+            info!("using sqlite database {}", database);
+            Ok(Arc::new(SqliteStorage::open(database)?))
+        },
+        None => Ok(Arc::new(json_storage(config))),
+    }
+}
+
+//----------------------------------------------------------------------------------------------------
+//----------------------------------------------------------------------------------------------------
+//a database set without sqlite support is turned away when the config is validated
+#[cfg(not(feature = "sqlite"))]
+pub fn from_config(config : &Config) -> Result<Arc<dyn Storage>, Error> {
+    Ok(Arc::new(json_storage(config)))
+}
+
+//----------------------------------------------------------------------------------------------------
+//----------------------------------------------------------------------------------------------------
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::checkout;
+
+    //--------------------------------------------------------------------------------------------------
+    //--------------------------------------------------------------------------------------------------
+    #[test]
+    fn orders_and_donations_are_appended() {
+        let directory = std::env::temp_dir().join(format!("sbhx_store_test_{}", checkout::random_token()));
+        std::fs::create_dir_all(&directory).unwrap();
+        let file = |name : &str| directory.join(name).to_string_lossy().to_string();
+        let storage = JsonStorage {
+            inventory_file: file("inventory.json"),
+            fundraisers_file: file("fundraising_goals.json"),
+            invoices_file: file("invoices.json"),
+            promo_codes_file: file("promo_codes.json"),
+            ledger_file: file("ledger.jsonl"),
+            orders_file: file("orders.jsonl"),
+            donations_file: file("donations.jsonl"),
+            pending_file: file("pending_charges.json"),
+        };
+
+        let order = Order {
+            item: "intro".to_string(),
+            amount: Money::from_cents(10000),
+            email: "ada@example.org".to_string(),
+            transaction_id: "txn1".to_string(),
+            promo_code: None,
+            tier: Tier::Public,
+            quantity: 1,
+        };
+        storage.record_order(&order).unwrap();
+        storage.record_order(&Order{ transaction_id: "txn2".to_string(), quantity: 2, ..order }).unwrap();
+        storage.record_donation(&DonationRecord {
+            fundraiser: "tools".to_string(),
+            amount: Money::from_cents(2500),
+            email: "ada@example.org".to_string(),
+            transaction_id: "txn3".to_string(),
+        }).unwrap();
+
+        let orders : Vec<Order> = std::fs::read_to_string(file("orders.jsonl")).unwrap().lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(orders.iter().map(|order| (order.transaction_id.as_str(), order.quantity)).collect::<Vec<_>>(), [("txn1", 1), ("txn2", 2)]);
+
+        let donations : Vec<DonationRecord> = std::fs::read_to_string(file("donations.jsonl")).unwrap().lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(donations.len(), 1);
+        assert_eq!(donations[0].amount, Money::from_cents(2500));
+
+        std::fs::remove_dir_all(&directory).unwrap();
+    }
+}
@@ -1,30 +1,93 @@
 use actix_web::{web, HttpResponse};
+use actix_web::http::StatusCode;
+use askama::Template;
 use serde::{Serialize, Deserialize};
-use braintree::{Braintree};
-use log::{debug, error, info};
+use log::{debug, info};
 use std::collections::BTreeMap;
-use std::fs::File;
-use std::sync::{Mutex};
 
+use crate::cart::MAX_SEATS_PER_CLASS;
+use crate::checkout::Checkouts;
+use crate::config::Config;
+use crate::error::{Error, Page};
+use crate::gateway::{self, Gateway};
+use crate::ledger::Ledger;
+use crate::mail::{Mailer, Receipt};
+use crate::membership::Tier;
+use crate::money::Money;
+use crate::pending::{Hold, PendingCharge, PendingCharges};
+use crate::state::{self, Listing, ReservationError, State};
+use crate::storage::Order;
+use crate::tax::TaxError;
 use crate::util;
 
-#[derive(Deserialize,Debug, Serialize)]
+#[derive(Deserialize,Debug, Serialize, Default)]
 pub struct CourseSignup
 {
     pub course_type : String,
+    #[serde(default)]
+    pub checkout_token : String,
+    #[serde(default)]
+    pub promo_code : String,
+    #[serde(default)]
+    pub member_code : String,
+    #[serde(default)]
+    pub tax_certificate : String,
     #[serde(flatten)]
     payment : util::Payment,
 }
 
-#[derive(Deserialize,Debug, Serialize)]
+#[derive(Deserialize,Debug, Serialize, Clone)]
 pub struct Item {
     pub number_of_items : Option<i32>,
-    pub price : f32,
-    pub discount : f32,
+    pub price : Money,
+    pub discount : Money,
     pub name : String,
     pub formname : String,
     pub image : String,
     pub dates : String,
+    #[serde(default)]
+    pub archived : bool,
+    #[serde(default)]
+    pub position : i32,
+    #[serde(default)]
+    pub taxable : bool,
+    #[serde(default)]
+    pub tier_prices : BTreeMap<Tier, Money>,
+}
+
+//what the store page shows for one class
+struct StoreEntry {
+    item : Item,
+    limited : bool,
+    seats_left : i32,
+    cart_max : i32,
+    member_price : String,
+}
+
+#[derive(Template)]
+#[template(path = "store.html")]
+struct StorePage<'a> {
+    entries : &'a [StoreEntry],
+}
+
+#[derive(Template)]
+#[template(path = "form.html")]
+struct ItemPage<'a> {
+    item : &'a Item,
+    price : String,
+    discount : String,
+    tax : String,
+    total : String,
+    client_token : String,
+    checkout_token : String,
+    buyer : &'a util::Payment,
+    promo_code : &'a str,
+    member : bool,
+    listed : bool,
+    member_price : String,
+    member_code : &'a str,
+    tax_certificate : &'a str,
+    message : &'a str,
 }
 
 //------------------------------------------------------------------------------------------------------
@@ -33,125 +96,374 @@ impl Item {
 
     //--------------------------------------------------------------------------------------------------
     //--------------------------------------------------------------------------------------------------
-    pub fn get_button(&self) -> String {
-        match self.number_of_items {
-            Some(number_of_items) => {
-                if number_of_items >= 1 {
-                    return format!(
-                        "<span class=\"d-block g-color-danger g-font-size-16\">{} Spaces Available</span>
-                         <a href=\"{}\" class=\"w-100 btn btn-lg btn-success\" role=\"button\">Buy Now</a>",
-                        number_of_items,
-                        self.formname);
-                }
-                "<span class=\"d-block g-color-danger g-font-size-16\">Sold Out</span>".to_string()
-            },
-            None => "<a href=\"{}\" class=\"w-100 btn btn-lg btn-success\" role=\"button\">Buy Now</a>".to_string(),
-        }
+    //price is what the public pays, a tier without its own price pays that too
+    pub fn price_for(&self, tier : Tier) -> Money {
+        *self.tier_prices.get(&tier).unwrap_or(&self.price)
     }
+}
+
+//------------------------------------------------------------------------------------------------------
+//------------------------------------------------------------------------------------------------------
+impl StoreEntry {
 
     //--------------------------------------------------------------------------------------------------
     //--------------------------------------------------------------------------------------------------
-    pub fn get_entry(&self) -> String {
-
-          format!("<div class=\"col-md-6 col-lg-4 g-mb-30\"><article class=\"u-shadow-v18 g-bg-white text-center rounded g-px-20 g-py-40 g-mb-5\">
-            <img class=\"d-inline-block img-fluid mb-4\"  src=\"{}\" Width=100 Height=100 alt=\"Image Description\">
-            <h4 class=\"h5 g-color-black g-font-weight-600 g-mb-10\">{}</h4>
-            <p>In Person at SBHX: 5782 Thornwood Dr, Goleta, CA 93117</p>
-            <p>Dates: {}</p>
-            <span class=\"d-block g-color-primary g-font-size-16\">${:.2}</span>
-            {}
-          </article></div>", self.image, self.name, self.dates, self.price, self.get_button())
+    fn new(item : Item) -> StoreEntry {
+        StoreEntry {
+            limited: item.number_of_items.is_some(),
+            seats_left: item.number_of_items.unwrap_or(0),
+            cart_max: item.number_of_items.map_or(MAX_SEATS_PER_CLASS as i32, |number_of_items| number_of_items.min(MAX_SEATS_PER_CLASS as i32)),
+            member_price: item.tier_prices.get(&Tier::Member).map_or(String::new(), |member_price| member_price.to_string()),
+            item,
+        }
     }
 }
 
+//------------------------------------------------------------------------------------------------------
+//------------------------------------------------------------------------------------------------------
+impl Listing for Item {
+    fn position(&self) -> i32 { self.position }
+
+    fn set_position(&mut self, position : i32) { self.position = position; }
+
+    fn archived(&self) -> bool { self.archived }
+
+    fn set_archived(&mut self, archived : bool) { self.archived = archived; }
+}
+
 //----------------------------------------------------------------------------------------------------
 //----------------------------------------------------------------------------------------------------
 pub async fn course_signup(
     signup : web::Form<CourseSignup>,
-    braintree : web::Data<Mutex<Braintree>>) -> HttpResponse {
+    config : web::Data<Config>,
+    gateway : web::Data<Gateway>,
+    ledger : web::Data<Ledger>,
+    mailer : web::Data<Mailer>,
+    state : web::Data<State>,
+    pending : web::Data<PendingCharges>,
+    checkouts : web::Data<Checkouts>) -> Result<HttpResponse, Error> {
     debug!("course signup request = {:#?}\n", signup);
+    let page = Page::new(&config, util::PaymentType::CourseSignup);
 
-    let mut inventory = util::get_file::<BTreeMap<String, Item>>("inventory.json".to_string());
+    let signup = signup.into_inner();
+    let token = signup.checkout_token.clone();
+    let form_checkouts = checkouts.clone();
+    checkouts.once(&token, page, async move {
+        signup_checkout(&signup, &config, &gateway, &ledger, &mailer, &state, &pending, &form_checkouts).await
+    }).await
+}
 
-    let item = inventory.get(&signup.course_type);
+//----------------------------------------------------------------------------------------------------
+//----------------------------------------------------------------------------------------------------
+async fn signup_checkout(
+    signup : &CourseSignup,
+    config : &Config,
+    gateway : &web::Data<Gateway>,
+    ledger : &Ledger,
+    mailer : &Mailer,
+    state : &State,
+    pending : &PendingCharges,
+    checkouts : &Checkouts) -> Result<HttpResponse, Error> {
+    let page = Page::new(config, util::PaymentType::CourseSignup);
 
-    if item.is_none() {
-        error!("Error: no item {} found \n", &signup.course_type);
-        return util::error(util::PaymentType::CourseSignup).await;
-    }
+    //hold a seat while the card is charged so two buyers can't both get the last one
+    let reservation = match state.reserve_seat(&signup.course_type) {
+        Ok(reservation) => reservation,
+        Err(ReservationError::UnknownItem) => return Err(Error::NotFound(page, signup.course_type.clone())),
+        Err(error) => return Err(Error::Unavailable(page, signup.course_type.clone(), error)),
+    };
 
-    //dont charge if no inventory available
-    let item = item.unwrap();
+    let item = match state.item(&signup.course_type) {
+        Some(item) => item,
+        None => {
+            state.release_seat(reservation);
+            return Err(Error::NotFound(page, signup.course_type.clone()));
+        },
+    };
 
-    match item.number_of_items {
-        Some(number_of_items) => {
-            if number_of_items < 1 {
-                error!("Error: number of items less then 0 == {:?} \n", item.number_of_items);
-                return util::error(util::PaymentType::CourseSignup).await;
-            }
+    //members are recognised by the member code handed out at the space
+    let tier = match config.members.tier(&signup.member_code) {
+        Ok(tier) => tier,
+        Err(error) => {
+            state.release_seat(reservation);
+            return render_item_page(config, gateway, checkouts, page, &item, signup, Some(&error)).await;
         },
-        None => (),
-    }
+    };
+    let tier_price = item.price_for(tier);
 
-    let result = util::process_payment(&signup.payment, item.price, braintree, util::PaymentType::CourseSignup, &item.name);
-    if result.is_err() {
-        error!("Error: payment process {:?}\n", result);
-        return util::error(util::PaymentType::CourseSignup).await;
-    }
+    //exempt buyers give the certificate number on file for their email
+    let exemption = match config.tax.exemption(&signup.payment.email, &signup.tax_certificate, config.today()) {
+        Ok(exemption) => exemption,
+        Err(error) => {
+            state.release_seat(reservation);
+            return render_item_page(config, gateway, checkouts, page, &item, signup, Some(&error)).await;
+        },
+    };
 
-    match inventory.get_mut(&signup.course_type) {
-        Some(ref mut item) => {
-            match &mut item.number_of_items {
-                Some(number_of_items) => *number_of_items -= 1,
-                None => (),
+    //the code is checked and held before the card is charged, a bad code sends the buyer back to the form
+    let promo = match signup.promo_code.trim() {
+        "" => None,
+        code => match state.reserve_promo(code, &[signup.course_type.as_str()], &signup.payment.email) {
+            Ok(promo) => Some(promo),
+            Err(error) => {
+                state.release_seat(reservation);
+                return render_item_page(config, gateway, checkouts, page, &item, signup, Some(&error)).await;
+            },
+        },
+    };
+
+    //a price too large to add up is refused before the card is charged
+    let certificate = exemption.map(|customer| customer.certificate.as_str());
+    let totals = promo.as_ref().map_or(Ok(Money::zero()), |promo| promo.discount.amount_off(tier_price))
+        .and_then(|discount| tier_price.checked_sub(discount))
+        .map_err(TaxError::from)
+        .and_then(|price| {
+            let tax = config.tax.quote(None, &[(price, item.taxable)], certificate)?;
+            let tax_total = tax.total()?;
+            Ok((price.checked_add(tax_total)?, tax, tax_total))
+        });
+    let (amount, tax, tax_total) = match totals {
+        Ok(totals) => totals,
+        Err(error) => {
+            state.release_seat(reservation);
+            if let Some(promo) = promo {
+                state.release_promo(promo);
             }
+            return Err(Error::Tax(page, error));
         },
-        None => {
-            error!("Error: bad course type {}\n", signup.course_type);
-            return util::error(util::PaymentType::CourseSignup).await;
+    };
+
+    let promo_code = promo.as_ref().map(|promo| promo.code.clone());
+    let sale = util::Sale {
+        payment_type: util::PaymentType::CourseSignup,
+        description: &item.name,
+        amount,
+        tax: Some(tax),
+        promo_code: promo_code.as_deref(),
+    };
+    let mut order = Order{
+        item: signup.course_type.clone(),
+        amount: sale.amount,
+        email: signup.payment.email.clone(),
+        transaction_id: String::new(),
+        promo_code: promo_code.clone(),
+        tier,
+        quantity: 1,
+    };
+
+    let result = util::process_payment(&signup.payment, &sale, gateway.clone(), config.gateway_timeout(), ledger).await;
+    let transaction = match result {
+        Ok(transaction) => transaction,
+        //the card may still be charged so the seat and the promo code stay held until the charge is checked
+        Err(error @ gateway::Error::Pending) => {
+            pending.hold(&signup.checkout_token, PendingCharge::new(&signup.payment, &sale, Hold::Seats{
+                reservation,
+                promo,
+                orders: vec![order],
+                cart_id: None,
+                dates: item.dates.clone(),
+            }));
+            return Err(Error::Payment(page, error));
+        },
+        Err(error) => {
+            state.release_seat(reservation);
+            if let Some(promo) = promo {
+                state.release_promo(promo);
+            }
+            //send the buyer back to the form they filled in with the reason it failed
+            return render_item_page(config, gateway, checkouts, page, &item, signup, Some(&error)).await;
         },
+    };
+
+    order.transaction_id = transaction.id.clone();
+    state.commit_seat(reservation, &order);
+    if let Some(promo) = promo {
+        state.commit_promo(promo, &transaction.id);
     }
 
-    info!("inventory after course signup {:#?}\n", inventory);
-    serde_json::to_writer_pretty(
-        &File::create("inventory.json").expect("unable to open file"),
-        &inventory).expect("unable to write inventory.json");
+    let mut receipt = Receipt::new(&signup.payment, &sale.payment_type, &item.name, sale.amount, &transaction);
+    receipt.dates = item.dates.clone();
+    receipt.tax = tax_total;
+    mailer.send_receipt(&receipt);
 
-    util::thanks(util::PaymentType::CourseSignup).await
+    Ok(util::thanks(config, util::PaymentType::CourseSignup).await)
 }
 
 //----------------------------------------------------------------------------------------------------
 //----------------------------------------------------------------------------------------------------
-pub async fn store() -> HttpResponse {
-    let store = include_str!("../static/store.html");
-    let inventory = util::get_file::<BTreeMap<String, Item>>("inventory.json".to_string());
+pub async fn store(state : web::Data<State>) -> HttpResponse {
+    let inventory = state.items();
     info!("inventory in store {:#?}\n", inventory);
-    let mut items = String::new();
-    for (_key, item) in inventory.iter() {
-        items += item.get_entry().as_str();
-    }
+    let entries : Vec<StoreEntry> = state::listed(&inventory).into_iter()
+        .map(|(_, item)| StoreEntry::new(item))
+        .collect();
+
+    util::render(StatusCode::OK, &StorePage{ entries: &entries })
+}
 
-    let store = store.replace("ITEMS", &items);
+//----------------------------------------------------------------------------------------------------
+//----------------------------------------------------------------------------------------------------
+pub async fn item_page(
+    config : web::Data<Config>,
+    gateway : web::Data<Gateway>,
+    state : web::Data<State>,
+    checkouts : web::Data<Checkouts>,
+    formname : web::Path<String>) -> Result<HttpResponse, Error> {
+    let page = Page::new(&config, util::PaymentType::CourseSignup);
+
+    let item = match state.item(&formname) {
+        Some(item) if !item.archived => item,
+        _ => return Err(Error::NotFound(page, formname.into_inner())),
+    };
 
-    HttpResponse::Ok().content_type("text/html; charset=utf-8").body(store)
+    render_item_page(&config, &gateway, &checkouts, page, &item, &CourseSignup::default(), None).await
 }
 
 //----------------------------------------------------------------------------------------------------
 //----------------------------------------------------------------------------------------------------
-pub async fn item_page(braintree : web::Data<Mutex<Braintree>>, formname : String) -> HttpResponse {
-    let inventory = util::get_file::<BTreeMap<String, Item>>("inventory.json".to_string());
-    let item = inventory.get(&formname).unwrap();
-    let braintree = braintree.lock().unwrap();
-
-    HttpResponse::Ok()
-        .content_type("text/html; charset=utf-8")
-        .body(include_str!("../static/form.html")
-              .replace("COURSETYPE", &item.formname)
-              .replace("PRICE", format!("{}", item.price + item.discount).as_str())
-              .replace("DISCOUNT", format!("{}", &item.discount).as_str())
-              .replace("TOTAL", format!("{}", &item.price).as_str())
-              .replace(
-                  "CLIENT_TOKEN_FROM_SERVER",
-                  braintree.client_token().generate(Default::default()).expect("unable to get client token").value.as_str()))
+async fn render_item_page(
+    config : &Config,
+    gateway : &web::Data<Gateway>,
+    checkouts : &Checkouts,
+    page : Page,
+    item : &Item,
+    signup : &CourseSignup,
+    failure : Option<&dyn util::Failure>) -> Result<HttpResponse, Error> {
+    //a buyer is only known to be a member once they have typed the code, until then the public price is shown
+    let tier = config.members.tier(&signup.member_code).unwrap_or_default();
+    let price = item.price_for(tier);
+
+    //the page shows the full rate, an exemption is only checked once the buyer pays
+    let totals = config.tax.quote(None, &[(price, item.taxable)], None)
+        .and_then(|tax| Ok(tax.total()?))
+        .and_then(|tax| Ok((price.checked_add(item.discount)?, tax, price.checked_add(tax)?)));
+    let (full_price, tax, total) = match totals {
+        Ok(totals) => totals,
+        Err(error) => return Err(Error::Tax(page, error)),
+    };
+
+    let client_token = gateway::client_token(gateway, config.gateway_timeout()).await
+        .map_err(|error| Error::Payment(page, error))?;
+
+    Ok(util::render(util::checkout_status(failure), &ItemPage{
+        price: format!("{}", full_price),
+        discount: format!("{}", item.discount),
+        tax: format!("{}", tax),
+        total: format!("{}", total),
+        item,
+        client_token,
+        checkout_token: checkouts.issue(),
+        buyer: &signup.payment,
+        promo_code: &signup.promo_code,
+        member: tier == Tier::Member,
+        listed: tier != Tier::Member && config.members.member(&signup.payment.email).is_some(),
+        member_price: item.tier_prices.get(&Tier::Member).map_or(String::new(), |member_price| member_price.to_string()),
+        member_code: &signup.member_code,
+        tax_certificate: &signup.tax_certificate,
+        message: failure.map_or("", |failure| failure.buyer_message()),
+    }))
 }
 
+//----------------------------------------------------------------------------------------------------
+//----------------------------------------------------------------------------------------------------
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::test;
+    use crate::ledger::LedgerStatus;
+    use crate::testing::{assert_escaped, checkout_service, item, TestStore};
+
+    //--------------------------------------------------------------------------------------------------
+    //--------------------------------------------------------------------------------------------------
+    fn render(number_of_items : Option<i32>) -> String {
+        let item = Item {
+            number_of_items,
+            taxable: true,
+            tier_prices: BTreeMap::from([(Tier::Member, Money::from_cents(10000))]),
+            ..item()
+        };
+        StorePage{ entries: &[StoreEntry::new(item)] }.render().unwrap()
+    }
+
+    //--------------------------------------------------------------------------------------------------
+    //--------------------------------------------------------------------------------------------------
+    #[test]
+    fn store_page_escapes_item_fields() {
+        let html = render(Some(3));
+        assert_escaped(&html);
+        assert!(html.contains("$125.00 + tax"));
+        assert!(html.contains("Members $100.00"));
+        assert!(html.contains("3 Spaces Available"));
+        assert!(html.contains("max=\"3\""));
+
+        assert_escaped(&render(None));
+    }
+
+    //--------------------------------------------------------------------------------------------------
+    //--------------------------------------------------------------------------------------------------
+    #[test]
+    fn sold_out_class_has_no_buy_button() {
+        let html = render(Some(0));
+        assert!(html.contains("Sold Out"));
+        assert!(!html.contains("Buy Now"));
+        assert!(!html.contains("Add to cart"));
+    }
+
+    //--------------------------------------------------------------------------------------------------
+    //--------------------------------------------------------------------------------------------------
+    //seats a buyer can still take, and the seats stocked on disk that admin sees
+    fn seats(store : &TestStore) -> (Option<i32>, Option<i32>) {
+        (store.state.item("intro").unwrap().number_of_items, store.state.stocked_items()["intro"].number_of_items)
+    }
+
+    //--------------------------------------------------------------------------------------------------
+    //--------------------------------------------------------------------------------------------------
+    #[actix_rt::test]
+    async fn signup_charges_the_card_and_takes_the_seat() {
+        let store = TestStore::new();
+        let mut service = checkout_service!(store);
+
+        let request = store.checkout("/store/signup", &[("course_type", "intro")], "fake-valid-nonce").to_request();
+        let response = test::call_service(&mut service, request).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(seats(&store), (Some(4), Some(4)));
+
+        let entries = store.ledger.entries().unwrap();
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].status, LedgerStatus::Succeeded);
+        assert_eq!(entries[0].amount, Money::from_cents(10000));
+    }
+
+    //--------------------------------------------------------------------------------------------------
+    //--------------------------------------------------------------------------------------------------
+    #[actix_rt::test]
+    async fn declined_signup_gives_the_seat_back() {
+        let store = TestStore::new();
+        let mut service = checkout_service!(store);
+
+        let request = store.checkout("/store/signup", &[("course_type", "intro")], "fake-processor-declined-visa-nonce").to_request();
+        let response = test::call_service(&mut service, request).await;
+        assert_eq!(response.status(), StatusCode::PAYMENT_REQUIRED);
+        assert_eq!(seats(&store), (Some(5), Some(5)));
+        assert_eq!(store.ledger.entries().unwrap()[0].status, LedgerStatus::Failed);
+        assert!(store.pending.charges().is_empty());
+    }
+
+    //--------------------------------------------------------------------------------------------------
+    //--------------------------------------------------------------------------------------------------
+    #[actix_rt::test]
+    async fn timed_out_signup_holds_the_seat_until_it_is_resolved() {
+        let store = TestStore::new();
+        let mut service = checkout_service!(store);
+
+        let request = store.checkout("/store/signup", &[("course_type", "intro")], "fake-gateway-timeout-nonce").to_request();
+        let response = test::call_service(&mut service, request).await;
+        assert_eq!(response.status(), StatusCode::ACCEPTED);
+        assert_eq!(seats(&store), (Some(4), Some(5)));
+        assert_eq!(store.ledger.entries().unwrap()[0].status, LedgerStatus::Pending);
+
+        let charges = store.pending.charges();
+        assert_eq!(charges.len(), 1);
+        assert!(matches!(&charges.values().next().unwrap().hold, Hold::Seats{ orders, .. } if orders[0].item == "intro"));
+    }
+}
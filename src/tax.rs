@@ -0,0 +1,583 @@
+use actix_web::http::StatusCode;
+use chrono::NaiveDate;
+use chrono_tz::Tz;
+use log::{error, info};
+use serde::{Serialize, Deserialize};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::io::{self, Write};
+use std::str::FromStr;
+
+use crate::ledger::{csv_field, LedgerEntry, LedgerStatus};
+use crate::money::{Fraction, Money, MoneyError};
+use crate::util;
+
+const REPORT_HEADER : &str = "period,jurisdiction,gross_sales,nontaxable_sales,exempt_sales,taxable_sales,state_tax,county_tax,district_tax,total_tax";
+
+//----------------------------------------------------------------------------------------------------
+//----------------------------------------------------------------------------------------------------
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct Rate {
+    pub jurisdiction : String,
+    pub state : Fraction,
+    pub county : Fraction,
+    pub district : Fraction,
+}
+
+//----------------------------------------------------------------------------------------------------
+//----------------------------------------------------------------------------------------------------
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ExemptCustomer {
+    pub name : String,
+    pub certificate : String,
+    #[serde(default)]
+    pub expires : Option<NaiveDate>,
+}
+
+//----------------------------------------------------------------------------------------------------
+//----------------------------------------------------------------------------------------------------
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct TaxTable {
+    pub jurisdiction : String,
+    pub exempt_customers_file : String,
+    pub rates : Vec<Rate>,
+    #[serde(skip)]
+    pub exempt_customers : BTreeMap<String, ExemptCustomer>,
+}
+
+//----------------------------------------------------------------------------------------------------
+//----------------------------------------------------------------------------------------------------
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct TaxQuote {
+    pub jurisdiction : String,
+    pub nontaxable : Money,
+    pub exempt : Money,
+    pub taxable : Money,
+    pub certificate : Option<String>,
+    pub state : Money,
+    pub county : Money,
+    pub district : Money,
+}
+
+//----------------------------------------------------------------------------------------------------
+//----------------------------------------------------------------------------------------------------
+#[derive(Debug, Clone, PartialEq)]
+pub struct Period {
+    pub name : String,
+    pub start : NaiveDate,
+    pub end : NaiveDate,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ExemptionError {
+    Unverified,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum TaxError {
+    NoRate(String),
+    Amount(MoneyError),
+}
+
+//----------------------------------------------------------------------------------------------------
+//----------------------------------------------------------------------------------------------------
+#[derive(Debug, Clone, Default)]
+pub struct Liability {
+    pub jurisdiction : String,
+    pub nontaxable : Money,
+    pub exempt : Money,
+    pub taxable : Money,
+    pub state : Money,
+    pub county : Money,
+    pub district : Money,
+}
+
+//------------------------------------------------------------------------------------------------------
+//------------------------------------------------------------------------------------------------------
+impl Rate {
+    pub fn combined(&self) -> Result<Fraction, MoneyError> {
+        self.state.checked_add(self.county)?.checked_add(self.district)
+    }
+}
+
+//------------------------------------------------------------------------------------------------------
+//------------------------------------------------------------------------------------------------------
+impl Default for TaxTable {
+    fn default() -> TaxTable {
+        TaxTable {
+            jurisdiction: "goleta".to_string(),
+            exempt_customers_file: "tax_exempt_customers.json".to_string(),
+            rates: vec![Rate {
+                jurisdiction: "goleta".to_string(),
+                state: Fraction::from_millionths(60_000),
+                county: Fraction::from_millionths(12_500),
+                district: Fraction::from_millionths(15_000),
+            }],
+            exempt_customers: BTreeMap::new(),
+        }
+    }
+}
+
+//------------------------------------------------------------------------------------------------------
+//------------------------------------------------------------------------------------------------------
+impl TaxTable {
+
+    //--------------------------------------------------------------------------------------------------
+    //--------------------------------------------------------------------------------------------------
+    pub fn load_exempt_customers(&mut self) -> io::Result<()> {
+        let contents = match std::fs::read_to_string(&self.exempt_customers_file) {
+            Ok(contents) => contents,
+            Err(error) if error.kind() == io::ErrorKind::NotFound => return Ok(()),
+            Err(error) => return Err(error),
+        };
+
+        let customers : BTreeMap<String, ExemptCustomer> = serde_json::from_str(&contents).map_err(io::Error::from)?;
+        self.exempt_customers = customers.into_iter()
+            .map(|(email, customer)| (email.trim().to_lowercase(), customer))
+            .collect();
+
+        info!("loaded {} tax exempt customers from {}", self.exempt_customers.len(), self.exempt_customers_file);
+        Ok(())
+    }
+
+    //--------------------------------------------------------------------------------------------------
+    //--------------------------------------------------------------------------------------------------
+    pub fn rate(&self, jurisdiction : &str) -> Option<&Rate> {
+        self.rates.iter().find(|rate| rate.jurisdiction == jurisdiction)
+    }
+
+    //--------------------------------------------------------------------------------------------------
+    //--------------------------------------------------------------------------------------------------
+    // anyone can type an exempt customer's email, so a sale is only zero rated when the certificate
+    // number typed in with it matches the one on file
+    pub fn exemption(&self, email : &str, certificate : &str, today : NaiveDate) -> Result<Option<&ExemptCustomer>, ExemptionError> {
+        let certificate = certificate.trim();
+        if certificate.is_empty() {
+            return Ok(None);
+        }
+
+        self.exempt_customers.get(&email.trim().to_lowercase())
+            .filter(|customer| customer.expires.map_or(true, |expires| expires >= today))
+            .filter(|customer| customer.certificate.trim().eq_ignore_ascii_case(certificate))
+            .map(Some)
+            .ok_or(ExemptionError::Unverified)
+    }
+
+    //--------------------------------------------------------------------------------------------------
+    //--------------------------------------------------------------------------------------------------
+    // lines are (amount, taxable), tax is rounded once on the taxable total and the district share
+    // takes the rounding difference so the parts always add up to what the buyer was charged.
+    // with no jurisdiction the sale is taxed where the space is, classes and the cart always are
+    // since they are taught there, only an invoice names another one
+    pub fn quote(&self, jurisdiction : Option<&str>, lines : &[(Money, bool)], certificate : Option<&str>) -> Result<TaxQuote, TaxError> {
+        let rate = match jurisdiction.and_then(|jurisdiction| self.rate(jurisdiction)) {
+            Some(rate) => rate,
+            None => {
+                if let Some(jurisdiction) = jurisdiction {
+                    error!("Error: unknown tax jurisdiction {}, using {}\n", jurisdiction, self.jurisdiction);
+                }
+                //checked when the config is loaded, so only a table built in code can get here
+                self.rate(&self.jurisdiction).ok_or_else(|| TaxError::NoRate(self.jurisdiction.clone()))?
+            },
+        };
+
+        let mut quote = TaxQuote {
+            jurisdiction: rate.jurisdiction.clone(),
+            certificate: certificate.map(|certificate| certificate.to_string()),
+            ..Default::default()
+        };
+
+        for (amount, taxable) in lines.iter() {
+            let total = if !taxable {
+                &mut quote.nontaxable
+            } else if certificate.is_some() {
+                &mut quote.exempt
+            } else {
+                &mut quote.taxable
+            };
+            *total = total.checked_add(*amount)?;
+        }
+
+        let total = quote.taxable.times_rate(rate.combined()?)?;
+        quote.state = quote.taxable.times_rate(rate.state)?;
+        //the state and county shares round on their own, so with no district tax they can come to a cent
+        //more than the total, the county gives it up so the district share is never negative
+        quote.county = quote.taxable.times_rate(rate.county)?.min(total.checked_sub(quote.state)?);
+        quote.district = total.checked_sub(quote.state)?.checked_sub(quote.county)?;
+        Ok(quote)
+    }
+
+    //--------------------------------------------------------------------------------------------------
+    //--------------------------------------------------------------------------------------------------
+    pub fn validate(&self, problems : &mut Vec<String>) {
+        if self.rate(&self.jurisdiction).is_none() {
+            problems.push(format!("tax.jurisdiction {} has no entry in tax.rates", self.jurisdiction));
+        }
+
+        for rate in self.rates.iter() {
+            for (name, value) in [("state", rate.state), ("county", rate.county), ("district", rate.district)] {
+                if value >= Fraction::whole() {
+                    problems.push(format!("tax rate {} {} {} must be a fraction between 0 and 1, e.g. 0.06", rate.jurisdiction, name, value));
+                }
+            }
+            if self.rates.iter().filter(|other| other.jurisdiction == rate.jurisdiction).count() > 1 {
+                problems.push(format!("tax jurisdiction {} is listed more than once", rate.jurisdiction));
+            }
+        }
+
+        for (email, customer) in self.exempt_customers.iter() {
+            if customer.certificate.trim().is_empty() {
+                problems.push(format!("tax exempt customer {} has no certificate number", email));
+            }
+        }
+    }
+}
+
+//------------------------------------------------------------------------------------------------------
+//------------------------------------------------------------------------------------------------------
+impl util::Failure for ExemptionError {
+
+    //--------------------------------------------------------------------------------------------------
+    //--------------------------------------------------------------------------------------------------
+    fn status_code(&self) -> StatusCode {
+        StatusCode::UNPROCESSABLE_ENTITY
+    }
+
+    //--------------------------------------------------------------------------------------------------
+    //--------------------------------------------------------------------------------------------------
+    fn buyer_message(&self) -> &'static str {
+        match self {
+            ExemptionError::Unverified => "That exemption certificate does not match the one we have on file for your email. Please check it, or leave it blank to pay sales tax.",
+        }
+    }
+}
+
+//------------------------------------------------------------------------------------------------------
+//------------------------------------------------------------------------------------------------------
+impl From<MoneyError> for TaxError {
+    fn from(error : MoneyError) -> TaxError {
+        TaxError::Amount(error)
+    }
+}
+
+//------------------------------------------------------------------------------------------------------
+//------------------------------------------------------------------------------------------------------
+impl fmt::Display for TaxError {
+    fn fmt(&self, f : &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            TaxError::NoRate(jurisdiction) => write!(f, "tax jurisdiction {} has no rate", jurisdiction),
+            TaxError::Amount(error) => write!(f, "{}", error),
+        }
+    }
+}
+
+//------------------------------------------------------------------------------------------------------
+//------------------------------------------------------------------------------------------------------
+impl TaxQuote {
+    pub fn total(&self) -> Result<Money, MoneyError> {
+        Money::total([self.state, self.county, self.district])
+    }
+}
+
+//------------------------------------------------------------------------------------------------------
+//------------------------------------------------------------------------------------------------------
+impl fmt::Display for Period {
+    fn fmt(&self, f : &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.name)
+    }
+}
+
+//------------------------------------------------------------------------------------------------------
+//------------------------------------------------------------------------------------------------------
+impl FromStr for Period {
+    type Err = String;
+
+    //a filing period is a year "2026", a quarter "2026-Q3" or a month "2026-07"
+    fn from_str(text : &str) -> Result<Period, String> {
+        let invalid = || format!("{} is not a filing period, use 2026, 2026-Q3 or 2026-07", text);
+
+        let (year, part) = match text.trim().split_once('-') {
+            Some((year, part)) => (year, Some(part)),
+            None => (text.trim(), None),
+        };
+        let year : i32 = year.parse().map_err(|_| invalid())?;
+
+        let (first_month, months) = match part {
+            None => (1, 12),
+            Some(quarter) if quarter.starts_with('Q') || quarter.starts_with('q') => {
+                match quarter[1..].parse::<u32>() {
+                    Ok(quarter) if (1..=4).contains(&quarter) => ((quarter - 1) * 3 + 1, 3),
+                    _ => return Err(invalid()),
+                }
+            },
+            Some(month) => match month.parse::<u32>() {
+                Ok(month) if (1..=12).contains(&month) => (month, 1),
+                _ => return Err(invalid()),
+            },
+        };
+
+        let start = NaiveDate::from_ymd_opt(year, first_month, 1).ok_or_else(invalid)?;
+        let end_month = first_month + months;
+        let end = if end_month > 12 {
+            NaiveDate::from_ymd_opt(year + 1, end_month - 12, 1)
+        } else {
+            NaiveDate::from_ymd_opt(year, end_month, 1)
+        }.ok_or_else(invalid)?;
+
+        Ok(Period { name: text.trim().to_string(), start, end })
+    }
+}
+
+//------------------------------------------------------------------------------------------------------
+//------------------------------------------------------------------------------------------------------
+impl Period {
+    pub fn contains(&self, date : NaiveDate) -> bool {
+        self.start <= date && date < self.end
+    }
+}
+
+//------------------------------------------------------------------------------------------------------
+//------------------------------------------------------------------------------------------------------
+impl Liability {
+
+    //--------------------------------------------------------------------------------------------------
+    //--------------------------------------------------------------------------------------------------
+    pub fn gross(&self) -> Result<Money, MoneyError> {
+        Money::total([self.nontaxable, self.exempt, self.taxable])
+    }
+
+    //--------------------------------------------------------------------------------------------------
+    //--------------------------------------------------------------------------------------------------
+    pub fn total_tax(&self) -> Result<Money, MoneyError> {
+        Money::total([self.state, self.county, self.district])
+    }
+
+    //--------------------------------------------------------------------------------------------------
+    //--------------------------------------------------------------------------------------------------
+    fn add(&mut self, quote : &TaxQuote) -> Result<(), MoneyError> {
+        self.nontaxable = self.nontaxable.checked_add(quote.nontaxable)?;
+        self.exempt = self.exempt.checked_add(quote.exempt)?;
+        self.taxable = self.taxable.checked_add(quote.taxable)?;
+        self.state = self.state.checked_add(quote.state)?;
+        self.county = self.county.checked_add(quote.county)?;
+        self.district = self.district.checked_add(quote.district)?;
+        Ok(())
+    }
+
+    //--------------------------------------------------------------------------------------------------
+    //--------------------------------------------------------------------------------------------------
+    pub fn to_csv(&self, period : &Period) -> Result<String, MoneyError> {
+        Ok([
+            period.to_string(),
+            self.jurisdiction.clone(),
+            self.gross()?.to_string(),
+            self.nontaxable.to_string(),
+            self.exempt.to_string(),
+            self.taxable.to_string(),
+            self.state.to_string(),
+            self.county.to_string(),
+            self.district.to_string(),
+            self.total_tax()?.to_string(),
+        ].iter().map(|field| csv_field(field)).collect::<Vec<String>>().join(","))
+    }
+}
+
+//----------------------------------------------------------------------------------------------------
+//----------------------------------------------------------------------------------------------------
+pub fn liability(entries : &[LedgerEntry], period : &Period, timezone : &Tz) -> Result<Vec<Liability>, MoneyError> {
+    let mut jurisdictions : BTreeMap<String, Liability> = BTreeMap::new();
+
+    //only sales carry a tax quote, donations never show up here
+    //the timestamp is utc but a sale belongs to the period of the day it was made at the store
+    for entry in entries.iter() {
+        let quote = match entry.tax.as_ref() {
+            Some(quote) if entry.status == LedgerStatus::Succeeded && period.contains(entry.timestamp.with_timezone(timezone).date_naive()) => quote,
+            _ => continue,
+        };
+
+        let liability = jurisdictions.entry(quote.jurisdiction.clone()).or_insert_with(|| Liability {
+            jurisdiction: quote.jurisdiction.clone(),
+            ..Default::default()
+        });
+        liability.add(quote)?;
+    }
+
+    Ok(jurisdictions.into_values().collect())
+}
+
+//----------------------------------------------------------------------------------------------------
+//----------------------------------------------------------------------------------------------------
+pub fn write_liability<W : Write>(entries : &[LedgerEntry], period : &Period, timezone : &Tz, writer : &mut W) -> io::Result<()> {
+    writeln!(writer, "{}", REPORT_HEADER)?;
+    let invalid = |error : MoneyError| io::Error::new(io::ErrorKind::InvalidData, error.to_string());
+    for liability in liability(entries, period, timezone).map_err(invalid)?.iter() {
+        writeln!(writer, "{}", liability.to_csv(period).map_err(invalid)?)?;
+    }
+    Ok(())
+}
+
+
+//----------------------------------------------------------------------------------------------------
+//----------------------------------------------------------------------------------------------------
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    //--------------------------------------------------------------------------------------------------
+    //--------------------------------------------------------------------------------------------------
+    fn cents(cents : i64) -> Money {
+        Money::from_cents(cents)
+    }
+
+    //--------------------------------------------------------------------------------------------------
+    //--------------------------------------------------------------------------------------------------
+    //the default table with a second jurisdiction that has no district tax and one exempt customer
+    fn table() -> TaxTable {
+        let mut table = TaxTable::default();
+        table.rates.push(Rate {
+            jurisdiction: "santa barbara".to_string(),
+            state: Fraction::from_millionths(60_000),
+            county: Fraction::from_millionths(12_500),
+            district: Fraction::from_millionths(0),
+        });
+        table.exempt_customers.insert("school@example.org".to_string(), ExemptCustomer {
+            name: "Goleta School".to_string(),
+            certificate: "SR-1234".to_string(),
+            expires: NaiveDate::from_ymd_opt(2026, 12, 31),
+        });
+        table
+    }
+
+    //--------------------------------------------------------------------------------------------------
+    //--------------------------------------------------------------------------------------------------
+    fn sale(timestamp : &str, status : LedgerStatus, taxable : i64, state : i64) -> LedgerEntry {
+        LedgerEntry {
+            timestamp: timestamp.parse().unwrap(),
+            payment_type: "course_signup".to_string(),
+            description: "intro".to_string(),
+            amount: cents(taxable + state),
+            first_name: "Ada".to_string(),
+            last_name: "Lovelace".to_string(),
+            email: "ada@example.org".to_string(),
+            transaction_id: Some("txn".to_string()),
+            status,
+            error: None,
+            tax: Some(TaxQuote{ jurisdiction: "goleta".to_string(), taxable: cents(taxable), state: cents(state), ..Default::default() }),
+        }
+    }
+
+    //--------------------------------------------------------------------------------------------------
+    //--------------------------------------------------------------------------------------------------
+    #[test]
+    fn default_rate_adds_up() {
+        let table = TaxTable::default();
+        let mut problems = Vec::new();
+        table.validate(&mut problems);
+        assert!(problems.is_empty(), "{:?}", problems);
+        assert_eq!(table.rate("goleta").unwrap().combined().unwrap(), Fraction::from_millionths(87_500));
+    }
+
+    //--------------------------------------------------------------------------------------------------
+    //--------------------------------------------------------------------------------------------------
+    #[test]
+    fn quote_splits_the_tax_between_state_county_and_district() {
+        let quote = table().quote(None, &[(cents(40), true), (cents(1000), false)], None).unwrap();
+        assert_eq!(quote.jurisdiction, "goleta");
+        assert_eq!(quote.taxable, cents(40));
+        assert_eq!(quote.nontaxable, cents(1000));
+        assert_eq!(quote.exempt, Money::zero());
+        //3.5 cents rounds up, the shares round on their own and the district takes what is left
+        assert_eq!(quote.total().unwrap(), cents(4));
+        assert_eq!((quote.state, quote.county, quote.district), (cents(2), cents(1), cents(1)));
+
+        let exempt = table().quote(None, &[(cents(40), true), (cents(1000), false)], Some("SR-1234")).unwrap();
+        assert_eq!(exempt.exempt, cents(40));
+        assert_eq!(exempt.taxable, Money::zero());
+        assert_eq!(exempt.total().unwrap(), Money::zero());
+        assert_eq!(exempt.certificate.as_deref(), Some("SR-1234"));
+    }
+
+    //--------------------------------------------------------------------------------------------------
+    //--------------------------------------------------------------------------------------------------
+    #[test]
+    fn district_share_is_never_negative() {
+        //state 2.52 and county 0.525 both round up but the total of 3.045 rounds down
+        let quote = table().quote(Some("santa barbara"), &[(cents(42), true)], None).unwrap();
+        assert_eq!(quote.total().unwrap(), cents(3));
+        assert_eq!((quote.state, quote.county, quote.district), (cents(3), Money::zero(), Money::zero()));
+    }
+
+    //--------------------------------------------------------------------------------------------------
+    //--------------------------------------------------------------------------------------------------
+    #[test]
+    fn unknown_jurisdiction_uses_the_default_and_a_missing_default_is_an_error() {
+        let quote = table().quote(Some("atlantis"), &[(cents(40), true)], None).unwrap();
+        assert_eq!(quote.jurisdiction, "goleta");
+
+        let table = TaxTable{ jurisdiction: "atlantis".to_string(), ..table() };
+        match table.quote(None, &[(cents(40), true)], None) {
+            Err(TaxError::NoRate(jurisdiction)) => assert_eq!(jurisdiction, "atlantis"),
+            other => panic!("expected no rate, got {:?}", other),
+        }
+    }
+
+    //--------------------------------------------------------------------------------------------------
+    //--------------------------------------------------------------------------------------------------
+    #[test]
+    fn exemption_needs_the_certificate_on_file() {
+        let table = table();
+        let today = NaiveDate::from_ymd_opt(2026, 10, 18).unwrap();
+
+        assert_eq!(table.exemption("school@example.org", " ", today).map(|customer| customer.is_some()), Ok(false));
+        assert_eq!(table.exemption(" School@Example.org ", "sr-1234", today).unwrap().unwrap().name, "Goleta School");
+
+        assert_eq!(table.exemption("school@example.org", "SR-9999", today).err(), Some(ExemptionError::Unverified));
+        assert_eq!(table.exemption("ada@example.org", "SR-1234", today).err(), Some(ExemptionError::Unverified));
+
+        let expired = NaiveDate::from_ymd_opt(2027, 1, 1).unwrap();
+        assert_eq!(table.exemption("school@example.org", "SR-1234", expired).err(), Some(ExemptionError::Unverified));
+    }
+
+    //--------------------------------------------------------------------------------------------------
+    //--------------------------------------------------------------------------------------------------
+    #[test]
+    fn parses_filing_periods() {
+        let period = |text : &str| text.parse::<Period>().unwrap();
+        let date = |year, month| NaiveDate::from_ymd_opt(year, month, 1).unwrap();
+
+        assert_eq!((period("2026").start, period("2026").end), (date(2026, 1), date(2027, 1)));
+        assert_eq!((period("2026-Q4").start, period("2026-Q4").end), (date(2026, 10), date(2027, 1)));
+        assert_eq!((period("2026-q2").start, period("2026-q2").end), (date(2026, 4), date(2026, 7)));
+        assert_eq!((period("2026-07").start, period("2026-07").end), (date(2026, 7), date(2026, 8)));
+        assert_eq!(period(" 2026-12 ").end, date(2027, 1));
+
+        for text in ["", "26-", "2026-Q5", "2026-Q0", "2026-13", "2026-00", "2026-Q", "twenty"] {
+            assert!(text.parse::<Period>().is_err(), "{} parsed", text);
+        }
+    }
+
+    //--------------------------------------------------------------------------------------------------
+    //--------------------------------------------------------------------------------------------------
+    #[test]
+    fn liability_counts_a_sale_in_the_period_of_its_local_day() {
+        let entries = [
+            //the evening of june 30th at the store
+            sale("2026-07-01T05:00:00Z", LedgerStatus::Succeeded, 10000, 600),
+            sale("2026-06-15T18:00:00Z", LedgerStatus::Succeeded, 5000, 300),
+            sale("2026-06-16T18:00:00Z", LedgerStatus::Failed, 5000, 300),
+            sale("2026-07-02T18:00:00Z", LedgerStatus::Succeeded, 2000, 120),
+        ];
+        let june : Period = "2026-06".parse().unwrap();
+
+        let local = liability(&entries, &june, &chrono_tz::America::Los_Angeles).unwrap();
+        assert_eq!(local.len(), 1);
+        assert_eq!(local[0].taxable, cents(15000));
+        assert_eq!(local[0].total_tax().unwrap(), cents(900));
+        assert_eq!(local[0].to_csv(&june).unwrap(), "\"2026-06\",\"goleta\",\"150.00\",\"0.00\",\"0.00\",\"150.00\",\"9.00\",\"0.00\",\"0.00\",\"9.00\"");
+
+        let utc = liability(&entries, &june, &chrono_tz::UTC).unwrap();
+        assert_eq!(utc[0].taxable, cents(5000));
+    }
+}
@@ -0,0 +1,216 @@
+use actix_web::{test, web};
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use crate::checkout::{self, Checkouts};
+use crate::config::{Config, MailConfig};
+use crate::fundraise::Fundraiser;
+use crate::gateway::{FakeGateway, Gateway};
+use crate::ledger::Ledger;
+use crate::mail::{self, Mailer};
+use crate::money::{Fraction, Money};
+use crate::pending::PendingCharges;
+use crate::persist;
+use crate::promo::{Discount, PromoCode};
+use crate::quote::{Invoice, InvoiceRepository, InvoiceStatus, LineItem};
+use crate::state::State;
+use crate::storage::{self, Storage};
+use crate::store::Item;
+
+//what buyers and admins could type into any field, templates must never let it through raw
+pub const PAYLOAD : &str = "<script>alert(\"x\")</script> Tom & Jerry's";
+
+//----------------------------------------------------------------------------------------------------
+//----------------------------------------------------------------------------------------------------
+pub fn assert_escaped(html : &str) {
+    assert!(html.contains("&lt;script&gt;alert(&quot;x&quot;)"), "markup not escaped in {}", html);
+    assert!(html.contains("Tom &amp; Jerry&#x27;s"), "ampersand or quote not escaped in {}", html);
+    assert!(!html.contains("<script>"), "raw script tag in {}", html);
+    assert!(!html.contains("alert(\"x\")"), "raw double quotes in {}", html);
+    assert!(!html.contains("Jerry's"), "raw single quote in {}", html);
+}
+
+//----------------------------------------------------------------------------------------------------
+//----------------------------------------------------------------------------------------------------
+//a class with 4 seats at $125.00 and the payload in every text field
+pub fn item() -> Item {
+    Item {
+        number_of_items: Some(4),
+        price: Money::from_cents(12500),
+        discount: Money::zero(),
+        name: PAYLOAD.to_string(),
+        formname: PAYLOAD.to_string(),
+        image: PAYLOAD.to_string(),
+        dates: PAYLOAD.to_string(),
+        archived: false,
+        position: 0,
+        taxable: false,
+        tier_prices: BTreeMap::new(),
+    }
+}
+
+//----------------------------------------------------------------------------------------------------
+//----------------------------------------------------------------------------------------------------
+//$250.00 raised of $1000.00 with the payload in every text field
+pub fn fundraiser() -> Fundraiser {
+    Fundraiser {
+        name: PAYLOAD.to_string(),
+        goal: Money::from_cents(100000),
+        amount_raised: Money::from_cents(25000),
+        formname: PAYLOAD.to_string(),
+        image: PAYLOAD.to_string(),
+        description: PAYLOAD.to_string(),
+        archived: false,
+        position: 0,
+    }
+}
+
+//----------------------------------------------------------------------------------------------------
+//----------------------------------------------------------------------------------------------------
+//10% off the payload class, unused of 5
+pub fn promo() -> PromoCode {
+    PromoCode {
+        discount: Discount::Percent(Fraction::from_millionths(100_000)),
+        item: Some(PAYLOAD.to_string()),
+        expires: None,
+        max_uses: Some(5),
+        max_uses_per_email: None,
+        redemptions: Vec::new(),
+    }
+}
+
+//----------------------------------------------------------------------------------------------------
+//----------------------------------------------------------------------------------------------------
+//a sent invoice for one $250.00 line with the payload as its description
+pub fn invoice() -> Invoice {
+    Invoice {
+        invoice_id: "1001".to_string(),
+        line_items: vec![LineItem{ description: PAYLOAD.to_string(), quantity: 1, unit_price: Money::from_cents(25000), taxable: false }],
+        due_date: None,
+        disable_sales_tax: true,
+        fees: Money::zero(),
+        status: InvoiceStatus::Sent,
+        amount_paid: Money::zero(),
+        transaction_ids: Vec::new(),
+        jurisdiction: None,
+        tax_exempt_certificate: None,
+    }
+}
+
+//----------------------------------------------------------------------------------------------------
+//----------------------------------------------------------------------------------------------------
+// a store on its own json files in a temporary directory, charging through the fake gateway,
+// stocked with the class "intro" at $100.00, the fundraiser "tools" and the invoice "1001"
+pub struct TestStore {
+    directory : PathBuf,
+    pub config : web::Data<Config>,
+    pub gateway : web::Data<Gateway>,
+    pub ledger : web::Data<Ledger>,
+    pub mailer : web::Data<Mailer>,
+    pub state : web::Data<State>,
+    pub invoices : web::Data<InvoiceRepository>,
+    pub pending : web::Data<PendingCharges>,
+    pub checkouts : web::Data<Checkouts>,
+}
+
+//------------------------------------------------------------------------------------------------------
+//------------------------------------------------------------------------------------------------------
+impl TestStore {
+
+    //--------------------------------------------------------------------------------------------------
+    //--------------------------------------------------------------------------------------------------
+    pub fn new() -> TestStore {
+        let directory = std::env::temp_dir().join(format!("sbhx_store_test_{}", checkout::random_token()));
+        std::fs::create_dir_all(&directory).unwrap();
+        let file = |name : &str| directory.join(name).to_string_lossy().to_string();
+
+        let config = Config {
+            inventory_file: file("inventory.json"),
+            fundraisers_file: file("fundraising_goals.json"),
+            invoices_file: file("invoices.json"),
+            promo_codes_file: file("promo_codes.json"),
+            ledger_file: file("ledger.jsonl"),
+            orders_file: file("orders.jsonl"),
+            donations_file: file("donations.jsonl"),
+            pending_file: file("pending_charges.json"),
+            verify_gateway: false,
+            gateway_timeout_seconds: 5,
+            ..Config::default()
+        };
+
+        let intro = Item{ number_of_items: Some(5), price: Money::from_cents(10000), formname: "intro".to_string(), ..item() };
+        persist::save(&config.inventory_file, &BTreeMap::from([("intro".to_string(), intro)])).unwrap();
+
+        let tools = Fundraiser{ amount_raised: Money::zero(), formname: "tools".to_string(), ..fundraiser() };
+        persist::save(&config.fundraisers_file, &BTreeMap::from([("tools".to_string(), tools)])).unwrap();
+
+        persist::save(&config.invoices_file, &BTreeMap::from([("1001".to_string(), invoice())])).unwrap();
+
+        let storage : Arc<dyn Storage> = Arc::new(storage::json_storage(&config));
+        let fake : Gateway = Box::new(FakeGateway::new());
+
+        TestStore {
+            gateway: web::Data::new(fake),
+            ledger: web::Data::new(Ledger::new(storage.clone())),
+            mailer: web::Data::new(mail::from_config(&MailConfig::default()).unwrap()),
+            state: web::Data::new(State::load(storage.clone()).unwrap()),
+            invoices: web::Data::new(InvoiceRepository::new(storage.clone(), config.tax.clone())),
+            pending: web::Data::new(PendingCharges::load(storage).unwrap()),
+            checkouts: web::Data::new(Checkouts::new()),
+            config: web::Data::new(config),
+            directory,
+        }
+    }
+
+    //--------------------------------------------------------------------------------------------------
+    //--------------------------------------------------------------------------------------------------
+    //a checkout form with a fresh token and the buyer paying with the given sandbox nonce
+    pub fn checkout(&self, uri : &str, fields : &[(&str, &str)], nonce : &str) -> test::TestRequest {
+        let token = self.checkouts.issue();
+        let mut form : Vec<(&str, &str)> = vec![
+            ("checkout_token", token.as_str()),
+            ("first_name", "Ada"),
+            ("last_name", "Lovelace"),
+            ("email", "ada@example.org"),
+            ("address", "1 Main St"),
+            ("address2", ""),
+            ("city", "Goleta"),
+            ("state", "CA"),
+            ("payment_method_nonce", nonce),
+        ];
+        form.extend_from_slice(fields);
+
+        test::TestRequest::post().uri(uri).set_form(&form)
+    }
+}
+
+//------------------------------------------------------------------------------------------------------
+//------------------------------------------------------------------------------------------------------
+impl Drop for TestStore {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.directory);
+    }
+}
+
+//----------------------------------------------------------------------------------------------------
+//----------------------------------------------------------------------------------------------------
+//the checkout routes of a TestStore as main sets them up
+macro_rules! checkout_service {
+    ($store:expr) => {
+        actix_web::test::init_service(actix_web::App::new()
+            .app_data($store.checkouts.clone())
+            .app_data($store.config.clone())
+            .app_data($store.gateway.clone())
+            .app_data($store.invoices.clone())
+            .app_data($store.ledger.clone())
+            .app_data($store.mailer.clone())
+            .app_data($store.pending.clone())
+            .app_data($store.state.clone())
+            .route("/store/signup", actix_web::web::post().to($crate::store::course_signup))
+            .route("/store/cart/checkout", actix_web::web::post().to($crate::cart::checkout_cart))
+            .route("/quote/process_invoice", actix_web::web::post().to($crate::quote::process_invoice))
+            .route("/donate/process_donation", actix_web::web::post().to($crate::fundraise::process_donation))).await
+    };
+}
+pub(crate) use checkout_service;
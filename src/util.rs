@@ -1,13 +1,19 @@
 use actix_web::{web, HttpResponse};
+use actix_web::http::StatusCode;
+use askama::Template;
 use serde::{Serialize, Deserialize};
-use log::{debug};
+use log::{debug, error, info, warn};
 use std::collections::HashMap;
-use std::sync::{Mutex};
-use std::fs::File;
-use std::io::BufReader;
-use braintree::{Address, Braintree, CreditCard, Customer};
+use std::time::Duration;
 
-#[derive(Deserialize,Debug, Serialize)]
+use crate::checkout;
+use crate::config::{Config, Urls};
+use crate::gateway::{self, Gateway, Transaction, TransactionRequest};
+use crate::ledger::{Ledger, LedgerEntry};
+use crate::money::Money;
+use crate::tax::TaxQuote;
+
+#[derive(Deserialize,Debug, Serialize, Clone, Default)]
 pub struct Payment {
     pub first_name : String,
     pub last_name : String,
@@ -20,6 +26,25 @@ pub struct Payment {
     pub company_name : Option<String>,
 }
 
+//----------------------------------------------------------------------------------------------------
+//----------------------------------------------------------------------------------------------------
+pub struct Sale<'a> {
+    pub payment_type : PaymentType,
+    pub description : &'a str,
+    pub amount : Money,
+    pub tax : Option<TaxQuote>,
+    pub promo_code : Option<&'a str>,
+}
+
+//----------------------------------------------------------------------------------------------------
+//----------------------------------------------------------------------------------------------------
+//anything that sends the buyer back to the checkout form with a reason
+pub trait Failure {
+    fn status_code(&self) -> StatusCode;
+
+    fn buyer_message(&self) -> &'static str;
+}
+
 pub enum PaymentType {
     CourseSignup,
     Donation,
@@ -27,7 +52,7 @@ pub enum PaymentType {
 }
 
 impl PaymentType {
-    fn as_str(&self) -> &'static str {
+    pub fn as_str(&self) -> &'static str {
          match self {
             PaymentType::CourseSignup => "Course Signup",
             PaymentType::Donation => "Donation",
@@ -35,93 +60,156 @@ impl PaymentType {
         }
     }
 
-    fn get_url(&self) -> &'static str {
+    pub fn get_url<'a>(&self, urls : &'a Urls) -> &'a str {
          match self {
-            PaymentType::CourseSignup => "https://store.sbhackerspace.com",
-            PaymentType::Donation => "https://donate.sbhackerspace.com",
-            PaymentType::Invoice => "https://invoice.sbhackerspace.com",
+            PaymentType::CourseSignup => &urls.store,
+            PaymentType::Donation => &urls.donate,
+            PaymentType::Invoice => &urls.invoice,
         }
     }
 }
 
+#[derive(Template)]
+#[template(path = "thanks.html")]
+struct ThanksPage<'a> {
+    name : &'static str,
+    url : &'a str,
+}
+
 //----------------------------------------------------------------------------------------------------
 //----------------------------------------------------------------------------------------------------
-pub async fn thanks(payment_type: PaymentType) -> HttpResponse {
-    HttpResponse::Ok()
-        .content_type("text/html; charset=utf-8")
-        .body(include_str!("../static/thanks.html")
-            .replace("NAME", payment_type.as_str())
-            .replace("URL", payment_type.get_url()))
+pub fn render<T : Template>(status : StatusCode, template : &T) -> HttpResponse {
+    match template.render() {
+        Ok(body) => HttpResponse::build(status).content_type("text/html; charset=utf-8").body(body),
+        Err(render_error) => {
+            error!("Error: unable to render template {:?}\n", render_error);
+            HttpResponse::InternalServerError().finish()
+        },
+    }
 }
 
 //----------------------------------------------------------------------------------------------------
 //----------------------------------------------------------------------------------------------------
-pub async fn error(payment_type: PaymentType) -> HttpResponse {
-    HttpResponse::Ok()
-        .content_type("text/html; charset=utf-8")
-        .body(include_str!("../static/error.html")
-            .replace("NAME", payment_type.as_str())
-            .replace("URL", payment_type.get_url()))
+pub fn checkout_status<F : Failure + ?Sized>(failure : Option<&F>) -> StatusCode {
+    failure.map_or(StatusCode::OK, |failure| failure.status_code())
+}
 
+//----------------------------------------------------------------------------------------------------
+//----------------------------------------------------------------------------------------------------
+//for secrets, the time taken does not give away how much of a guess was right
+pub fn constant_time_eq(left : &[u8], right : &[u8]) -> bool {
+    if left.len() != right.len() {
+        return false;
+    }
+    left.iter().zip(right.iter()).fold(0, |difference, (l, r)| difference | (l ^ r)) == 0
+}
 
+//----------------------------------------------------------------------------------------------------
+//----------------------------------------------------------------------------------------------------
+pub async fn thanks(config : &Config, payment_type: PaymentType) -> HttpResponse {
+    render(StatusCode::OK, &ThanksPage{ name: payment_type.as_str(), url: payment_type.get_url(&config.urls) })
 }
 
 //----------------------------------------------------------------------------------------------------
 //----------------------------------------------------------------------------------------------------
-pub fn process_payment(payment : &Payment, price: f32, braintree : web::Data<Mutex<Braintree>>, payment_type: PaymentType, description: &String) -> Result<braintree::transaction::Transaction, braintree::Error>{
-    let braintree = braintree.lock().unwrap();
+pub async fn process_payment(
+    payment : &Payment,
+    sale : &Sale<'_>,
+    gateway : web::Data<Gateway>,
+    timeout : Duration,
+    ledger : &Ledger) -> Result<Transaction, gateway::Error>{
+    let result = charge(payment, sale, &gateway, timeout).await;
 
-    debug!("trying to generate customer\n");
-    let result = braintree.customer().generate(Customer{
-        email: Some(payment.email.to_string()),
-        first_name: Some(payment.first_name.to_string()),
-        last_name: Some(payment.last_name.to_string()),
-        company: payment.company_name.clone(),
-        payment_method_nonce: Some(payment.payment_method_nonce.to_string()),
-        credit_card: Some(CreditCard{
-            billing_address: Some(Address{
-                first_name: Some(payment.first_name.to_string()),
-                last_name: Some(payment.last_name.to_string()),
-                locality: Some(payment.city.to_string()),
-                region: Some(payment.state.to_string()),
-                street_address: Some(payment.address.to_string()),
-                ..Default::default()
-            }),
-            ..Default::default()
-        }),
-        ..Default::default()
-    });
-
-    debug!("customer = {:?}\n", result);
-    match result {
-        Ok(customer) => {
-            braintree.transaction().create(braintree::transaction::Request{
-                amount: format!("{:.2}", price),
-                payment_method_token: customer.credit_card.unwrap().token,
-                options: Some(braintree::transaction::Options{
-                    submit_for_settlement: Some(true),
-                    ..Default::default()
-                }),
-                descriptor: Some(braintree::descriptor::Descriptor{
-                    name: Some("sbhx   *   product".to_string()),
-                    url: Some("".to_string()),
-                    phone: Some("8052422533".to_string()),
-                }),
-                custom_fields: HashMap::from([("payment_type".to_string(), payment_type.as_str().to_string()), ("description".to_string(), description.clone())]),
-                ..Default::default()
-            })
-        },
-        Err(error) => Err(error),
+    if let Err(error) = &result {
+        if let gateway::Error::Pending = error {
+            error!("Error: {} payment of {} for {} has an unknown result, check it in braintree\n", sale.payment_type.as_str(), sale.amount, payment.email);
+        } else if error.status_code().is_server_error() {
+            error!("Error: {} payment failed {:?}\n", sale.payment_type.as_str(), error);
+        } else {
+            warn!("{} payment failed {:?}\n", sale.payment_type.as_str(), error);
+        }
     }
+
+    ledger.record(&LedgerEntry::new(payment, sale, &result));
+    result
 }
 
 //----------------------------------------------------------------------------------------------------
 //----------------------------------------------------------------------------------------------------
-pub fn get_file<T: serde::de::DeserializeOwned>(file_name: String) -> T
-{
-    let file = File::open(&file_name).expect(format!("unable to open {:}", &file_name.as_str()).as_str());
-    let reader = BufReader::new(file);
-    let data :T = serde_json::from_reader(reader).expect("failure reading inventory.json");
-    data
+async fn charge(
+    payment : &Payment,
+    sale : &Sale<'_>,
+    gateway : &web::Data<Gateway>,
+    timeout : Duration) -> Result<Transaction, gateway::Error>{
+    //braintree refuses a $0 transaction so a class a promo code made free is completed without it,
+    //any other amount that isn't positive is a mistake and must never be recorded as paid
+    if !sale.amount.is_positive() {
+        let promo_made_free = match (&sale.payment_type, sale.promo_code) {
+            (PaymentType::CourseSignup, Some(_)) => sale.amount.is_zero(),
+            _ => false,
+        };
+        if !promo_made_free {
+            return Err(gateway::Error::Validation(format!("{} of {} is not a positive amount", sale.payment_type.as_str(), sale.amount)));
+        }
+
+        let transaction = Transaction{ id: format!("free-{}", checkout::random_token()), amount: sale.amount.to_string() };
+        info!("{} for {} is free with promo code {}, recorded as {}\n",
+            sale.payment_type.as_str(), payment.email, sale.promo_code.unwrap_or_default(), transaction.id);
+        return Ok(transaction);
+    }
+
+    let customer_payment = payment.clone();
+
+    debug!("trying to generate customer\n");
+    let customer = gateway::call(gateway, timeout, move |gateway| gateway.create_customer(&customer_payment)).await?;
+
+    debug!("customer = {:?}\n", customer);
+    let request = TransactionRequest{
+        amount: sale.amount.to_string(),
+        payment_method_token: customer.payment_method_token,
+        custom_fields: HashMap::from([("payment_type".to_string(), sale.payment_type.as_str().to_string()), ("description".to_string(), sale.description.to_string())]),
+    };
+    //a charge that timed out keeps running at braintree, so it may still go through and is not a failure
+    match gateway::call(gateway, timeout, move |gateway| gateway.create_transaction(request)).await {
+        Err(gateway::Error::Timeout) => Err(gateway::Error::Pending),
+        result => result,
+    }
 }
 
+//----------------------------------------------------------------------------------------------------
+//----------------------------------------------------------------------------------------------------
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    //--------------------------------------------------------------------------------------------------
+    //--------------------------------------------------------------------------------------------------
+    fn sale(payment_type : PaymentType, cents : i64, promo_code : Option<&str>) -> Sale<'_> {
+        Sale { payment_type, description: "test", amount: Money::from_cents(cents), tax: None, promo_code }
+    }
+
+    //--------------------------------------------------------------------------------------------------
+    //--------------------------------------------------------------------------------------------------
+    #[actix_rt::test]
+    async fn only_a_class_a_promo_code_made_free_skips_the_gateway() {
+        let gateway : web::Data<Gateway> = web::Data::new(Box::new(gateway::FakeGateway::new()));
+        let timeout = Duration::from_secs(5);
+        //no nonce, so anything that reaches the gateway fails
+        let payment = Payment::default();
+
+        let free = charge(&payment, &sale(PaymentType::CourseSignup, 0, Some("FREE")), &gateway, timeout).await.unwrap();
+        assert!(free.id.starts_with("free-"));
+
+        for sale in [
+            sale(PaymentType::CourseSignup, 0, None),
+            sale(PaymentType::CourseSignup, -500, Some("FREE")),
+            sale(PaymentType::Donation, 0, None),
+            sale(PaymentType::Invoice, 0, None),
+            sale(PaymentType::Invoice, -500, None)] {
+            assert!(matches!(charge(&payment, &sale, &gateway, timeout).await, Err(gateway::Error::Validation(_))));
+        }
+
+        assert!(matches!(charge(&payment, &sale(PaymentType::CourseSignup, 500, Some("HALF")), &gateway, timeout).await,
+            Err(gateway::Error::MissingPaymentMethod)));
+    }
+}